@@ -4,66 +4,178 @@ use lazy_static::*;
 use redis::cluster::{ClusterClient, ClusterConnection};
 use redis::Commands;
 use redis::{Client, Connection};
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod counter;
+mod error;
+mod keys;
+mod leaderboard;
+mod local;
+mod lock;
+mod pool;
+mod pubsub;
+mod rate_limit;
+mod registry;
+mod stream;
+mod typed;
+pub use counter::*;
+pub use error::*;
+pub use keys::*;
+pub use leaderboard::*;
+pub use local::*;
+pub use lock::*;
+pub use pool::*;
+pub use pubsub::*;
+pub use rate_limit::*;
+pub use registry::*;
+pub use stream::*;
+pub use typed::*;
 
 lazy_static! {
-    static ref CLUSTERCACHES: Mutex<Vec<ClusterClient>> = Mutex::new(vec![]);
-    static ref CACHES: Mutex<Vec<Client>> = Mutex::new(vec![]);
+    // 同步连接  key: 缓存名称
+    static ref CLUSTERCACHES: Mutex<HashMap<String, Arc<ClusterClient>>> = Mutex::new(HashMap::new());
+    // 集群种子节点 按模式删除时用于发现全部主节点
+    static ref CLUSTERNODES: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+    static ref CACHES: Mutex<HashMap<String, Client>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn register_sync(name: &str, client: Client) {
+    let mut pools = CACHES.lock().unwrap_or_else(|e| e.into_inner());
+    (*pools).insert(name.to_owned(), client);
+}
+
+pub(crate) fn remove_sync(name: &str) {
+    CACHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(name);
+    CLUSTERCACHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(name);
+    CLUSTERNODES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(name);
+}
+
+pub(crate) fn register_cluster_sync(name: &str, nodes: Vec<String>) -> CacheResult<()> {
+    let client = ClusterClient::open(nodes.clone())?;
+    let mut pools = CLUSTERCACHES.lock().unwrap_or_else(|e| e.into_inner());
+    (*pools).insert(name.to_owned(), Arc::new(client));
+    CLUSTERNODES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_owned(), nodes);
+    Ok(())
+}
+
+pub(crate) fn cluster_nodes(name: &str) -> Option<Vec<String>> {
+    CLUSTERNODES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
 }
 
 /// 初始化缓存数据库连接 当前只支持 redis
 ///
+/// 同 [`init_cluster_pool`] 使用默认连接池配置, 同步与异步连接均可使用
+///
 /// # ! `集群使用`
 /// [`conn_struct`]:Document
 /// # Example
-/// ``` rust
-/// let nodes = vec!["redis://127.0.0.1/"];
-/// init_cluster_connections(nodes);
+/// ```rust,no_run
+/// let nodes = vec!["redis://127.0.0.1/".to_owned()];
+/// yn_util::caches::init_cluster_connections(nodes)?;
+/// # Ok::<(), yn_util::caches::CacheError>(())
 /// ```
-pub fn init_cluster_connections(nodes: Vec<String>) {
-    let client = ClusterClient::open(nodes).unwrap();
-    let mut pools = CLUSTERCACHES.lock().unwrap();
-    (*pools).push(client);
+pub fn init_cluster_connections(nodes: Vec<String>) -> CacheResult<()> {
+    register(DEFAULT, CacheBackend::Cluster(nodes), PoolConfig::new())
 }
 
 /// 獲取資料庫連接
 ///
 /// # ! `集群使用`
-/// ```rust
-/// let mut redis = cache::get_cluster_conn();
+/// ```rust,no_run
+/// use redis::Commands;
+///
+/// let mut redis = yn_util::caches::get_cluster_conn()?;
 /// let _val = redis.get::<&str, String>("hello").unwrap_or("world".to_owned());
+/// # Ok::<(), yn_util::caches::CacheError>(())
 /// ```
-pub fn get_cluster_conn() -> ClusterConnection {
-    let pools = CLUSTERCACHES.lock().unwrap();
+pub fn get_cluster_conn() -> CacheResult<ClusterConnection> {
+    get_cluster_conn_by(DEFAULT)
+}
 
-    unsafe { (*pools).get_unchecked(0).get_connection().unwrap() }
+/// 獲取命名緩存的資料庫連接
+///
+/// # ! `集群使用`
+pub fn get_cluster_conn_by(name: &str) -> CacheResult<ClusterConnection> {
+    let client = {
+        let pools = CLUSTERCACHES.lock().unwrap_or_else(|e| e.into_inner());
+        match (*pools).get(name) {
+            Some(client) => client.clone(),
+            None if name == DEFAULT => return Err(CacheError::NotInitialized),
+            None => return Err(CacheError::Unknown(name.to_owned())),
+        }
+    };
+    Ok(client.get_connection()?)
 }
 
 /// redis 测试
-pub fn set() {
-    let _: () = get_conn().set("test", "value").unwrap();
-    let rv: String = get_conn().get("test").unwrap();
+pub fn set() -> CacheResult<()> {
+    let _: () = get_conn()?.set("test", "value")?;
+    let rv: String = get_conn()?.get("test")?;
     info!("rv = {:?}", rv);
+    Ok(())
 }
 
 /// 初始化資料庫連接
 ///
+/// 同 [`init_pool`] 使用默认连接池配置, 同步与异步连接均可使用
+///
 /// # ! `单体使用`
 /// 連接字符串形似: 'redis://127.0.0.1'
-pub fn init_connections(conn_string: &str) {
-    let cache = redis::Client::open(conn_string).unwrap();
-    let mut pools = CACHES.lock().unwrap();
-    (*pools).push(cache);
+pub fn init_connections(conn_string: &str) -> CacheResult<()> {
+    register(
+        DEFAULT,
+        CacheBackend::Single(conn_string.to_owned()),
+        PoolConfig::new(),
+    )
 }
 
 /// 獲取資料庫連接
 ///
+/// 同步阻塞连接, actix 异步处理函数中请使用 [`get_pool`]
+///
 /// # ! `单体使用`
-/// ```rust
-/// let mut redis = cache::get_conn();
+/// ```rust,no_run
+/// use redis::Commands;
+///
+/// let mut redis = yn_util::caches::get_conn()?;
 /// let _val = redis.get::<&str, String>("hello").unwrap_or("world".to_owned());
+/// # Ok::<(), yn_util::caches::CacheError>(())
 /// ```
-pub fn get_conn() -> Connection {
-    let pools = CACHES.lock().unwrap();
-    unsafe { (*pools).get_unchecked(0).get_connection().unwrap() }
+pub fn get_conn() -> CacheResult<Connection> {
+    get_conn_by(DEFAULT)
+}
+
+/// 獲取命名緩存的資料庫連接 (单节点或哨兵)
+///
+/// # ! `单体使用`
+pub fn get_conn_by(name: &str) -> CacheResult<Connection> {
+    let client = {
+        let pools = CACHES.lock().unwrap_or_else(|e| e.into_inner());
+        (*pools).get(name).cloned()
+    };
+    let client = match (client, sentinel(name)) {
+        (Some(client), _) => client,
+        (None, Some(sentinel)) => sentinel.resolve_sync(Duration::from_secs(3))?,
+        (None, None) if name == DEFAULT => return Err(CacheError::NotInitialized),
+        (None, None) => return Err(CacheError::Unknown(name.to_owned())),
+    };
+    Ok(client.get_connection()?)
 }
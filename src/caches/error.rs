@@ -0,0 +1,113 @@
+use super::*;
+use crate::utils::BusinessError;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use thiserror::Error;
+
+lazy_static! {
+    // 降级模式 缓存故障时回源数据库
+    static ref FALLBACK: AtomicBool = AtomicBool::new(true);
+}
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("缓存未初始化")]
+    NotInitialized,
+    #[error("缓存 {0} 未配置")]
+    Unknown(String),
+    #[error("缓存操作超时")]
+    Timeout,
+    #[error("获取锁 {0} 超时")]
+    LockTimeout(String),
+    #[error("缓存编解码错误: {0}")]
+    Codec(String),
+    #[error("缓存 {0} 的本地层已按不同配置创建")]
+    ConfigConflict(String),
+    #[error("缓存错误: {0}")]
+    Redis(#[from] redis::RedisError),
+}
+
+impl CacheError {
+    /// 连接已不可用 需要重连
+    pub fn is_broken(&self) -> bool {
+        match self {
+            CacheError::NotInitialized
+            | CacheError::Unknown(_)
+            | CacheError::LockTimeout(_)
+            | CacheError::Codec(_)
+            | CacheError::ConfigConflict(_) => false,
+            CacheError::Timeout => true,
+            CacheError::Redis(e) => {
+                e.is_io_error()
+                    || e.is_connection_dropped()
+                    || e.is_timeout()
+                    || e.is_connection_refusal()
+            }
+        }
+    }
+}
+
+impl From<CacheError> for BusinessError {
+    fn from(e: CacheError) -> Self {
+        log::error!("cache error, {}", e);
+        BusinessError::InternalError { source: anyhow!(e) }
+    }
+}
+
+pub type CacheResult<T> = Result<T, CacheError>;
+
+/// 设置降级模式 (默认开启)
+///
+/// 开启时 [`degrade`] / [`with_fallback`] 遇到缓存故障只记录日志, 由调用方回源数据库;
+/// 关闭时缓存故障直接作为请求错误返回
+pub fn set_fallback(enabled: bool) {
+    FALLBACK.store(enabled, Ordering::Relaxed);
+}
+
+/// 是否开启降级模式
+pub fn fallback_enabled() -> bool {
+    FALLBACK.load(Ordering::Relaxed)
+}
+
+/// 按降级模式处理缓存结果: 故障时返回 `Ok(None)` 或错误
+pub fn degrade<T>(result: CacheResult<T>) -> Result<Option<T>, BusinessError> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if fallback_enabled() => {
+            log::warn!("cache degraded, {}", e);
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 优先读缓存, 未命中或缓存故障 (降级模式) 时执行 load 回源
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::caches;
+/// use yn_util::dao::Dao;
+///
+/// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+/// let cached = async {
+///     let pool = caches::get_pool()?;
+///     pool.query::<Option<String>>(redis::cmd("GET").arg("config:site")).await
+/// };
+/// let load = async {
+///     let data = Dao::new("YNOS", "config").find_one(bson::doc! {"key": "site"}).await?;
+///     Ok(data.map(|d| d.to_string()))
+/// };
+/// let site = caches::with_fallback(cached, load).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn with_fallback<T, C, L>(cached: C, load: L) -> Result<Option<T>, BusinessError>
+where
+    C: Future<Output = CacheResult<Option<T>>>,
+    L: Future<Output = Result<Option<T>, BusinessError>>,
+{
+    match degrade(cached.await)? {
+        Some(Some(v)) => Ok(Some(v)),
+        _ => load.await,
+    }
+}
@@ -0,0 +1,529 @@
+use super::*;
+use actix_web::{error::BlockingError, web};
+use redis::{ConnectionAddr, ConnectionInfo, ConnectionLike, IntoConnectionInfo, RedisResult};
+
+/// 每次 SCAN 的数量
+const SCAN_COUNT: usize = 500;
+/// 每次 SPOP 的数量
+const SPOP_BATCH: usize = 500;
+/// 按模式删除时 节点连接与读写的超时
+const NODE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 加入标签集合, 集合的过期时间取成员中最长的
+const TAG_SCRIPT: &str = r#"
+local added = redis.call("SADD", KEYS[1], ARGV[1])
+local ttl = tonumber(ARGV[2])
+local current = redis.call("PTTL", KEYS[1])
+if ttl <= 0 then
+    redis.call("PERSIST", KEYS[1])
+elseif current >= 0 and current < ttl then
+    redis.call("PEXPIRE", KEYS[1], ttl)
+elseif current == -1 and redis.call("SCARD", KEYS[1]) == 1 then
+    redis.call("PEXPIRE", KEYS[1], ttl)
+end
+return added
+"#;
+
+/// key 构造 `app:namespace:part1:part2`
+///
+/// # Examples
+/// ```rust
+/// use yn_util::caches::KeyBuilder;
+///
+/// let keys = KeyBuilder::new("ynos").namespace("user");
+/// assert_eq!(keys.key(&["10086", "profile"]), "ynos:user:10086:profile");
+/// assert_eq!(keys.pattern("10086:*"), "ynos:user:10086:*");
+///
+/// // 集群中同一命名空间的 key 落在同一 slot, 可以使用多 key 命令
+/// let keys = KeyBuilder::new("ynos").namespace("cart").slot_tag();
+/// assert_eq!(keys.key(&["10086"]), "ynos:{cart}:10086");
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyBuilder {
+    parts: Vec<String>,
+}
+
+impl KeyBuilder {
+    /// 应用前缀
+    pub fn new(app: &str) -> Self {
+        KeyBuilder {
+            parts: vec![app.to_owned()],
+        }
+    }
+
+    /// 追加命名空间
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.parts.push(namespace.to_owned());
+        self
+    }
+
+    /// 将最后一级命名空间作为集群 hash tag
+    pub fn slot_tag(mut self) -> Self {
+        if let Some(last) = self.parts.last_mut() {
+            *last = format!("{{{}}}", last.trim_matches(['{', '}']));
+        }
+        self
+    }
+
+    /// 前缀 不含结尾的 `:`, 可用于 [`Cache::prefix`]
+    pub fn prefix(&self) -> String {
+        self.parts.join(":")
+    }
+
+    /// 完整 key
+    pub fn key(&self, parts: &[&str]) -> String {
+        let mut key = self.prefix();
+        for part in parts {
+            key.push(':');
+            key.push_str(part);
+        }
+        key
+    }
+
+    /// 匹配模式 用于 [`delete_pattern`]
+    pub fn pattern(&self, pattern: &str) -> String {
+        format!("{}:{}", self.prefix(), pattern)
+    }
+}
+
+/// 带版本号的命名空间
+///
+/// key 中包含命名空间当前版本 `app:namespace:v{n}:...`, [`Namespace::flush`] 将版本号加一,
+/// 旧版本的 key 不再被访问, 由过期时间自然清理 (写入时须设置 ttl)
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::Namespace;
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let products = Namespace::new("ynos", "product")?;
+/// let cache = products.cache().await?;
+/// cache.set("10086", &"手机", Some(Duration::from_secs(600))).await?;
+///
+/// // 批量导入商品后 整个命名空间失效
+/// products.flush().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Namespace {
+    cache: Cache,
+    namespace: String,
+}
+
+impl Namespace {
+    /// 使用默认缓存
+    pub fn new(app: &str, namespace: &str) -> CacheResult<Self> {
+        Namespace::named(DEFAULT, app, namespace)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str, app: &str, namespace: &str) -> CacheResult<Self> {
+        Ok(Namespace {
+            cache: Cache::named(name)?.prefix(app),
+            namespace: namespace.to_owned(),
+        })
+    }
+
+    fn version_key(&self) -> String {
+        format!("{}:version", self.namespace)
+    }
+
+    /// 当前版本
+    pub async fn version(&self) -> CacheResult<u64> {
+        let version: Option<u64> = self.cache.get(&self.version_key()).await?;
+        Ok(version.unwrap_or(0))
+    }
+
+    /// 当前版本的缓存
+    pub async fn cache(&self) -> CacheResult<Cache> {
+        let version = self.version().await?;
+        Ok(self
+            .cache
+            .clone()
+            .namespace(&format!("{}:v{}", self.namespace, version)))
+    }
+
+    /// 当前版本的完整 key
+    pub async fn key(&self, key: &str) -> CacheResult<String> {
+        Ok(self.cache().await?.key(key))
+    }
+
+    /// 使整个命名空间失效 返回新版本
+    pub async fn flush(&self) -> CacheResult<u64> {
+        self.cache
+            .backend()
+            .query(redis::cmd("INCR").arg(self.cache.key(&self.version_key())))
+            .await
+    }
+}
+
+/// 标签 按标签批量失效
+///
+/// 标签集合 `{prefix}:{tag}` 记录打了该标签的完整 key
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::{Cache, Tags};
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let cache = Cache::named("default")?.prefix("ynos");
+/// let tags = Tags::named("default")?.prefix("ynos:tag");
+/// let ttl = Duration::from_secs(600);
+///
+/// cache.set("order:1", &"订单1", Some(ttl)).await?;
+/// tags.tag(&cache.key("order:1"), &["user:10086", "shop:7"], Some(ttl)).await?;
+///
+/// // 店铺信息修改后 相关缓存全部失效
+/// tags.invalidate("shop:7").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Tags {
+    backend: AsyncCache,
+    prefix: String,
+}
+
+impl Tags {
+    /// 使用默认缓存
+    pub fn new() -> CacheResult<Self> {
+        Tags::named(DEFAULT)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str) -> CacheResult<Self> {
+        Ok(Tags {
+            backend: get(name)?,
+            prefix: "tag".to_owned(),
+        })
+    }
+
+    /// 使用缓存的连接 标签集合位于缓存前缀下 (`{前缀}tag:{标签}`)
+    pub fn for_cache<C: Codec>(cache: &Cache<C>) -> Self {
+        Tags {
+            backend: cache.backend().clone(),
+            prefix: cache.key("tag"),
+        }
+    }
+
+    /// 设置标签集合前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    fn tag_key(&self, tag: &str) -> String {
+        format!("{}:{}", self.prefix, tag)
+    }
+
+    /// 为 key 打上标签 ttl 一般与 key 的过期时间相同
+    pub async fn tag(&self, key: &str, tags: &[&str], ttl: Option<Duration>) -> CacheResult<()> {
+        let ttl = ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1));
+        let args = [key.to_owned(), ttl.to_string()];
+        for tag in tags {
+            self.backend
+                .eval::<i64>(TAG_SCRIPT, &[self.tag_key(tag)], &args)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 标签下的全部 key
+    pub async fn members(&self, tag: &str) -> CacheResult<Vec<String>> {
+        self.backend
+            .query(redis::cmd("SMEMBERS").arg(self.tag_key(tag)))
+            .await
+    }
+
+    /// 删除标签下的全部 key 返回删除的数量
+    pub async fn invalidate(&self, tag: &str) -> CacheResult<i64> {
+        let tag_key = self.tag_key(tag);
+        let mut total = 0;
+        loop {
+            // 逐批弹出 期间新打上标签的 key 也会被处理
+            let keys: Vec<String> = self
+                .backend
+                .query(redis::cmd("SPOP").arg(&tag_key).arg(SPOP_BATCH))
+                .await?;
+            if keys.is_empty() {
+                return Ok(total);
+            }
+            total += delete_keys(&self.backend, &keys).await?;
+        }
+    }
+
+    /// 删除多个标签下的 key
+    pub async fn invalidate_many(&self, tags: &[&str]) -> CacheResult<i64> {
+        let mut total = 0;
+        for tag in tags {
+            total += self.invalidate(tag).await?;
+        }
+        Ok(total)
+    }
+}
+
+/// 删除完整 key 集群中逐个删除
+async fn delete_keys(backend: &AsyncCache, keys: &[String]) -> CacheResult<i64> {
+    match backend {
+        AsyncCache::Single(_) => backend.query(redis::cmd("DEL").arg(keys)).await,
+        AsyncCache::Cluster(_) => {
+            let mut total = 0;
+            for key in keys {
+                total += backend.query::<i64>(redis::cmd("DEL").arg(key)).await?;
+            }
+            Ok(total)
+        }
+    }
+}
+
+/// 按模式删除 key (使用 SCAN, 不会阻塞 redis)
+///
+/// 集群时在每个主节点上分别扫描; 删除期间新写入的匹配 key 可能不会被删除
+///
+/// # ! `阻塞` actix 异步处理函数中请使用 [`delete_pattern_async`]
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::caches::{self, KeyBuilder};
+///
+/// let keys = KeyBuilder::new("ynos").namespace("user");
+/// let deleted = caches::delete_pattern(&keys.pattern("10086:*"))?;
+/// # Ok::<(), yn_util::caches::CacheError>(())
+/// ```
+pub fn delete_pattern(pattern: &str) -> CacheResult<u64> {
+    delete_pattern_by(DEFAULT, pattern)
+}
+
+/// 按模式删除命名缓存中的 key
+pub fn delete_pattern_by(name: &str, pattern: &str) -> CacheResult<u64> {
+    match cluster_nodes(name) {
+        Some(seeds) => {
+            let mut total = 0;
+            for node in cluster_masters(&seeds)? {
+                total += scan_delete(&mut node_conn(node)?, pattern)?;
+            }
+            Ok(total)
+        }
+        None => {
+            let mut conn = get_conn_by(name)?;
+            conn.set_read_timeout(Some(NODE_TIMEOUT))?;
+            conn.set_write_timeout(Some(NODE_TIMEOUT))?;
+            Ok(scan_delete(&mut conn, pattern)?)
+        }
+    }
+}
+
+/// 连接集群节点 建立连接与读写均有超时, 避免节点无响应时一直阻塞
+fn node_conn(info: ConnectionInfo) -> RedisResult<redis::Connection> {
+    let conn = Client::open(info)?.get_connection_with_timeout(NODE_TIMEOUT)?;
+    conn.set_read_timeout(Some(NODE_TIMEOUT))?;
+    conn.set_write_timeout(Some(NODE_TIMEOUT))?;
+    Ok(conn)
+}
+
+/// 按模式删除 在线程池中执行
+pub async fn delete_pattern_async(name: &str, pattern: &str) -> CacheResult<u64> {
+    let (name, pattern) = (name.to_owned(), pattern.to_owned());
+    web::block(move || delete_pattern_by(&name, &pattern))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                redis::RedisError::from((redis::ErrorKind::IoError, "redis 线程池已关闭")).into()
+            }
+        })
+}
+
+fn scan_delete<C: ConnectionLike>(conn: &mut C, pattern: &str) -> RedisResult<u64> {
+    let mut cursor = 0u64;
+    let mut total = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query(conn)?;
+        if !keys.is_empty() {
+            // 集群节点上的 key 可能属于不同 slot, 逐个删除
+            let mut pipe = redis::pipe();
+            for key in keys.iter() {
+                pipe.cmd("DEL").arg(key);
+            }
+            let deleted: Vec<u64> = pipe.query(conn)?;
+            total += deleted.iter().sum::<u64>();
+        }
+        if next == 0 {
+            return Ok(total);
+        }
+        cursor = next;
+    }
+}
+
+/// 向种子节点查询集群的全部主节点 连接参数 (密码等) 沿用种子节点
+fn cluster_masters(seeds: &[String]) -> CacheResult<Vec<ConnectionInfo>> {
+    let mut last = CacheError::NotInitialized;
+    for seed in seeds {
+        let nodes = seed.as_str().into_connection_info().and_then(|info| {
+            let nodes: String = redis::cmd("CLUSTER")
+                .arg("NODES")
+                .query(&mut node_conn(info.clone())?)?;
+            Ok(parse_masters(&nodes, &info))
+        });
+        match nodes {
+            Ok((masters, failed)) => {
+                // 已下线的主节点由提升的从节点接替, 从节点会作为新的主节点出现在列表中
+                if !failed.is_empty() {
+                    log::warn!("集群主节点 {} 已下线, 跳过", failed.join(", "));
+                }
+                return Ok(masters);
+            }
+            Err(e) => last = e.into(),
+        }
+        log::warn!("{} 查询集群节点失败, {}", seed, last);
+    }
+    Err(last)
+}
+
+/// 解析 `CLUSTER NODES` 中的主节点, 返回 (可用的主节点, 已下线的主节点地址)
+///
+/// 只跳过带 `fail` 标记的节点; `fail?` 只是部分节点认为其不可达, 仍然尝试连接
+fn parse_masters(nodes: &str, seed: &ConnectionInfo) -> (Vec<ConnectionInfo>, Vec<String>) {
+    let seed_host = match &*seed.addr {
+        ConnectionAddr::Tcp(host, _) | ConnectionAddr::TcpTls { host, .. } => host.clone(),
+        ConnectionAddr::Unix(_) => "127.0.0.1".to_owned(),
+    };
+    let mut failed = vec![];
+    let masters = nodes
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags: Vec<&str> = fields.get(2)?.split(',').collect();
+            if !flags.contains(&"master") {
+                return None;
+            }
+            // ip:port@cport[,hostname]
+            let addr = fields.get(1)?.split(['@', ',']).next()?;
+            if flags.contains(&"fail") {
+                failed.push(addr.to_owned());
+                return None;
+            }
+            let (host, port) = addr.rsplit_once(':')?;
+            let host = match host {
+                "" => seed_host.clone(),
+                host => host.to_owned(),
+            };
+            let port = port.parse().ok()?;
+            let addr = match &*seed.addr {
+                ConnectionAddr::TcpTls { insecure, .. } => ConnectionAddr::TcpTls {
+                    host,
+                    port,
+                    insecure: *insecure,
+                },
+                _ => ConnectionAddr::Tcp(host, port),
+            };
+            Some(ConnectionInfo {
+                addr: Box::new(addr),
+                ..seed.clone()
+            })
+        })
+        .collect();
+    (masters, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const NODES: &str = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@31003 master,fail? - 0 1426238318243 3 connected 10923-16383
+6ec23923021cf3ffec47632106199cb7f496ce01 127.0.0.1:30005@31005 master,fail - 1426238316232 1426238316232 5 connected
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca :30001@31001 myself,master - 0 0 1 connected 0-5460
+824fe116063bc5fcf9f4ffd895bc17aee7731ac3 10.0.0.6:30006@31006,redis-6.example master - 0 1426238316232 6 connected
+";
+
+    fn hosts(masters: &[ConnectionInfo]) -> Vec<String> {
+        masters
+            .iter()
+            .map(|info| match &*info.addr {
+                ConnectionAddr::Tcp(host, port) => format!("{}:{}", host, port),
+                ConnectionAddr::TcpTls { host, port, .. } => format!("tls://{}:{}", host, port),
+                ConnectionAddr::Unix(path) => path.display().to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn masters_skip_failed_only() {
+        let seed = "redis://:secret@10.0.0.1:30001/"
+            .into_connection_info()
+            .unwrap();
+        let (masters, failed) = parse_masters(NODES, &seed);
+        assert_eq!(
+            hosts(&masters),
+            vec![
+                "127.0.0.1:30002",
+                "127.0.0.1:30003",
+                "10.0.0.1:30001",
+                "10.0.0.6:30006"
+            ]
+        );
+        assert_eq!(failed, vec!["127.0.0.1:30005"]);
+        // 连接参数沿用种子节点
+        assert!(masters
+            .iter()
+            .all(|m| m.passwd.as_deref() == Some("secret")));
+    }
+
+    #[test]
+    fn masters_keep_tls() {
+        let seed = ConnectionInfo {
+            addr: Box::new(ConnectionAddr::TcpTls {
+                host: "10.0.0.1".to_owned(),
+                port: 30001,
+                insecure: true,
+            }),
+            db: 0,
+            username: None,
+            passwd: None,
+        };
+        let (masters, _) = parse_masters(NODES, &seed);
+        assert_eq!(hosts(&masters)[0], "tls://127.0.0.1:30002");
+        assert!(matches!(
+            &*masters[0].addr,
+            ConnectionAddr::TcpTls { insecure: true, .. }
+        ));
+        assert_eq!(parse_masters("", &seed).0.len(), 0);
+    }
+
+    #[test]
+    fn key_builder() {
+        let keys = KeyBuilder::new("ynos").namespace("user");
+        assert_eq!(keys.prefix(), "ynos:user");
+        assert_eq!(keys.key(&[]), "ynos:user");
+        assert_eq!(keys.key(&["1", "profile"]), "ynos:user:1:profile");
+        assert_eq!(keys.pattern("*"), "ynos:user:*");
+        // 重复调用 slot_tag 不会嵌套花括号
+        let keys = keys.slot_tag().slot_tag();
+        assert_eq!(keys.key(&["1"]), "ynos:{user}:1");
+    }
+
+    #[test]
+    fn tags_under_cache_prefix() {
+        let client = Client::open("redis://127.0.0.1/").unwrap();
+        let backend = AsyncCache::Single(Arc::new(SinglePool::new(client, PoolConfig::new())));
+        let cache = Cache::new(backend)
+            .prefix("ynos")
+            .namespace("dao:YNOS.member");
+        let tags = Tags::for_cache(&cache);
+        assert_eq!(tags.tag_key("query"), "ynos:dao:YNOS.member:tag:query");
+        assert_eq!(tags.prefix("t").tag_key("query"), "t:query");
+    }
+}
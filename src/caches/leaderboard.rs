@@ -0,0 +1,252 @@
+use super::*;
+
+/// 排行榜条目
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RankEntry {
+    pub member: String,
+    pub score: f64,
+    /// 名次 从 1 开始
+    pub rank: u64,
+}
+
+/// 排行榜 (有序集合)
+///
+/// 默认分数高的在前, `ascending` 后分数低的在前 (例如用时最短)
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::Leaderboard;
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let sales = Leaderboard::new("product:sales:2021-01")?.ttl(Duration::from_secs(90 * 86400));
+/// sales.incr("10086", 2.0).await?;
+///
+/// let top10 = sales.top(10).await?;
+/// let rank = sales.rank("10086").await?;
+/// let page2 = sales.page(2, 20).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Leaderboard {
+    backend: AsyncCache,
+    key: String,
+    ascending: bool,
+    ttl: Option<Duration>,
+}
+
+impl Leaderboard {
+    /// 使用默认缓存
+    pub fn new(board: &str) -> CacheResult<Self> {
+        Leaderboard::named(DEFAULT, board)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str, board: &str) -> CacheResult<Self> {
+        Ok(Leaderboard {
+            backend: get(name)?,
+            key: format!("board:{}", board),
+            ascending: false,
+            ttl: None,
+        })
+    }
+
+    /// 分数低的在前
+    pub fn ascending(mut self) -> Self {
+        self.ascending = true;
+        self
+    }
+
+    /// 排行榜过期时间 每次写入后重新计时
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// 写入后设置过期 (同一 key, 集群中可以使用管道)
+    async fn write<T>(&self, cmd: redis::Cmd) -> CacheResult<T>
+    where
+        T: redis::FromRedisValue + Send + 'static,
+    {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return self.backend.query(&cmd).await,
+        };
+        let mut pipe = redis::pipe();
+        pipe.add_command(cmd)
+            .cmd("PEXPIRE")
+            .arg(&self.key)
+            .arg((ttl.as_millis() as u64).max(1))
+            .ignore();
+        let (value,): (T,) = self.backend.query_pipe(&pipe).await?;
+        Ok(value)
+    }
+
+    /// 增加分数 返回新分数
+    pub async fn incr(&self, member: &str, by: f64) -> CacheResult<f64> {
+        let mut cmd = redis::cmd("ZINCRBY");
+        cmd.arg(&self.key).arg(by).arg(member);
+        self.write(cmd).await
+    }
+
+    /// 设置分数
+    pub async fn set(&self, member: &str, score: f64) -> CacheResult<()> {
+        let mut cmd = redis::cmd("ZADD");
+        cmd.arg(&self.key).arg(score).arg(member);
+        self.write::<i64>(cmd).await?;
+        Ok(())
+    }
+
+    /// 移除成员 返回是否存在
+    pub async fn remove(&self, member: &str) -> CacheResult<bool> {
+        self.backend
+            .query(redis::cmd("ZREM").arg(&self.key).arg(member))
+            .await
+    }
+
+    /// 成员分数
+    pub async fn score(&self, member: &str) -> CacheResult<Option<f64>> {
+        self.backend
+            .query(redis::cmd("ZSCORE").arg(&self.key).arg(member))
+            .await
+    }
+
+    /// 成员名次 从 1 开始, 不在榜上时返回 `None`
+    pub async fn rank(&self, member: &str) -> CacheResult<Option<u64>> {
+        let cmd = if self.ascending { "ZRANK" } else { "ZREVRANK" };
+        let rank: Option<u64> = self
+            .backend
+            .query(redis::cmd(cmd).arg(&self.key).arg(member))
+            .await?;
+        Ok(rank.map(|r| r + 1))
+    }
+
+    /// 成员数量
+    pub async fn len(&self) -> CacheResult<u64> {
+        self.backend.query(redis::cmd("ZCARD").arg(&self.key)).await
+    }
+
+    /// 按名次区间读取 `start` / `stop` 为从 0 开始的位置, 包含两端
+    pub async fn range(&self, start: u64, stop: u64) -> CacheResult<Vec<RankEntry>> {
+        let cmd = if self.ascending {
+            "ZRANGE"
+        } else {
+            "ZREVRANGE"
+        };
+        let members: Vec<(String, f64)> = self
+            .backend
+            .query(
+                redis::cmd(cmd)
+                    .arg(&self.key)
+                    .arg(start)
+                    .arg(stop)
+                    .arg("WITHSCORES"),
+            )
+            .await?;
+        Ok(ranked(members, start))
+    }
+
+    /// 前 n 名
+    pub async fn top(&self, n: u64) -> CacheResult<Vec<RankEntry>> {
+        if n == 0 {
+            return Ok(vec![]);
+        }
+        self.range(0, (n - 1).min(MAX_INDEX)).await
+    }
+
+    /// 分页读取 page 从 1 开始
+    pub async fn page(&self, page: i64, page_size: i64) -> CacheResult<Vec<RankEntry>> {
+        let (start, stop) = page_range(page, page_size);
+        self.range(start, stop).await
+    }
+
+    /// 成员及其前后各 n 名
+    pub async fn around(&self, member: &str, n: u64) -> CacheResult<Vec<RankEntry>> {
+        match self.rank(member).await? {
+            Some(rank) => {
+                let (start, stop) = around_range(rank, n);
+                self.range(start, stop).await
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// 删除排行榜
+    pub async fn clear(&self) -> CacheResult<()> {
+        self.backend.query(redis::cmd("DEL").arg(&self.key)).await
+    }
+}
+
+/// `start` 起的成员加上名次
+fn ranked(members: Vec<(String, f64)>, start: u64) -> Vec<RankEntry> {
+    members
+        .into_iter()
+        .zip(start + 1..)
+        .map(|((member, score), rank)| RankEntry {
+            member,
+            score,
+            rank,
+        })
+        .collect()
+}
+
+/// redis 位置参数为有符号 64 位整数
+const MAX_INDEX: u64 = i64::MAX as u64;
+
+/// 第 page 页的位置区间 (从 0 开始, 包含两端) 超出范围时取 [`MAX_INDEX`]
+fn page_range(page: i64, page_size: i64) -> (u64, u64) {
+    let page_size = page_size.max(1) as u64;
+    let start = (page.max(1) as u64 - 1)
+        .saturating_mul(page_size)
+        .min(MAX_INDEX);
+    (start, start.saturating_add(page_size - 1).min(MAX_INDEX))
+}
+
+/// 名次 rank 前后各 n 名的位置区间
+fn around_range(rank: u64, n: u64) -> (u64, u64) {
+    let index = rank.saturating_sub(1);
+    (
+        index.saturating_sub(n),
+        index.saturating_add(n).min(MAX_INDEX),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        assert_eq!(page_range(1, 20), (0, 19));
+        assert_eq!(page_range(3, 10), (20, 29));
+        // 页码与页大小至少为 1
+        assert_eq!(page_range(0, 0), (0, 0));
+        assert_eq!(page_range(-2, 5), (0, 4));
+        // 请求中的大数不会溢出
+        assert_eq!(page_range(i64::MAX, i64::MAX), (MAX_INDEX, MAX_INDEX));
+        assert_eq!(page_range(2, i64::MAX), (MAX_INDEX, MAX_INDEX));
+        assert_eq!(page_range(i64::MAX, 10), (MAX_INDEX, MAX_INDEX));
+    }
+
+    #[test]
+    fn around() {
+        assert_eq!(around_range(1, 2), (0, 2));
+        assert_eq!(around_range(2, 2), (0, 3));
+        assert_eq!(around_range(10, 2), (7, 11));
+        assert_eq!(around_range(10, 0), (9, 9));
+        assert_eq!(around_range(10, u64::MAX), (0, MAX_INDEX));
+    }
+
+    #[test]
+    fn ranks_follow_start() {
+        let members = vec![("a".to_owned(), 9.0), ("b".to_owned(), 7.5)];
+        let entries = ranked(members, 20);
+        let ranks: Vec<(&str, f64, u64)> = entries
+            .iter()
+            .map(|e| (e.member.as_str(), e.score, e.rank))
+            .collect();
+        assert_eq!(ranks, vec![("a", 9.0, 21), ("b", 7.5, 22)]);
+        assert!(ranked(vec![], 0).is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+use super::*;
+use crate::utils::BusinessError;
+use futures::StreamExt;
+use lru::LruCache;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+lazy_static! {
+    // 进程内缓存层  key: 缓存名称, 同一名称只允许一种配置
+    static ref LOCALS: Mutex<HashMap<String, Arc<LocalTier>>> = Mutex::new(HashMap::new());
+    // 当前进程标识 忽略自己发出的失效广播
+    static ref ORIGIN: String = bson::oid::ObjectId::new().to_hex();
+}
+
+/// 进程内缓存层配置
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalConfig {
+    /// 最大条数 超出时淘汰最久未使用的
+    pub capacity: usize,
+    /// 本地缓存时长 (不超过写入时指定的 ttl)
+    pub ttl: Duration,
+    /// 失效广播频道
+    pub channel: String,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        LocalConfig {
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+            channel: "yn_util:cache:invalidate".to_owned(),
+        }
+    }
+}
+
+impl LocalConfig {
+    pub fn new() -> Self {
+        LocalConfig::default()
+    }
+
+    /// 设置最大条数
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// 设置本地缓存时长
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 设置失效广播频道
+    pub fn channel(mut self, channel: &str) -> Self {
+        self.channel = channel.to_owned();
+        self
+    }
+}
+
+/// 进程内 LRU 层 存储编码后的值, 与类型无关
+struct LocalTier {
+    entries: Mutex<LruCache<String, (Instant, Vec<u8>)>>,
+    config: LocalConfig,
+    /// 订阅断开期间可能错过失效广播, 此时不使用本地层
+    subscribed: AtomicBool,
+}
+
+impl LocalTier {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if !self.subscribed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let key = key.to_owned();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(&key) {
+            Some((expires, bytes)) if *expires > Instant::now() => Some(bytes.clone()),
+            Some(_) => {
+                entries.pop(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, key: String, bytes: Vec<u8>, ttl: Option<Duration>) {
+        let max = self.config.ttl;
+        let ttl = ttl.map_or(max, |ttl| ttl.min(max));
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .put(key, (Instant::now() + ttl, bytes));
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop(&key.to_owned());
+    }
+
+    fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+/// 订阅失效广播 断开后清空本地层并重新订阅
+async fn listen(name: String, local: Arc<LocalTier>) {
+    loop {
+        match pubsub_conn(&name, Duration::from_secs(3)).await {
+            Ok(mut pubsub) => match pubsub.subscribe(&local.config.channel).await {
+                Ok(()) => {
+                    local.subscribed.store(true, Ordering::Relaxed);
+                    let mut messages = pubsub.on_message();
+                    while let Some(msg) = messages.next().await {
+                        let payload: String = match msg.get_payload() {
+                            Ok(payload) => payload,
+                            Err(_) => continue,
+                        };
+                        // 消息格式: `来源进程 key`
+                        match payload.split_once(' ') {
+                            Some((origin, _)) if origin == ORIGIN.as_str() => {}
+                            Some((_, key)) => local.remove(key),
+                            None => {}
+                        }
+                    }
+                    log::warn!("缓存 {} 失效订阅断开, 重新订阅", name);
+                }
+                Err(e) => log::warn!("缓存 {} 失效订阅失败, {}", name, e),
+            },
+            Err(e) => log::warn!("缓存 {} 失效订阅连接失败, {}", name, e),
+        }
+        local.subscribed.store(false, Ordering::Relaxed);
+        local.clear();
+        tokio::time::delay_for(Duration::from_secs(1)).await;
+    }
+}
+
+/// 两级缓存: 进程内 LRU + redis
+///
+/// 读取优先命中本地层; 写入 / 删除时通过 redis 发布订阅广播失效,
+/// 各实例收到后丢弃本地副本. 同一缓存名称的本地层在进程内共享,
+/// 须在 actix 运行时内创建 (订阅任务随运行时启动)
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::{LocalConfig, TieredCache};
+/// use yn_util::dao::Dao;
+///
+/// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+/// let cache = TieredCache::named("default", LocalConfig::new().capacity(2000))?.prefix("ynos");
+/// let regions: Vec<bson::Document> = cache
+///     .get_or_set_with("position:region", Some(Duration::from_secs(3600)), || async {
+///         Dao::new("position", "region")
+///             .find(bson::doc! {}, None, None, None, None, true, None)
+///             .await
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TieredCache<C: Codec = Json> {
+    remote: Cache<C>,
+    local: Arc<LocalTier>,
+}
+
+impl<C: Codec> Clone for TieredCache<C> {
+    fn clone(&self) -> Self {
+        TieredCache {
+            remote: self.remote.clone(),
+            local: self.local.clone(),
+        }
+    }
+}
+
+impl TieredCache<Json> {
+    /// 使用已注册的命名缓存, 首次创建时按 config 建立本地层并订阅失效广播
+    ///
+    /// 同一名称的本地层在进程内共享, 再次创建时 config 须与首次一致,
+    /// 否则返回 [`CacheError::ConfigConflict`]
+    pub fn named(name: &str, config: LocalConfig) -> CacheResult<Self> {
+        let remote = Cache::named(name)?;
+        let local = local_tier(name, config)?;
+        Ok(TieredCache { remote, local })
+    }
+}
+
+/// 取得命名缓存的本地层 不存在时创建并启动订阅任务
+fn local_tier(name: &str, config: LocalConfig) -> CacheResult<Arc<LocalTier>> {
+    let config = LocalConfig {
+        capacity: config.capacity.max(1),
+        ..config
+    };
+    let mut locals = LOCALS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(local) = locals.get(name) {
+        if local.config != config {
+            return Err(CacheError::ConfigConflict(name.to_owned()));
+        }
+        return Ok(local.clone());
+    }
+    let local = Arc::new(LocalTier {
+        entries: Mutex::new(LruCache::new(config.capacity)),
+        config,
+        subscribed: AtomicBool::new(false),
+    });
+    actix_web::rt::spawn(listen(name.to_owned(), local.clone()));
+    locals.insert(name.to_owned(), local.clone());
+    Ok(local)
+}
+
+impl<C: Codec> TieredCache<C> {
+    /// 更换编码
+    pub fn codec<D: Codec>(self) -> TieredCache<D> {
+        TieredCache {
+            remote: self.remote.codec(),
+            local: self.local,
+        }
+    }
+
+    /// 设置 key 前缀 (一般为应用名), 替换已有前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.remote = self.remote.prefix(prefix);
+        self
+    }
+
+    /// 在现有前缀后追加命名空间
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.remote = self.remote.namespace(namespace);
+        self
+    }
+
+    /// redis 层
+    pub fn remote(&self) -> &Cache<C> {
+        &self.remote
+    }
+
+    /// 读取
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> CacheResult<Option<T>> {
+        let full = self.remote.key(key);
+        if let Some(bytes) = self.local.get(&full) {
+            return C::decode(&bytes).map(Some);
+        }
+        match self.remote.get_raw(key).await? {
+            Some(bytes) => {
+                let value = C::decode(&bytes)?;
+                self.local.put(full, bytes, None);
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 写入 并通知其他实例丢弃本地副本
+    ///
+    /// 通知失败只记录日志 (见 [`delete`](Self::delete))
+    pub async fn set<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> CacheResult<()> {
+        let bytes = C::encode(value)?;
+        self.remote.set_raw(key, bytes.clone(), ttl).await?;
+        let full = self.remote.key(key);
+        self.local.put(full.clone(), bytes, ttl);
+        self.after_write(&full).await;
+        Ok(())
+    }
+
+    /// 读取缓存, 未命中时执行 loader 并写入
+    ///
+    /// 缓存故障按降级模式处理 (见 [`set_fallback`])
+    pub async fn get_or_set_with<T, F, Fut>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        loader: F,
+    ) -> Result<T, BusinessError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, BusinessError>>,
+    {
+        if let Some(Some(v)) = degrade(self.get(key).await)? {
+            return Ok(v);
+        }
+        let value = loader().await?;
+        degrade(self.set(key, &value, ttl).await)?;
+        Ok(value)
+    }
+
+    /// 删除 并通知其他实例丢弃本地副本
+    ///
+    /// redis 已经更新后通知失败只记录日志, 返回错误会让调用方重复写入;
+    /// 其他实例的本地副本在本地 ttl 后过期
+    pub async fn delete(&self, key: &str) -> CacheResult<bool> {
+        let existed = self.remote.delete(key).await?;
+        let full = self.remote.key(key);
+        self.local.remove(&full);
+        self.after_write(&full).await;
+        Ok(existed)
+    }
+
+    /// 只丢弃各实例的本地副本 (redis 中的值已由其他途径更新时使用)
+    pub async fn invalidate(&self, key: &str) -> CacheResult<()> {
+        let full = self.remote.key(key);
+        self.local.remove(&full);
+        self.broadcast(&full).await
+    }
+
+    /// 清空当前进程的本地层
+    pub fn clear_local(&self) {
+        self.local.clear();
+    }
+
+    async fn after_write(&self, full_key: &str) {
+        if let Err(e) = self.broadcast(full_key).await {
+            log::warn!("{} 本地缓存失效通知失败, {}", full_key, e);
+        }
+    }
+
+    async fn broadcast(&self, full_key: &str) -> CacheResult<()> {
+        let payload = format!("{} {}", ORIGIN.as_str(), full_key);
+        self.remote
+            .backend()
+            .query(
+                redis::cmd("PUBLISH")
+                    .arg(&self.local.config.channel)
+                    .arg(payload),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_config_rejected() {
+        actix_web::rt::System::new("test").block_on(async {
+            let name = "local_tier_conflict";
+            let config = LocalConfig::new().capacity(10);
+            let first = local_tier(name, config.clone()).unwrap();
+            let again = local_tier(name, config.clone()).unwrap();
+            assert!(Arc::ptr_eq(&first, &again));
+            // capacity 0 与 1 视为相同
+            local_tier("local_tier_floor", LocalConfig::new().capacity(1)).unwrap();
+            let mut zero = LocalConfig::new();
+            zero.capacity = 0;
+            local_tier("local_tier_floor", zero).unwrap();
+
+            let other = config.ttl(Duration::from_secs(5));
+            assert!(matches!(
+                local_tier(name, other),
+                Err(CacheError::ConfigConflict(n)) if n == name
+            ));
+        });
+    }
+
+    #[test]
+    fn local_ttl_capped() {
+        let tier = LocalTier {
+            entries: Mutex::new(LruCache::new(2)),
+            config: LocalConfig::new().ttl(Duration::from_secs(60)),
+            subscribed: AtomicBool::new(true),
+        };
+        tier.put("a".to_owned(), vec![1], Some(Duration::from_secs(0)));
+        tier.put("b".to_owned(), vec![2], Some(Duration::from_secs(3600)));
+        assert_eq!(tier.get("a"), None);
+        assert_eq!(tier.get("b"), Some(vec![2]));
+        // 未订阅时不使用本地层
+        tier.subscribed.store(false, Ordering::Relaxed);
+        assert_eq!(tier.get("b"), None);
+    }
+}
@@ -0,0 +1,483 @@
+use super::*;
+use futures::future::join_all;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// 持有者一致时删除
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// 持有者一致时续期
+const EXTEND_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// 分布式锁配置
+#[derive(Clone, Debug)]
+pub struct LockOptions {
+    /// 租期 持有者崩溃后最长在此时间后自动释放
+    pub ttl: Duration,
+    /// 获取锁的最长等待时间 `None` 时只尝试一次
+    pub wait: Option<Duration>,
+    /// 重试间隔
+    pub retry_delay: Duration,
+    /// 持有期间自动续期 (每 ttl/3 续期一次)
+    pub auto_extend: bool,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        LockOptions {
+            ttl: Duration::from_secs(30),
+            wait: None,
+            retry_delay: Duration::from_millis(100),
+            auto_extend: true,
+        }
+    }
+}
+
+impl LockOptions {
+    pub fn new() -> Self {
+        LockOptions::default()
+    }
+
+    /// 设置租期
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl.max(Duration::from_millis(10));
+        self
+    }
+
+    /// 设置最长等待时间
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = Some(wait);
+        self
+    }
+
+    /// 设置重试间隔
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// 是否自动续期
+    pub fn auto_extend(mut self, auto_extend: bool) -> Self {
+        self.auto_extend = auto_extend;
+        self
+    }
+}
+
+/// 分布式锁
+///
+/// 单节点时使用 `SET NX PX`; 多节点 (Redlock) 时须在过半节点上加锁成功,
+/// 且耗时小于租期才算获取成功. 锁的持有者由随机令牌标识, 只能释放自己持有的锁
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::{DistributedLock, LockOptions};
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// // 定时任务 多实例只执行一次
+/// let lock = DistributedLock::new("job:daily_report")?;
+/// if let Some(guard) = lock.try_acquire().await? {
+///     // ... 执行任务
+///     guard.release().await?;
+/// }
+///
+/// // 扣减库存 最多等待 3 秒
+/// let opts = LockOptions::new().ttl(Duration::from_secs(5)).wait(Duration::from_secs(3));
+/// let lock = DistributedLock::redlock(&["lock1", "lock2", "lock3"], "stock:10086")?.options(opts);
+/// let guard = lock.acquire().await?;
+/// // ... 扣减库存
+/// guard.release().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DistributedLock {
+    nodes: Vec<AsyncCache>,
+    key: String,
+    opts: LockOptions,
+}
+
+impl DistributedLock {
+    /// 使用默认缓存
+    pub fn new(key: &str) -> CacheResult<Self> {
+        DistributedLock::named(DEFAULT, key)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str, key: &str) -> CacheResult<Self> {
+        DistributedLock::redlock(&[name], key)
+    }
+
+    /// Redlock 使用多个相互独立的命名缓存 (不应是同一集群的节点)
+    pub fn redlock(names: &[&str], key: &str) -> CacheResult<Self> {
+        if names.is_empty() {
+            return Err(CacheError::NotInitialized);
+        }
+        let nodes = names
+            .iter()
+            .map(|name| get(name))
+            .collect::<CacheResult<Vec<_>>>()?;
+        Ok(DistributedLock {
+            nodes,
+            key: format!("lock:{}", key),
+            opts: LockOptions::default(),
+        })
+    }
+
+    /// 设置锁配置
+    pub fn options(mut self, opts: LockOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// 尝试获取一次 已被占用时返回 `None`
+    pub async fn try_acquire(&self) -> CacheResult<Option<LockGuard>> {
+        let token = bson::oid::ObjectId::new().to_hex();
+        let start = Instant::now();
+        let ttl = self.opts.ttl.as_millis() as u64;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(&self.key).arg(&token).arg("NX").arg("PX").arg(ttl);
+        let results = join_all(
+            self.nodes
+                .iter()
+                .map(|node| node.query::<Option<String>>(&cmd)),
+        )
+        .await;
+        let mut last = None;
+        let mut locked = 0;
+        for result in results {
+            match result {
+                Ok(Some(_)) => locked += 1,
+                Ok(None) => {}
+                Err(e) => last = Some(e),
+            }
+        }
+        if locked >= quorum(self.nodes.len()) && within_validity(start.elapsed(), self.opts.ttl) {
+            let inner = Arc::new(LockInner {
+                nodes: self.nodes.clone(),
+                key: self.key.clone(),
+                token,
+                ttl: self.opts.ttl,
+                released: AtomicBool::new(false),
+                lost: AtomicBool::new(false),
+            });
+            if self.opts.auto_extend {
+                match actix_web::rt::System::is_set() {
+                    true => actix_web::rt::spawn(keep_alive(inner.clone())),
+                    false => log::warn!("锁 {} 不在 actix 运行时内, 无法自动续期", self.key),
+                }
+            }
+            return Ok(Some(LockGuard { inner }));
+        }
+        // 未达到多数 释放已加上的部分
+        if locked > 0 {
+            let _ = release_all(&self.nodes, &self.key, &token).await;
+        }
+        match last {
+            Some(e) if locked == 0 && self.nodes.len() == 1 => Err(e),
+            _ => Ok(None),
+        }
+    }
+
+    /// 获取锁 按 `wait` 配置重试, 超时返回 [`CacheError::LockTimeout`]
+    pub async fn acquire(&self) -> CacheResult<LockGuard> {
+        let deadline = Instant::now() + self.opts.wait.unwrap_or_default();
+        loop {
+            if let Some(guard) = self.try_acquire().await? {
+                return Ok(guard);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(CacheError::LockTimeout(self.key.clone()));
+            }
+            tokio::time::delay_for(self.opts.retry_delay.min(deadline - now)).await;
+        }
+    }
+}
+
+/// 过半节点数
+fn quorum(nodes: usize) -> usize {
+    nodes / 2 + 1
+}
+
+/// 扣除时钟漂移 (租期的 1% + 2 毫秒) 后, 加锁耗时是否仍小于租期
+fn within_validity(elapsed: Duration, ttl: Duration) -> bool {
+    let drift = ttl / 100 + Duration::from_millis(2);
+    elapsed + drift < ttl
+}
+
+struct LockInner {
+    nodes: Vec<AsyncCache>,
+    key: String,
+    token: String,
+    ttl: Duration,
+    released: AtomicBool,
+    lost: AtomicBool,
+}
+
+impl LockInner {
+    async fn extend(&self, ttl: Duration) -> CacheResult<bool> {
+        let keys = [self.key.clone()];
+        let args = [self.token.clone(), (ttl.as_millis() as u64).to_string()];
+        let results = join_all(
+            self.nodes
+                .iter()
+                .map(|node| node.eval::<i64>(EXTEND_SCRIPT, &keys, &args)),
+        )
+        .await;
+        let extended = results.iter().filter(|r| matches!(r, Ok(1))).count();
+        if extended >= quorum(self.nodes.len()) {
+            return Ok(true);
+        }
+        match results.into_iter().find_map(|r| r.err()) {
+            Some(e) if extended == 0 && self.nodes.len() == 1 => Err(e),
+            _ => {
+                self.lost.store(true, Ordering::Relaxed);
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// 持有期间定时续期, 续期失败 (锁已过期被他人获取) 时停止
+async fn keep_alive(inner: Arc<LockInner>) {
+    loop {
+        tokio::time::delay_for(inner.ttl / 3).await;
+        if inner.released.load(Ordering::Relaxed) {
+            return;
+        }
+        match inner.extend(inner.ttl).await {
+            Ok(true) => {}
+            Ok(false) => {
+                log::warn!("锁 {} 已失效, 停止续期", inner.key);
+                return;
+            }
+            Err(e) => log::warn!("锁 {} 续期失败, {}", inner.key, e),
+        }
+    }
+}
+
+async fn release_all(nodes: &[AsyncCache], key: &str, token: &str) -> CacheResult<bool> {
+    let keys = [key.to_owned()];
+    let args = [token.to_owned()];
+    let results = join_all(
+        nodes
+            .iter()
+            .map(|node| node.eval::<i64>(RELEASE_SCRIPT, &keys, &args)),
+    )
+    .await;
+    let mut released = false;
+    let mut last = None;
+    for result in results {
+        match result {
+            Ok(n) => released |= n > 0,
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) if !released => Err(e),
+        _ => Ok(released),
+    }
+}
+
+/// 锁的持有凭证
+///
+/// 应调用 [`LockGuard::release`] 释放; 直接丢弃时在后台释放, 不在 actix 运行时内则等待租期过期
+pub struct LockGuard {
+    inner: Arc<LockInner>,
+}
+
+impl LockGuard {
+    /// 锁的 key
+    pub fn key(&self) -> &str {
+        &self.inner.key
+    }
+
+    /// 持有者令牌
+    pub fn token(&self) -> &str {
+        &self.inner.token
+    }
+
+    /// 续期失败, 锁可能已被他人获取
+    pub fn is_lost(&self) -> bool {
+        self.inner.lost.load(Ordering::Relaxed)
+    }
+
+    /// 手动续期 返回是否仍持有锁
+    pub async fn extend(&self, ttl: Duration) -> CacheResult<bool> {
+        self.inner.extend(ttl).await
+    }
+
+    /// 释放 返回锁是否仍由自己持有 (false 表示已过期)
+    pub async fn release(self) -> CacheResult<bool> {
+        self.inner.released.store(true, Ordering::Relaxed);
+        release_all(&self.inner.nodes, &self.inner.key, &self.inner.token).await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if self.inner.released.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if !actix_web::rt::System::is_set() {
+            log::warn!("锁 {} 未释放, 等待过期", self.inner.key);
+            return;
+        }
+        let inner = self.inner.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = release_all(&inner.nodes, &inner.key, &inner.token).await {
+                log::warn!("锁 {} 释放失败, {}", inner.key, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn run<F: std::future::Future + 'static>(f: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(f)
+    }
+
+    /// 对每条命令回复固定内容的假节点
+    fn node(reply: &'static [u8]) -> AsyncCache {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                std::thread::spawn(move || {
+                    let mut buf = [0; 1024];
+                    while let Ok(n) = stream.read(&mut buf) {
+                        if n == 0 || stream.write_all(reply).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        let client = Client::open(format!("redis://{}/", addr)).unwrap();
+        let config = PoolConfig::new()
+            .pool_size(1)
+            .connect_timeout(Duration::from_millis(300))
+            .command_timeout(Duration::from_millis(300));
+        AsyncCache::Single(Arc::new(SinglePool::new(client, config)))
+    }
+
+    /// 拒绝连接的节点
+    fn down() -> AsyncCache {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::open(format!("redis://{}/", addr)).unwrap();
+        AsyncCache::Single(Arc::new(SinglePool::new(client, PoolConfig::new())))
+    }
+
+    fn lock(nodes: Vec<AsyncCache>) -> DistributedLock {
+        DistributedLock {
+            nodes,
+            key: "lock:test".to_owned(),
+            opts: LockOptions::new().auto_extend(false),
+        }
+    }
+
+    const OK: &[u8] = b"+OK\r\n";
+    const NIL: &[u8] = b"$-1\r\n";
+
+    #[test]
+    fn quorum_is_majority() {
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(2), 2);
+        assert_eq!(quorum(3), 2);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(5), 3);
+    }
+
+    #[test]
+    fn validity_accounts_for_drift() {
+        let ttl = Duration::from_secs(1);
+        assert!(within_validity(Duration::from_millis(0), ttl));
+        assert!(within_validity(Duration::from_millis(987), ttl));
+        // 漂移 10 + 2 毫秒
+        assert!(!within_validity(Duration::from_millis(988), ttl));
+        assert!(!within_validity(Duration::from_secs(2), ttl));
+    }
+
+    #[test]
+    fn ttl_floor() {
+        assert_eq!(
+            LockOptions::new().ttl(Duration::from_millis(1)).ttl,
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn acquired_on_majority() {
+        run(async move {
+            let guard = lock(vec![node(OK), node(OK), node(NIL)])
+                .try_acquire()
+                .await
+                .unwrap();
+            assert_eq!(guard.unwrap().key(), "lock:test");
+            // 一个节点故障不影响获取
+            let guard = lock(vec![node(OK), down(), node(OK)])
+                .try_acquire()
+                .await
+                .unwrap();
+            assert!(guard.is_some());
+        });
+    }
+
+    #[test]
+    fn not_acquired_without_majority() {
+        run(async move {
+            let guard = lock(vec![node(OK), node(NIL), node(NIL)])
+                .try_acquire()
+                .await
+                .unwrap();
+            assert!(guard.is_none());
+            // 多节点时故障不返回错误, 视为未获取
+            let guard = lock(vec![node(OK), down(), down()])
+                .try_acquire()
+                .await
+                .unwrap();
+            assert!(guard.is_none());
+        });
+    }
+
+    #[test]
+    fn single_node_error_surfaces() {
+        run(async move {
+            let result = lock(vec![down()]).try_acquire().await;
+            assert!(matches!(result, Err(e) if e.is_broken()));
+            let result = lock(vec![node(NIL)]).try_acquire().await.unwrap();
+            assert!(result.is_none());
+        });
+    }
+
+    #[test]
+    fn acquire_times_out() {
+        run(async move {
+            let opts = LockOptions::new()
+                .auto_extend(false)
+                .wait(Duration::from_millis(50))
+                .retry_delay(Duration::from_millis(10));
+            let result = lock(vec![node(NIL)]).options(opts).acquire().await;
+            assert!(matches!(result, Err(CacheError::LockTimeout(k)) if k == "lock:test"));
+        });
+    }
+}
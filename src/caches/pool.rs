@@ -0,0 +1,374 @@
+use super::*;
+use actix_web::{error::BlockingError, web};
+use redis::aio::MultiplexedConnection;
+use redis::{Cmd, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisResult};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// 连接池配置
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// 连接数
+    pub pool_size: usize,
+    /// 建立连接超时
+    pub connect_timeout: Duration,
+    /// 命令超时
+    pub command_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            pool_size: 4,
+            connect_timeout: Duration::from_secs(3),
+            command_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn new() -> Self {
+        PoolConfig::default()
+    }
+
+    /// 设置连接数
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size.max(1);
+        self
+    }
+
+    /// 设置建立连接超时
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 设置命令超时
+    pub fn command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+}
+
+/// 连接来源
+enum Source {
+    Client(Client),
+    /// 每次重连时向哨兵查询主节点, 主从切换后自动连接新的主节点
+    Sentinel(SentinelConfig),
+}
+
+/// 单节点连接池
+///
+/// 由多个多路复用连接组成, 请求轮询分配; 连接断开后下次使用时自动重连
+pub struct SinglePool {
+    source: Source,
+    config: PoolConfig,
+    slots: Vec<tokio::sync::Mutex<Option<MultiplexedConnection>>>,
+    next: AtomicUsize,
+}
+
+impl SinglePool {
+    pub fn new(client: Client, config: PoolConfig) -> Self {
+        SinglePool::with_source(Source::Client(client), config)
+    }
+
+    /// 哨兵模式
+    pub fn sentinel(sentinel: SentinelConfig, config: PoolConfig) -> Self {
+        SinglePool::with_source(Source::Sentinel(sentinel), config)
+    }
+
+    fn with_source(source: Source, config: PoolConfig) -> Self {
+        let slots = (0..config.pool_size.max(1))
+            .map(|_| tokio::sync::Mutex::new(None))
+            .collect();
+        SinglePool {
+            source,
+            config,
+            slots,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    async fn conn(&self, index: usize) -> CacheResult<MultiplexedConnection> {
+        let mut slot = self.slots[index].lock().await;
+        if let Some(conn) = slot.as_ref() {
+            return Ok(conn.clone());
+        }
+        let client = match &self.source {
+            Source::Client(client) => client.clone(),
+            Source::Sentinel(sentinel) => sentinel.resolve(self.config.connect_timeout).await?,
+        };
+        let conn = tokio::time::timeout(
+            self.config.connect_timeout,
+            client.get_multiplexed_tokio_connection(),
+        )
+        .await
+        .map_err(|_| CacheError::Timeout)??;
+        *slot = Some(conn.clone());
+        Ok(conn)
+    }
+
+    async fn reset(&self, index: usize) {
+        *self.slots[index].lock().await = None;
+    }
+
+    async fn query<T: FromRedisValue>(&self, cmd: &Cmd) -> CacheResult<T> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut conn = self.conn(index).await?;
+        let result = tokio::time::timeout(self.config.command_timeout, cmd.query_async(&mut conn))
+            .await
+            .map_err(|_| CacheError::Timeout)
+            .and_then(|r| r.map_err(CacheError::from));
+        if let Err(e) = &result {
+            if e.is_broken() {
+                self.reset(index).await;
+            }
+        }
+        result
+    }
+
+    async fn query_pipe<T: FromRedisValue>(&self, pipe: &Pipeline) -> CacheResult<T> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let mut conn = self.conn(index).await?;
+        let result = tokio::time::timeout(self.config.command_timeout, pipe.query_async(&mut conn))
+            .await
+            .map_err(|_| CacheError::Timeout)
+            .and_then(|r| r.map_err(CacheError::from));
+        if let Err(e) = &result {
+            if e.is_broken() {
+                self.reset(index).await;
+            }
+        }
+        result
+    }
+}
+
+/// 集群连接池
+///
+/// redis 集群连接只有同步接口, 命令在 actix 线程池中执行, 不阻塞异步执行器
+pub struct ClusterPool {
+    client: Arc<ClusterClient>,
+    config: PoolConfig,
+    idle: Mutex<Vec<ClusterConnection>>,
+    permits: Semaphore,
+}
+
+impl ClusterPool {
+    pub fn new(client: ClusterClient, config: PoolConfig) -> Self {
+        let permits = Semaphore::new(config.pool_size.max(1));
+        ClusterPool {
+            client: Arc::new(client),
+            config,
+            idle: Mutex::new(vec![]),
+            permits,
+        }
+    }
+
+    async fn run<T, F>(&self, f: F) -> CacheResult<T>
+    where
+        F: FnOnce(&mut ClusterConnection) -> RedisResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let _permit = self.permits.acquire().await;
+        let conn = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let client = self.client.clone();
+        let timeout = self.config.command_timeout;
+        let task = web::block(move || {
+            let mut conn = match conn {
+                Some(conn) => conn,
+                None => {
+                    let conn = client.get_connection()?;
+                    conn.set_read_timeout(Some(timeout))?;
+                    conn.set_write_timeout(Some(timeout))?;
+                    conn
+                }
+            };
+            let result = f(&mut conn);
+            Ok::<_, RedisError>((conn, result))
+        });
+        let (conn, result) =
+            match tokio::time::timeout(self.config.connect_timeout + timeout, task).await {
+                Ok(Ok(v)) => v,
+                Ok(Err(BlockingError::Error(e))) => return Err(e.into()),
+                Ok(Err(BlockingError::Canceled)) => {
+                    return Err(RedisError::from((ErrorKind::IoError, "redis 线程池已关闭")).into())
+                }
+                Err(_) => return Err(CacheError::Timeout),
+            };
+        let result = result.map_err(CacheError::from);
+        let broken = matches!(&result, Err(e) if e.is_broken());
+        if !broken {
+            self.idle
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(conn);
+        }
+        result
+    }
+}
+
+/// 异步缓存连接 (单节点或集群)
+#[derive(Clone)]
+pub enum AsyncCache {
+    Single(Arc<SinglePool>),
+    Cluster(Arc<ClusterPool>),
+}
+
+impl AsyncCache {
+    /// 执行命令
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// # async fn run() -> yn_util::caches::CacheResult<()> {
+    /// let cache = yn_util::caches::get_pool()?;
+    /// let _: () = cache.query(redis::cmd("SET").arg("hello").arg("world")).await?;
+    /// let val: Option<String> = cache.query(redis::cmd("GET").arg("hello")).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn query<T>(&self, cmd: &Cmd) -> CacheResult<T>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        match self {
+            AsyncCache::Single(pool) => pool.query(cmd).await,
+            AsyncCache::Cluster(pool) => {
+                let cmd = cmd.clone();
+                pool.run(move |conn| cmd.query(conn)).await
+            }
+        }
+    }
+
+    /// 执行管道命令 (集群模式下管道内的 key 须在同一 slot)
+    pub async fn query_pipe<T>(&self, pipe: &Pipeline) -> CacheResult<T>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        match self {
+            AsyncCache::Single(pool) => pool.query_pipe(pipe).await,
+            AsyncCache::Cluster(pool) => {
+                let pipe = pipe.clone();
+                pool.run(move |conn| pipe.query(conn)).await
+            }
+        }
+    }
+
+    /// 执行 lua 脚本 (集群模式下 keys 须在同一 slot)
+    pub async fn eval<T>(&self, script: &str, keys: &[String], args: &[String]) -> CacheResult<T>
+    where
+        T: FromRedisValue + Send + 'static,
+    {
+        self.query(&eval_cmd(script, keys, args)).await
+    }
+}
+
+/// `EVAL script numkeys key [key ...] arg [arg ...]`
+fn eval_cmd(script: &str, keys: &[String], args: &[String]) -> Cmd {
+    let mut cmd = redis::cmd("EVAL");
+    cmd.arg(script).arg(keys.len()).arg(keys).arg(args);
+    cmd
+}
+
+/// 初始化异步连接池 注册为默认缓存
+///
+/// # ! `单体使用`
+/// 連接字符串形似: 'redis://127.0.0.1'
+pub fn init_pool(conn_string: &str, config: PoolConfig) -> CacheResult<()> {
+    register(
+        DEFAULT,
+        CacheBackend::Single(conn_string.to_owned()),
+        config,
+    )
+}
+
+/// 初始化异步连接池 注册为默认缓存
+///
+/// # ! `集群使用`
+pub fn init_cluster_pool(nodes: Vec<String>, config: PoolConfig) -> CacheResult<()> {
+    register(DEFAULT, CacheBackend::Cluster(nodes), config)
+}
+
+/// 获取默认异步连接池
+pub fn get_pool() -> CacheResult<AsyncCache> {
+    get(DEFAULT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn run<F: std::future::Future + 'static>(f: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(f)
+    }
+
+    fn config() -> PoolConfig {
+        PoolConfig::new()
+            .pool_size(2)
+            .connect_timeout(Duration::from_millis(300))
+            .command_timeout(Duration::from_millis(200))
+    }
+
+    #[test]
+    fn pool_size_at_least_one() {
+        assert_eq!(PoolConfig::new().pool_size(0).pool_size, 1);
+        let client = Client::open("redis://127.0.0.1/").unwrap();
+        let pool = SinglePool::new(client, PoolConfig::new().pool_size(0));
+        assert_eq!(pool.slots.len(), 1);
+    }
+
+    #[test]
+    fn eval_packs_keys_and_args() {
+        let keys = vec!["k1".to_owned(), "k2".to_owned()];
+        let args = vec!["a".to_owned()];
+        let cmd = eval_cmd("return 1", &keys, &args);
+        let expected = redis::cmd("EVAL")
+            .arg("return 1")
+            .arg(2)
+            .arg("k1")
+            .arg("k2")
+            .arg("a")
+            .get_packed_command();
+        assert_eq!(cmd.get_packed_command(), expected);
+    }
+
+    #[test]
+    fn command_timeout_resets_connection() {
+        // 接受连接但从不响应
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _conns: Vec<_> = listener.incoming().collect();
+        });
+        let client = Client::open(format!("redis://{}/", addr)).unwrap();
+        let pool = SinglePool::new(client, config());
+        run(async move {
+            let result = pool
+                .query::<Option<String>>(redis::cmd("GET").arg("k"))
+                .await;
+            assert!(matches!(result, Err(CacheError::Timeout)));
+            // 超时的连接已丢弃, 下次使用时重连
+            assert!(pool.slots[0].lock().await.is_none());
+        });
+    }
+
+    #[test]
+    fn connection_refused_is_broken() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::open(format!("redis://{}/", addr)).unwrap();
+        let pool = SinglePool::new(client, config());
+        run(async move {
+            let err = pool
+                .query::<()>(redis::cmd("PING").arg("x"))
+                .await
+                .unwrap_err();
+            assert!(err.is_broken());
+            assert!(pool.slots.iter().all(|s| s.try_lock().unwrap().is_none()));
+        });
+    }
+}
@@ -0,0 +1,193 @@
+use super::*;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::Stream;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// 订阅消息
+#[derive(Clone, Debug)]
+pub struct Message<T> {
+    /// 消息所在频道
+    pub channel: String,
+    /// 模式订阅时匹配的模式
+    pub pattern: Option<String>,
+    pub payload: T,
+}
+
+/// 发布订阅
+///
+/// 消息经 codec 编码; 订阅在后台任务中维持, 连接断开后自动重连并重新订阅
+/// (断开期间发布的消息会丢失, 需要可靠投递时使用 [`StreamQueue`])
+///
+/// # Examples
+/// ```rust,no_run
+/// use futures::StreamExt;
+/// use yn_util::caches::Bus;
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct OrderPaid {
+///     order_no: String,
+/// }
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let bus = Bus::named("default")?;
+/// let mut orders = bus.psubscribe::<OrderPaid>(&["order:*"]);
+/// actix_web::rt::spawn(async move {
+///     while let Some(Ok(msg)) = orders.next().await {
+///         log::info!("{} {}", msg.channel, msg.payload.order_no);
+///     }
+/// });
+/// bus.publish("order:paid", &OrderPaid { order_no: "20210101".into() }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Bus<C: Codec = Json> {
+    name: String,
+    backend: AsyncCache,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec> Clone for Bus<C> {
+    fn clone(&self) -> Self {
+        Bus {
+            name: self.name.clone(),
+            backend: self.backend.clone(),
+            codec: PhantomData,
+        }
+    }
+}
+
+impl Bus<Json> {
+    /// 使用默认缓存
+    pub fn new() -> CacheResult<Self> {
+        Bus::named(DEFAULT)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str) -> CacheResult<Self> {
+        Ok(Bus {
+            name: name.to_owned(),
+            backend: get(name)?,
+            codec: PhantomData,
+        })
+    }
+}
+
+impl<C: Codec> Bus<C> {
+    /// 更换编码
+    pub fn codec<D: Codec>(self) -> Bus<D> {
+        Bus {
+            name: self.name,
+            backend: self.backend,
+            codec: PhantomData,
+        }
+    }
+
+    /// 发布 返回收到消息的订阅者数量
+    pub async fn publish<T: Serialize + ?Sized>(
+        &self,
+        channel: &str,
+        message: &T,
+    ) -> CacheResult<i64> {
+        self.backend
+            .query(redis::cmd("PUBLISH").arg(channel).arg(C::encode(message)?))
+            .await
+    }
+
+    /// 订阅频道 须在 actix 运行时内调用
+    pub fn subscribe<T>(&self, channels: &[&str]) -> Subscription<T>
+    where
+        T: DeserializeOwned + 'static,
+    {
+        self.listen(channels, false)
+    }
+
+    /// 按模式订阅 例如 `order:*`
+    pub fn psubscribe<T>(&self, patterns: &[&str]) -> Subscription<T>
+    where
+        T: DeserializeOwned + 'static,
+    {
+        self.listen(patterns, true)
+    }
+
+    fn listen<T>(&self, channels: &[&str], pattern: bool) -> Subscription<T>
+    where
+        T: DeserializeOwned + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded();
+        let channels = channels.iter().map(|c| c.to_string()).collect();
+        actix_web::rt::spawn(forward::<C, T>(self.name.clone(), channels, pattern, tx));
+        Subscription { rx }
+    }
+}
+
+/// 维持订阅并转发消息, 订阅方丢弃 [`Subscription`] 后结束
+async fn forward<C, T>(
+    name: String,
+    channels: Vec<String>,
+    pattern: bool,
+    tx: UnboundedSender<CacheResult<Message<T>>>,
+) where
+    C: Codec,
+    T: DeserializeOwned,
+{
+    let mut delay = Duration::from_millis(500);
+    while !tx.is_closed() {
+        let subscribed = async {
+            let mut pubsub = pubsub_conn(&name, Duration::from_secs(3)).await?;
+            for channel in channels.iter() {
+                if pattern {
+                    pubsub.psubscribe(channel).await?;
+                } else {
+                    pubsub.subscribe(channel).await?;
+                }
+            }
+            Ok::<_, CacheError>(pubsub)
+        };
+        let mut pubsub = match subscribed.await {
+            Ok(pubsub) => pubsub,
+            Err(e) => {
+                log::warn!("订阅 {:?} 失败, {}", channels, e);
+                tokio::time::delay_for(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+                continue;
+            }
+        };
+        delay = Duration::from_millis(500);
+        let mut messages = pubsub.on_message();
+        loop {
+            // 定期检查订阅方是否已丢弃
+            let msg = match tokio::time::timeout(Duration::from_secs(30), messages.next()).await {
+                Ok(Some(msg)) => msg,
+                Ok(None) => break,
+                Err(_) if tx.is_closed() => return,
+                Err(_) => continue,
+            };
+            let message = C::decode(msg.get_payload_bytes()).map(|payload| Message {
+                channel: msg.get_channel_name().to_owned(),
+                pattern: msg.get_pattern::<Option<String>>().ok().flatten(),
+                payload,
+            });
+            if tx.unbounded_send(message).is_err() {
+                return;
+            }
+        }
+        log::warn!("订阅 {:?} 连接断开, 重新订阅", channels);
+    }
+}
+
+/// 订阅消息流
+pub struct Subscription<T> {
+    rx: UnboundedReceiver<CacheResult<Message<T>>>,
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = CacheResult<Message<T>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
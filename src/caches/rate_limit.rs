@@ -0,0 +1,359 @@
+use super::*;
+use crate::utils::BusinessError;
+use std::collections::VecDeque;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// 固定窗口: 窗口内计数, 首次计数时设置过期
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local n = redis.call("INCR", KEYS[1])
+if n == 1 then
+    redis.call("PEXPIRE", KEYS[1], ARGV[1])
+end
+local ttl = redis.call("PTTL", KEYS[1])
+if ttl < 0 then
+    redis.call("PEXPIRE", KEYS[1], ARGV[1])
+    ttl = tonumber(ARGV[1])
+end
+return {n, ttl}
+"#;
+
+/// 滑动窗口: 有序集合记录窗口内每次请求的时间
+const SLIDING_WINDOW_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local limit = tonumber(ARGV[3])
+redis.call("ZREMRANGEBYSCORE", KEYS[1], "-inf", now - window)
+local count = redis.call("ZCARD", KEYS[1])
+local allowed = 0
+if count < limit then
+    redis.call("ZADD", KEYS[1], now, ARGV[4])
+    redis.call("PEXPIRE", KEYS[1], window)
+    count = count + 1
+    allowed = 1
+end
+local reset = window
+local oldest = redis.call("ZRANGE", KEYS[1], 0, 0, "WITHSCORES")
+if oldest[2] then
+    reset = tonumber(oldest[2]) + window - now
+end
+return {allowed, count, reset}
+"#;
+
+/// 令牌桶: 按时间补充令牌, 每次请求消耗一个
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local data = redis.call("HMGET", KEYS[1], "tokens", "ts")
+local tokens = tonumber(data[1]) or capacity
+local ts = tonumber(data[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) / interval)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call("HMSET", KEYS[1], "tokens", tostring(tokens), "ts", now)
+redis.call("PEXPIRE", KEYS[1], math.ceil(capacity * interval))
+local wait = 0
+if allowed == 0 then
+    wait = math.ceil((1 - tokens) * interval)
+end
+return {allowed, math.floor(tokens), wait, math.ceil((capacity - tokens) * interval)}
+"#;
+
+/// 本地计数超过此数量时清理过期的 key
+const LOCAL_PRUNE_SIZE: usize = 10_000;
+
+/// 限流算法
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateAlgorithm {
+    /// 固定窗口 实现简单, 窗口交界处可能出现两倍突发
+    FixedWindow,
+    /// 滑动窗口 精确, 每个请求占用一条记录
+    SlidingWindow,
+    /// 令牌桶 允许 limit 个突发, 之后按 window/limit 的速率放行
+    TokenBucket,
+}
+
+/// 限流规则: window 时间内最多 limit 次
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    pub algorithm: RateAlgorithm,
+    pub limit: u64,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub fn new(algorithm: RateAlgorithm, limit: u64, window: Duration) -> Self {
+        RateLimit {
+            algorithm,
+            limit: limit.max(1),
+            window: window.max(Duration::from_millis(1)),
+        }
+    }
+
+    /// 固定窗口
+    pub fn fixed_window(limit: u64, window: Duration) -> Self {
+        RateLimit::new(RateAlgorithm::FixedWindow, limit, window)
+    }
+
+    /// 滑动窗口
+    pub fn sliding_window(limit: u64, window: Duration) -> Self {
+        RateLimit::new(RateAlgorithm::SlidingWindow, limit, window)
+    }
+
+    /// 令牌桶 容量 capacity, 每 per 时间补满
+    pub fn token_bucket(capacity: u64, per: Duration) -> Self {
+        RateLimit::new(RateAlgorithm::TokenBucket, capacity, per)
+    }
+
+    fn window_ms(&self) -> u64 {
+        self.window.as_millis() as u64
+    }
+
+    /// 令牌桶补充一个令牌的毫秒数
+    fn interval_ms(&self) -> f64 {
+        self.window_ms() as f64 / self.limit as f64
+    }
+}
+
+/// 限流结果
+#[derive(Clone, Debug)]
+pub struct RateDecision {
+    /// 是否放行
+    pub allowed: bool,
+    /// 窗口内上限
+    pub limit: u64,
+    /// 剩余次数
+    pub remaining: u64,
+    /// 距离额度完全恢复的时间
+    pub reset: Duration,
+    /// 被拒绝时 建议的重试等待时间
+    pub retry_after: Option<Duration>,
+}
+
+enum LocalState {
+    Window { start: Instant, count: u64 },
+    Log(VecDeque<Instant>),
+    Bucket { tokens: f64, ts: Instant },
+}
+
+/// 限流器
+///
+/// 计数保存在 redis 中 (lua 脚本保证原子性), 多实例共享额度;
+/// redis 故障且开启降级模式时使用进程内计数
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::{RateLimit, RateLimiter};
+///
+/// # async fn run(phone: &str) -> Result<(), yn_util::utils::BusinessError> {
+/// // 同一手机号每分钟最多发送 1 条短信
+/// let limiter = RateLimiter::new(RateLimit::fixed_window(1, Duration::from_secs(60))).prefix("sms");
+/// let decision = limiter.check(phone).await?;
+/// if !decision.allowed {
+///     // 返回 "发送过于频繁"
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: String,
+    prefix: String,
+    rule: RateLimit,
+    local: Arc<Mutex<HashMap<String, LocalState>>>,
+}
+
+impl RateLimiter {
+    /// 使用默认缓存
+    pub fn new(rule: RateLimit) -> Self {
+        RateLimiter::named(DEFAULT, rule)
+    }
+
+    /// 使用命名缓存 (缓存在首次检查时获取, 可先于缓存初始化创建)
+    pub fn named(name: &str, rule: RateLimit) -> Self {
+        RateLimiter {
+            name: name.to_owned(),
+            prefix: "rate".to_owned(),
+            rule,
+            local: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 设置 key 前缀 用于区分不同接口的限流
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = format!("rate:{}", prefix);
+        self
+    }
+
+    /// 限流规则
+    pub fn rule(&self) -> &RateLimit {
+        &self.rule
+    }
+
+    /// 检查并计数
+    pub async fn check(&self, key: &str) -> Result<RateDecision, BusinessError> {
+        match degrade(self.check_remote(key).await)? {
+            Some(decision) => Ok(decision),
+            None => Ok(self.check_local(key)),
+        }
+    }
+
+    /// 只使用 redis 检查
+    pub async fn check_remote(&self, key: &str) -> CacheResult<RateDecision> {
+        let cache = get(&self.name)?;
+        let keys = [format!("{}:{}", self.prefix, key)];
+        let rule = &self.rule;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let ms = Duration::from_millis;
+        let decision = match rule.algorithm {
+            RateAlgorithm::FixedWindow => {
+                let args = [rule.window_ms().to_string()];
+                let (count, ttl): (u64, u64) =
+                    cache.eval(FIXED_WINDOW_SCRIPT, &keys, &args).await?;
+                let allowed = count <= rule.limit;
+                RateDecision {
+                    allowed,
+                    limit: rule.limit,
+                    remaining: rule.limit.saturating_sub(count),
+                    reset: ms(ttl),
+                    retry_after: if allowed { None } else { Some(ms(ttl)) },
+                }
+            }
+            RateAlgorithm::SlidingWindow => {
+                // 同一毫秒内的请求以唯一后缀区分
+                let member = format!("{}-{}", now, bson::oid::ObjectId::new().to_hex());
+                let args = [
+                    now.to_string(),
+                    rule.window_ms().to_string(),
+                    rule.limit.to_string(),
+                    member,
+                ];
+                let (allowed, count, reset): (u64, u64, u64) =
+                    cache.eval(SLIDING_WINDOW_SCRIPT, &keys, &args).await?;
+                let allowed = allowed == 1;
+                RateDecision {
+                    allowed,
+                    limit: rule.limit,
+                    remaining: rule.limit.saturating_sub(count),
+                    reset: ms(reset),
+                    retry_after: if allowed { None } else { Some(ms(reset)) },
+                }
+            }
+            RateAlgorithm::TokenBucket => {
+                let args = [
+                    rule.limit.to_string(),
+                    rule.interval_ms().to_string(),
+                    now.to_string(),
+                ];
+                let (allowed, tokens, wait, reset): (u64, u64, u64, u64) =
+                    cache.eval(TOKEN_BUCKET_SCRIPT, &keys, &args).await?;
+                RateDecision {
+                    allowed: allowed == 1,
+                    limit: rule.limit,
+                    remaining: tokens,
+                    reset: ms(reset),
+                    retry_after: if allowed == 1 { None } else { Some(ms(wait)) },
+                }
+            }
+        };
+        Ok(decision)
+    }
+
+    /// 只使用进程内计数检查
+    pub fn check_local(&self, key: &str) -> RateDecision {
+        let rule = &self.rule;
+        let now = Instant::now();
+        let mut states = self.local.lock().unwrap_or_else(|e| e.into_inner());
+        if states.len() > LOCAL_PRUNE_SIZE {
+            states.retain(|_, state| match state {
+                LocalState::Window { start, .. } => now.duration_since(*start) < rule.window,
+                LocalState::Log(log) => log
+                    .back()
+                    .is_some_and(|t| now.duration_since(*t) < rule.window),
+                LocalState::Bucket { ts, .. } => now.duration_since(*ts) < rule.window,
+            });
+        }
+        let state = states
+            .entry(key.to_owned())
+            .or_insert_with(|| match rule.algorithm {
+                RateAlgorithm::FixedWindow => LocalState::Window {
+                    start: now,
+                    count: 0,
+                },
+                RateAlgorithm::SlidingWindow => LocalState::Log(VecDeque::new()),
+                RateAlgorithm::TokenBucket => LocalState::Bucket {
+                    tokens: rule.limit as f64,
+                    ts: now,
+                },
+            });
+        match state {
+            LocalState::Window { start, count } => {
+                if now.duration_since(*start) >= rule.window {
+                    *start = now;
+                    *count = 0;
+                }
+                *count += 1;
+                let reset = rule.window - now.duration_since(*start);
+                let allowed = *count <= rule.limit;
+                RateDecision {
+                    allowed,
+                    limit: rule.limit,
+                    remaining: rule.limit.saturating_sub(*count),
+                    reset,
+                    retry_after: if allowed { None } else { Some(reset) },
+                }
+            }
+            LocalState::Log(log) => {
+                while log
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= rule.window)
+                {
+                    log.pop_front();
+                }
+                let allowed = (log.len() as u64) < rule.limit;
+                if allowed {
+                    log.push_back(now);
+                }
+                let reset = log
+                    .front()
+                    .map_or(rule.window, |t| rule.window - now.duration_since(*t));
+                RateDecision {
+                    allowed,
+                    limit: rule.limit,
+                    remaining: rule.limit.saturating_sub(log.len() as u64),
+                    reset,
+                    retry_after: if allowed { None } else { Some(reset) },
+                }
+            }
+            LocalState::Bucket { tokens, ts } => {
+                let interval = rule.interval_ms();
+                let elapsed = now.duration_since(*ts).as_millis() as f64;
+                *tokens = (*tokens + elapsed / interval).min(rule.limit as f64);
+                *ts = now;
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                let ms = |v: f64| Duration::from_millis(v.ceil() as u64);
+                RateDecision {
+                    allowed,
+                    limit: rule.limit,
+                    remaining: tokens.floor() as u64,
+                    reset: ms((rule.limit as f64 - *tokens) * interval),
+                    retry_after: if allowed {
+                        None
+                    } else {
+                        Some(ms((1.0 - *tokens) * interval))
+                    },
+                }
+            }
+        }
+    }
+}
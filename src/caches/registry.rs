@@ -0,0 +1,239 @@
+use super::*;
+use redis::aio::{Connection, PubSub};
+use redis::{ConnectionAddr, ConnectionInfo};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// 默认缓存名称 `init_connections` / `init_pool` 等未指定名称的接口使用
+pub const DEFAULT: &str = "default";
+
+lazy_static! {
+    // 已注册的异步缓存  key: 名称
+    static ref REGISTRY: RwLock<HashMap<String, AsyncCache>> = RwLock::new(HashMap::new());
+    // 缓存后端配置 同步连接 / 订阅连接按需建立
+    static ref BACKENDS: RwLock<HashMap<String, CacheBackend>> = RwLock::new(HashMap::new());
+}
+
+/// 哨兵配置
+#[derive(Clone, Debug)]
+pub struct SentinelConfig {
+    /// 哨兵节点 形似 'redis://127.0.0.1:26379'
+    pub sentinels: Vec<String>,
+    /// 主节点名称
+    pub master_name: String,
+    /// 主节点密码
+    pub password: Option<String>,
+    /// 数据库
+    pub db: i64,
+}
+
+impl SentinelConfig {
+    pub fn new(sentinels: Vec<String>, master_name: &str) -> Self {
+        SentinelConfig {
+            sentinels,
+            master_name: master_name.to_owned(),
+            password: None,
+            db: 0,
+        }
+    }
+
+    /// 设置主节点密码
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_owned());
+        self
+    }
+
+    /// 设置数据库
+    pub fn db(mut self, db: i64) -> Self {
+        self.db = db;
+        self
+    }
+
+    fn master_client(&self, (host, port): (String, u16)) -> CacheResult<Client> {
+        Ok(Client::open(ConnectionInfo {
+            addr: Box::new(ConnectionAddr::Tcp(host, port)),
+            db: self.db,
+            username: None,
+            passwd: self.password.clone(),
+        })?)
+    }
+
+    fn query_master(&self) -> redis::Cmd {
+        let mut cmd = redis::cmd("SENTINEL");
+        cmd.arg("get-master-addr-by-name").arg(&self.master_name);
+        cmd
+    }
+
+    /// 向哨兵查询当前主节点 依次尝试每个哨兵
+    pub async fn resolve(&self, timeout: Duration) -> CacheResult<Client> {
+        let mut last = CacheError::NotInitialized;
+        for url in self.sentinels.iter() {
+            let query = async {
+                let mut conn = Client::open(url.as_str())?.get_async_connection().await?;
+                self.query_master().query_async(&mut conn).await
+            };
+            match tokio::time::timeout(timeout, query).await {
+                Ok(Ok(addr)) => return self.master_client(addr),
+                Ok(Err(e)) => last = e.into(),
+                Err(_) => last = CacheError::Timeout,
+            }
+            log::warn!("sentinel {} 查询主节点失败, {}", url, last);
+        }
+        Err(last)
+    }
+
+    /// 向哨兵查询当前主节点 (同步)
+    pub fn resolve_sync(&self, timeout: Duration) -> CacheResult<Client> {
+        let mut last = CacheError::NotInitialized;
+        for url in self.sentinels.iter() {
+            let query = Client::open(url.as_str())
+                .and_then(|c| c.get_connection_with_timeout(timeout))
+                .and_then(|mut conn| self.query_master().query(&mut conn));
+            match query {
+                Ok(addr) => return self.master_client(addr),
+                Err(e) => last = e.into(),
+            }
+            log::warn!("sentinel {} 查询主节点失败, {}", url, last);
+        }
+        Err(last)
+    }
+}
+
+/// 缓存后端
+#[derive(Clone, Debug)]
+pub enum CacheBackend {
+    /// 单节点 连接字符串形似 'redis://127.0.0.1'
+    Single(String),
+    /// 集群节点
+    Cluster(Vec<String>),
+    /// 哨兵
+    Sentinel(SentinelConfig),
+}
+
+/// 注册命名缓存, 同名时替换
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::caches::{self, CacheBackend, PoolConfig, SentinelConfig};
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// caches::register("sessions", CacheBackend::Single("redis://127.0.0.1/1".into()), PoolConfig::new())?;
+/// caches::register(
+///     "business",
+///     CacheBackend::Cluster(vec!["redis://10.0.0.1:7000".into(), "redis://10.0.0.2:7000".into()]),
+///     PoolConfig::new().pool_size(8),
+/// )?;
+/// caches::register(
+///     "rate_limit",
+///     CacheBackend::Sentinel(SentinelConfig::new(vec!["redis://10.0.0.3:26379".into()], "mymaster")),
+///     PoolConfig::new(),
+/// )?;
+///
+/// let sessions = caches::get("sessions")?;
+/// let ttl: i64 = sessions.query(redis::cmd("TTL").arg("session:1")).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn register(name: &str, backend: CacheBackend, config: PoolConfig) -> CacheResult<()> {
+    let cache = match backend.clone() {
+        CacheBackend::Single(url) => {
+            AsyncCache::Single(Arc::new(SinglePool::new(Client::open(url)?, config)))
+        }
+        CacheBackend::Cluster(nodes) => AsyncCache::Cluster(Arc::new(ClusterPool::new(
+            ClusterClient::open(nodes)?,
+            config,
+        ))),
+        CacheBackend::Sentinel(sentinel) => {
+            AsyncCache::Single(Arc::new(SinglePool::sentinel(sentinel, config)))
+        }
+    };
+    // 同步连接 get_conn_by / get_cluster_conn_by 使用同一配置
+    remove_sync(name);
+    match &backend {
+        CacheBackend::Single(url) => register_sync(name, Client::open(url.as_str())?),
+        CacheBackend::Cluster(nodes) => register_cluster_sync(name, nodes.clone())?,
+        CacheBackend::Sentinel(_) => {}
+    }
+    BACKENDS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_owned(), backend);
+    REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_owned(), cache);
+    Ok(())
+}
+
+/// 获取命名缓存
+pub fn get(name: &str) -> CacheResult<AsyncCache> {
+    REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or_else(|| match name {
+            DEFAULT => CacheError::NotInitialized,
+            _ => CacheError::Unknown(name.to_owned()),
+        })
+}
+
+/// 已注册的缓存名称
+pub fn names() -> Vec<String> {
+    REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .keys()
+        .cloned()
+        .collect()
+}
+
+pub(crate) fn sentinel(name: &str) -> Option<SentinelConfig> {
+    match backend(name)? {
+        CacheBackend::Sentinel(sentinel) => Some(sentinel),
+        _ => None,
+    }
+}
+
+fn backend(name: &str) -> Option<CacheBackend> {
+    BACKENDS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// 建立独立的连接 (订阅 / 阻塞读取等不能与其他命令共用的场景)
+///
+/// 集群时依次尝试各节点直到连接成功
+pub(crate) async fn dedicated_conn(name: &str, timeout: Duration) -> CacheResult<Connection> {
+    let urls = match backend(name) {
+        Some(CacheBackend::Single(url)) => vec![url],
+        Some(CacheBackend::Cluster(nodes)) => nodes,
+        Some(CacheBackend::Sentinel(sentinel)) => {
+            let client = sentinel.resolve(timeout).await?;
+            return tokio::time::timeout(timeout, client.get_async_connection())
+                .await
+                .map_err(|_| CacheError::Timeout)?
+                .map_err(CacheError::from);
+        }
+        None if name == DEFAULT => return Err(CacheError::NotInitialized),
+        None => return Err(CacheError::Unknown(name.to_owned())),
+    };
+    let mut last = CacheError::NotInitialized;
+    for url in urls.iter() {
+        let connect = async { Client::open(url.as_str())?.get_async_connection().await };
+        match tokio::time::timeout(timeout, connect).await {
+            Ok(Ok(conn)) => return Ok(conn),
+            Ok(Err(e)) => last = e.into(),
+            Err(_) => last = CacheError::Timeout,
+        }
+        log::warn!("{} 连接失败, {}", url, last);
+    }
+    Err(last)
+}
+
+/// 建立订阅连接 集群中任一节点发布的消息会广播到全部节点
+pub(crate) async fn pubsub_conn(name: &str, timeout: Duration) -> CacheResult<PubSub> {
+    Ok(dedicated_conn(name, timeout).await?.into_pubsub())
+}
@@ -0,0 +1,497 @@
+use super::*;
+use redis::streams::{
+    StreamClaimReply, StreamId, StreamPendingCountReply, StreamPendingId, StreamReadReply,
+};
+use redis::Value;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+/// 消息内容字段
+const PAYLOAD_FIELD: &str = "payload";
+
+/// 每次认领最多扫描的 XPENDING 页数 (与 XAUTOCLAIM 相同, 共 count * 10 条)
+const CLAIM_SCAN_PAGES: usize = 10;
+
+/// 消息队列配置
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    /// 每次读取的最大条数
+    pub count: usize,
+    /// 没有新消息时的阻塞等待时间 `None` 时立即返回
+    pub block: Option<Duration>,
+    /// 队列最大长度 (近似裁剪) `None` 时不裁剪
+    pub max_len: Option<usize>,
+    /// 消息超过此时间未确认视为消费者已失效, 可被其他消费者认领
+    pub claim_idle: Duration,
+    /// 无法解码的消息转入的死信队列 `None` 时保留在未确认列表中
+    pub dead_letter: Option<String>,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions {
+            count: 10,
+            block: Some(Duration::from_secs(5)),
+            max_len: Some(100_000),
+            claim_idle: Duration::from_secs(60),
+            dead_letter: None,
+        }
+    }
+}
+
+impl StreamOptions {
+    pub fn new() -> Self {
+        StreamOptions::default()
+    }
+
+    /// 设置每次读取的最大条数
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = count.max(1);
+        self
+    }
+
+    /// 设置阻塞等待时间
+    pub fn block(mut self, block: Option<Duration>) -> Self {
+        self.block = block;
+        self
+    }
+
+    /// 设置队列最大长度
+    pub fn max_len(mut self, max_len: Option<usize>) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// 设置未确认消息的认领时间
+    pub fn claim_idle(mut self, idle: Duration) -> Self {
+        self.claim_idle = idle;
+        self
+    }
+
+    /// 设置死信队列
+    ///
+    /// 死信消息的字段: `id` 原消息 id, `payload` 原消息内容, `error` 解码错误
+    pub fn dead_letter(mut self, key: Option<&str>) -> Self {
+        self.dead_letter = key.map(|k| k.to_owned());
+        self
+    }
+}
+
+/// 队列消息
+#[derive(Clone, Debug)]
+pub struct StreamEntry<T> {
+    /// 消息 id 确认时使用
+    pub id: String,
+    pub payload: T,
+}
+
+/// 基于 redis streams 消费组的消息队列 (至少一次投递)
+///
+/// 同一消费组内每条消息只投递给一个消费者, 处理完成后须 [`StreamQueue::ack`];
+/// 消费者崩溃留下的未确认消息由其他消费者通过 [`StreamQueue::claim_stale`] 接管
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::caches::{StreamOptions, StreamQueue};
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct SendSms {
+///     phone: String,
+///     content: String,
+/// }
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let opts = StreamOptions::new().dead_letter(Some("jobs:sms:dead"));
+/// let queue = StreamQueue::new("jobs:sms", "sms-workers", "worker-1")?.options(opts);
+/// queue.create_group().await?;
+/// queue.add(&SendSms { phone: "13800000000".into(), content: "验证码 1234".into() }).await?;
+///
+/// loop {
+///     let mut jobs = queue.claim_stale::<SendSms>().await?;
+///     jobs.extend(queue.read::<SendSms>().await?);
+///     for job in jobs {
+///         // ... 发送短信
+///         queue.ack(&[job.id.as_str()]).await?;
+///     }
+/// }
+/// # }
+/// ```
+pub struct StreamQueue<C: Codec = Json> {
+    name: String,
+    backend: AsyncCache,
+    key: String,
+    group: String,
+    consumer: String,
+    opts: StreamOptions,
+    /// 阻塞读取使用的独立连接 (阻塞命令会占住多路复用连接)
+    blocking: Arc<tokio::sync::Mutex<Option<redis::aio::Connection>>>,
+    /// 下次认领时 XPENDING 的起点
+    claim_cursor: Arc<Mutex<String>>,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec> Clone for StreamQueue<C> {
+    fn clone(&self) -> Self {
+        StreamQueue {
+            name: self.name.clone(),
+            backend: self.backend.clone(),
+            key: self.key.clone(),
+            group: self.group.clone(),
+            consumer: self.consumer.clone(),
+            opts: self.opts.clone(),
+            blocking: Arc::new(tokio::sync::Mutex::new(None)),
+            claim_cursor: Arc::new(Mutex::new("-".to_owned())),
+            codec: PhantomData,
+        }
+    }
+}
+
+impl StreamQueue<Json> {
+    /// 使用默认缓存
+    pub fn new(key: &str, group: &str, consumer: &str) -> CacheResult<Self> {
+        StreamQueue::named(DEFAULT, key, group, consumer)
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str, key: &str, group: &str, consumer: &str) -> CacheResult<Self> {
+        Ok(StreamQueue {
+            name: name.to_owned(),
+            backend: get(name)?,
+            key: key.to_owned(),
+            group: group.to_owned(),
+            consumer: consumer.to_owned(),
+            opts: StreamOptions::default(),
+            blocking: Arc::new(tokio::sync::Mutex::new(None)),
+            claim_cursor: Arc::new(Mutex::new("-".to_owned())),
+            codec: PhantomData,
+        })
+    }
+}
+
+impl<C: Codec> StreamQueue<C> {
+    /// 更换编码
+    pub fn codec<D: Codec>(self) -> StreamQueue<D> {
+        StreamQueue {
+            name: self.name,
+            backend: self.backend,
+            key: self.key,
+            group: self.group,
+            consumer: self.consumer,
+            opts: self.opts,
+            blocking: self.blocking,
+            claim_cursor: self.claim_cursor,
+            codec: PhantomData,
+        }
+    }
+
+    /// 设置队列配置
+    pub fn options(mut self, opts: StreamOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// 创建消费组 (不存在时同时创建队列), 已存在时忽略
+    pub async fn create_group(&self) -> CacheResult<()> {
+        let mut cmd = redis::cmd("XGROUP");
+        cmd.arg("CREATE")
+            .arg(&self.key)
+            .arg(&self.group)
+            .arg("$")
+            .arg("MKSTREAM");
+        match self.backend.query::<()>(&cmd).await {
+            Err(CacheError::Redis(e)) if e.code() == Some("BUSYGROUP") => Ok(()),
+            result => result,
+        }
+    }
+
+    /// 添加消息 返回消息 id
+    pub async fn add<T: Serialize + ?Sized>(&self, payload: &T) -> CacheResult<String> {
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(&self.key);
+        if let Some(max_len) = self.opts.max_len {
+            cmd.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        cmd.arg("*").arg(PAYLOAD_FIELD).arg(C::encode(payload)?);
+        self.backend.query(&cmd).await
+    }
+
+    /// 读取新消息
+    ///
+    /// 单节点时按 `block` 配置阻塞等待; 集群下不阻塞, 没有消息时返回空
+    pub async fn read<T: DeserializeOwned>(&self) -> CacheResult<Vec<StreamEntry<T>>> {
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP")
+            .arg(&self.group)
+            .arg(&self.consumer)
+            .arg("COUNT")
+            .arg(self.opts.count);
+        let reply: Option<StreamReadReply> = match (&self.backend, self.opts.block) {
+            (AsyncCache::Single(_), Some(block)) => {
+                cmd.arg("BLOCK")
+                    .arg(block.as_millis() as u64)
+                    .arg("STREAMS")
+                    .arg(&self.key)
+                    .arg(">");
+                self.query_blocking(&cmd, block).await?
+            }
+            _ => {
+                cmd.arg("STREAMS").arg(&self.key).arg(">");
+                self.backend.query(&cmd).await?
+            }
+        };
+        let ids = reply
+            .map(|r| r.keys.into_iter().flat_map(|k| k.ids).collect())
+            .unwrap_or_default();
+        self.decode(ids).await
+    }
+
+    async fn query_blocking<T: redis::FromRedisValue>(
+        &self,
+        cmd: &redis::Cmd,
+        block: Duration,
+    ) -> CacheResult<T> {
+        let mut slot = self.blocking.lock().await;
+        let mut conn = match slot.take() {
+            Some(conn) => conn,
+            None => dedicated_conn(&self.name, Duration::from_secs(3)).await?,
+        };
+        let result = tokio::time::timeout(
+            block + Duration::from_secs(3),
+            cmd.query_async::<_, T>(&mut conn),
+        )
+        .await
+        .map_err(|_| CacheError::Timeout)
+        .and_then(|r| r.map_err(CacheError::from));
+        if !matches!(&result, Err(e) if e.is_broken()) {
+            *slot = Some(conn);
+        }
+        result
+    }
+
+    /// 确认消息已处理
+    pub async fn ack(&self, ids: &[&str]) -> CacheResult<i64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        self.backend
+            .query(redis::cmd("XACK").arg(&self.key).arg(&self.group).arg(ids))
+            .await
+    }
+
+    /// 认领超过 `claim_idle` 未确认的消息 (其他消费者崩溃或超时留下的)
+    ///
+    /// 分页扫描未确认列表, 最多认领 `count` 条; 每次最多扫描 `count * 10` 条,
+    /// 下次从上次停止的位置继续, 到达末尾后从头开始
+    pub async fn claim_stale<T: DeserializeOwned>(&self) -> CacheResult<Vec<StreamEntry<T>>> {
+        let idle = self.opts.claim_idle.as_millis() as u64;
+        let mut start = self
+            .claim_cursor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let mut ids = vec![];
+        for _ in 0..CLAIM_SCAN_PAGES {
+            let pending: StreamPendingCountReply = self
+                .backend
+                .query(
+                    redis::cmd("XPENDING")
+                        .arg(&self.key)
+                        .arg(&self.group)
+                        .arg(&start)
+                        .arg("+")
+                        .arg(self.opts.count),
+                )
+                .await?;
+            let next = select_idle(pending.ids, self.opts.count, idle, &mut ids);
+            start = next.unwrap_or_else(|| "-".to_owned());
+            if start == "-" || ids.len() >= self.opts.count {
+                break;
+            }
+        }
+        *self.claim_cursor.lock().unwrap_or_else(|e| e.into_inner()) = start;
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let claimed: StreamClaimReply = self
+            .backend
+            .query(
+                redis::cmd("XCLAIM")
+                    .arg(&self.key)
+                    .arg(&self.group)
+                    .arg(&self.consumer)
+                    .arg(idle)
+                    .arg(ids),
+            )
+            .await?;
+        self.decode(claimed.ids).await
+    }
+
+    /// 消费组中未确认的消息数量
+    pub async fn pending(&self) -> CacheResult<usize> {
+        let (count, ..): (usize, Value, Value, Value) = self
+            .backend
+            .query(redis::cmd("XPENDING").arg(&self.key).arg(&self.group))
+            .await?;
+        Ok(count)
+    }
+
+    /// 解码消息
+    ///
+    /// 无法解码的消息转入死信队列后确认; 未配置死信队列时保留在未确认列表中,
+    /// 在 `claim_idle` 后会被再次认领
+    async fn decode<T: DeserializeOwned>(
+        &self,
+        ids: Vec<StreamId>,
+    ) -> CacheResult<Vec<StreamEntry<T>>> {
+        let mut entries = vec![];
+        for item in ids {
+            match decode_entry::<C, T>(&item) {
+                Ok(payload) => entries.push(StreamEntry {
+                    id: item.id,
+                    payload,
+                }),
+                Err(e) => {
+                    log::error!("队列 {} 消息 {} 无法解码, {}", self.key, item.id, e);
+                    if let Some(dead) = &self.opts.dead_letter {
+                        self.bury(dead, &item, &e).await?;
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// 转入死信队列并确认
+    async fn bury(&self, dead: &str, item: &StreamId, error: &CacheError) -> CacheResult<()> {
+        let mut cmd = redis::cmd("XADD");
+        cmd.arg(dead);
+        if let Some(max_len) = self.opts.max_len {
+            cmd.arg("MAXLEN").arg("~").arg(max_len);
+        }
+        cmd.arg("*").arg("id").arg(&item.id);
+        if let Some(Value::Data(bytes)) = item.map.get(PAYLOAD_FIELD) {
+            cmd.arg(PAYLOAD_FIELD).arg(bytes.as_slice());
+        }
+        cmd.arg("error").arg(error.to_string());
+        self.backend.query::<String>(&cmd).await?;
+        self.ack(&[item.id.as_str()]).await?;
+        Ok(())
+    }
+}
+
+fn decode_entry<C: Codec, T: DeserializeOwned>(item: &StreamId) -> CacheResult<T> {
+    match item.map.get(PAYLOAD_FIELD) {
+        Some(Value::Data(bytes)) => C::decode(bytes),
+        _ => Err(CacheError::Codec("缺少消息内容".to_owned())),
+    }
+}
+
+/// 从一页 XPENDING 结果中选出空闲超过 idle 毫秒的消息, 加入 ids 直到满 count 条
+///
+/// 返回下一页的起点, 已到达末尾时返回 None
+fn select_idle(
+    page: Vec<StreamPendingId>,
+    count: usize,
+    idle: u64,
+    ids: &mut Vec<String>,
+) -> Option<String> {
+    let full = page.len() >= count;
+    let mut last = None;
+    for pending in page {
+        if ids.len() >= count {
+            break;
+        }
+        if pending.last_delivered_ms as u64 >= idle {
+            ids.push(pending.id.clone());
+        }
+        last = Some(pending.id);
+    }
+    match (ids.len() >= count, full) {
+        (false, false) => None,
+        _ => next_id(&last?),
+    }
+}
+
+/// 紧随其后的消息 id (`毫秒-序号`), XPENDING 的起点包含自身
+fn next_id(id: &str) -> Option<String> {
+    let (ms, seq) = id.split_once('-')?;
+    let (ms, seq): (u64, u64) = (ms.parse().ok()?, seq.parse().ok()?);
+    Some(match seq.checked_add(1) {
+        Some(seq) => format!("{}-{}", ms, seq),
+        None => format!("{}-0", ms.checked_add(1)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn pending(id: &str, idle: usize) -> StreamPendingId {
+        StreamPendingId {
+            id: id.to_owned(),
+            last_delivered_ms: idle,
+            ..Default::default()
+        }
+    }
+
+    fn entry(fields: &[(&str, &[u8])]) -> StreamId {
+        let map: HashMap<String, Value> = fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::Data(v.to_vec())))
+            .collect();
+        StreamId {
+            id: "1-0".to_owned(),
+            map,
+        }
+    }
+
+    #[test]
+    fn next_ids() {
+        assert_eq!(next_id("1526569495631-0").unwrap(), "1526569495631-1");
+        assert_eq!(next_id(&format!("5-{}", u64::MAX)).unwrap(), "6-0");
+        assert_eq!(next_id("bad"), None);
+        assert_eq!(next_id("1-x"), None);
+    }
+
+    #[test]
+    fn select_pages_through_pending() {
+        // 整页都未超时 继续下一页
+        let mut ids = vec![];
+        let next = select_idle(vec![pending("1-0", 5), pending("2-0", 5)], 2, 10, &mut ids);
+        assert!(ids.is_empty());
+        assert_eq!(next.as_deref(), Some("2-1"));
+        // 不足一页 已到末尾
+        let next = select_idle(vec![pending("3-0", 50)], 2, 10, &mut ids);
+        assert_eq!(ids, vec!["3-0"]);
+        assert_eq!(next, None);
+        // 凑满 count 条时停在最后选中的消息之后
+        let mut ids = vec![];
+        let page = vec![pending("1-0", 50), pending("2-0", 50), pending("3-0", 50)];
+        let next = select_idle(page, 2, 10, &mut ids);
+        assert_eq!(ids, vec!["1-0", "2-0"]);
+        assert_eq!(next.as_deref(), Some("2-1"));
+    }
+
+    #[test]
+    fn decode_entries() {
+        let ok = entry(&[(PAYLOAD_FIELD, b"{\"n\":1}")]);
+        let value: serde_json::Value = decode_entry::<Json, _>(&ok).unwrap();
+        assert_eq!(value["n"], 1);
+        let missing = entry(&[("other", b"1")]);
+        assert!(matches!(
+            decode_entry::<Json, serde_json::Value>(&missing),
+            Err(CacheError::Codec(_))
+        ));
+        let broken = entry(&[(PAYLOAD_FIELD, b"{")]);
+        assert!(decode_entry::<Json, serde_json::Value>(&broken).is_err());
+    }
+
+    #[test]
+    fn options() {
+        let opts = StreamOptions::new().count(0).dead_letter(Some("jobs:dead"));
+        assert_eq!(opts.count, 1);
+        assert_eq!(opts.dead_letter.as_deref(), Some("jobs:dead"));
+        assert_eq!(StreamOptions::new().dead_letter, None);
+    }
+}
@@ -0,0 +1,435 @@
+use super::*;
+use crate::utils::BusinessError;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// 缓存值编解码
+pub trait Codec: Send + Sync + 'static {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> CacheResult<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> CacheResult<T>;
+}
+
+/// json 编码 (默认) 可读性好, 便于其他语言共享缓存
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> CacheResult<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> CacheResult<T> {
+        serde_json::from_slice(bytes).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+}
+
+/// bincode 编码 体积小速度快, 结构体字段变更后旧缓存无法解码
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> CacheResult<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> CacheResult<T> {
+        bincode::deserialize(bytes).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+}
+
+/// MessagePack 编码 结构体按字段名序列化, 兼容字段增减
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> CacheResult<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> CacheResult<T> {
+        rmp_serde::from_slice(bytes).map_err(|e| CacheError::Codec(e.to_string()))
+    }
+}
+
+/// 类型化缓存
+///
+/// 值经 codec 序列化后存储, key 自动加上前缀; ttl 为 `None` 时不过期
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::caches::Cache;
+/// use yn_util::dao::Dao;
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Site {
+///     name: String,
+///     icp: String,
+/// }
+///
+/// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+/// let cache = Cache::named("default")?.prefix("ynos").namespace("config");
+/// cache
+///     .set("site", &Site { name: "ynos".into(), icp: "".into() }, Some(Duration::from_secs(600)))
+///     .await?;
+/// let site: Option<Site> = cache.get("site").await?;
+///
+/// let site: Site = cache
+///     .get_or_set_with("site", Some(Duration::from_secs(600)), || async {
+///         let doc = Dao::new("YNOS", "config").find_one(bson::doc! {"key": "site"}).await?;
+///         let name = doc.and_then(|d| d.get_str("name").ok().map(|s| s.to_owned()));
+///         Ok(Site { name: name.unwrap_or_default(), icp: "".into() })
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Cache<C: Codec = Json> {
+    backend: AsyncCache,
+    prefix: String,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C: Codec> Clone for Cache<C> {
+    fn clone(&self) -> Self {
+        Cache {
+            backend: self.backend.clone(),
+            prefix: self.prefix.clone(),
+            codec: PhantomData,
+        }
+    }
+}
+
+impl Cache<Json> {
+    pub fn new(backend: AsyncCache) -> Self {
+        Cache {
+            backend,
+            prefix: "".to_owned(),
+            codec: PhantomData,
+        }
+    }
+
+    /// 使用已注册的命名缓存
+    pub fn named(name: &str) -> CacheResult<Self> {
+        Ok(Cache::new(get(name)?))
+    }
+}
+
+impl<C: Codec> Cache<C> {
+    /// 更换编码 例如 `cache.codec::<MsgPack>()`
+    pub fn codec<D: Codec>(self) -> Cache<D> {
+        Cache {
+            backend: self.backend,
+            prefix: self.prefix,
+            codec: PhantomData,
+        }
+    }
+
+    /// 设置 key 前缀 (一般为应用名), 替换已有前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = match prefix {
+            "" => "".to_owned(),
+            _ => format!("{}:", prefix),
+        };
+        self
+    }
+
+    /// 在现有前缀后追加命名空间
+    pub fn namespace(mut self, namespace: &str) -> Self {
+        self.prefix = format!("{}{}:", self.prefix, namespace);
+        self
+    }
+
+    /// 底层连接 执行未封装的命令
+    pub fn backend(&self) -> &AsyncCache {
+        &self.backend
+    }
+
+    /// 加上前缀后的完整 key
+    pub fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn set_cmd(&self, key: &str, bytes: Vec<u8>, ttl: Option<Duration>) -> redis::Cmd {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(self.key(key)).arg(bytes);
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl_millis(ttl));
+        }
+        cmd
+    }
+
+    pub(crate) async fn get_raw(&self, key: &str) -> CacheResult<Option<Vec<u8>>> {
+        self.backend
+            .query(redis::cmd("GET").arg(self.key(key)))
+            .await
+    }
+
+    pub(crate) async fn set_raw(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> CacheResult<()> {
+        self.backend.query(&self.set_cmd(key, bytes, ttl)).await
+    }
+
+    /// 读取
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> CacheResult<Option<T>> {
+        let bytes = self.get_raw(key).await?;
+        bytes.map(|b| C::decode(&b)).transpose()
+    }
+
+    /// 写入
+    pub async fn set<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> CacheResult<()> {
+        self.set_raw(key, C::encode(value)?, ttl).await
+    }
+
+    /// 读取缓存, 未命中时执行 loader 并写入
+    ///
+    /// 缓存故障按降级模式处理 (见 [`set_fallback`]), 开启时直接回源
+    pub async fn get_or_set_with<T, F, Fut>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        loader: F,
+    ) -> Result<T, BusinessError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, BusinessError>>,
+    {
+        if let Some(Some(v)) = degrade(self.get(key).await)? {
+            return Ok(v);
+        }
+        let value = loader().await?;
+        degrade(self.set(key, &value, ttl).await)?;
+        Ok(value)
+    }
+
+    /// 删除 返回 key 是否存在
+    pub async fn delete(&self, key: &str) -> CacheResult<bool> {
+        let n: i64 = self
+            .backend
+            .query(redis::cmd("DEL").arg(self.key(key)))
+            .await?;
+        Ok(n > 0)
+    }
+
+    /// 批量删除 返回删除的数量
+    pub async fn delete_many(&self, keys: &[&str]) -> CacheResult<i64> {
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        match &self.backend {
+            AsyncCache::Single(_) => {
+                let keys: Vec<String> = keys.iter().map(|k| self.key(k)).collect();
+                self.backend.query(redis::cmd("DEL").arg(keys)).await
+            }
+            // 集群中 key 可能分布在不同 slot, 逐个删除
+            AsyncCache::Cluster(_) => {
+                let mut total = 0;
+                for key in keys {
+                    total += self.delete(key).await? as i64;
+                }
+                Ok(total)
+            }
+        }
+    }
+
+    /// 是否存在
+    pub async fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.backend
+            .query(redis::cmd("EXISTS").arg(self.key(key)))
+            .await
+    }
+
+    /// 设置过期时间 key 不存在时返回 false
+    pub async fn expire(&self, key: &str, ttl: Duration) -> CacheResult<bool> {
+        self.backend
+            .query(
+                redis::cmd("PEXPIRE")
+                    .arg(self.key(key))
+                    .arg(ttl_millis(ttl)),
+            )
+            .await
+    }
+
+    /// 剩余过期时间 key 不存在或未设置过期时返回 `None`
+    pub async fn ttl(&self, key: &str) -> CacheResult<Option<Duration>> {
+        let ms: i64 = self
+            .backend
+            .query(redis::cmd("PTTL").arg(self.key(key)))
+            .await?;
+        Ok(match ms {
+            ms if ms >= 0 => Some(Duration::from_millis(ms as u64)),
+            _ => None,
+        })
+    }
+
+    /// 批量读取 结果与 keys 顺序一致
+    pub async fn mget<T: DeserializeOwned>(&self, keys: &[&str]) -> CacheResult<Vec<Option<T>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let values: Vec<Option<Vec<u8>>> = match &self.backend {
+            AsyncCache::Single(_) => {
+                let keys: Vec<String> = keys.iter().map(|k| self.key(k)).collect();
+                self.backend.query(redis::cmd("MGET").arg(keys)).await?
+            }
+            AsyncCache::Cluster(_) => {
+                let mut values = vec![];
+                for key in keys {
+                    values.push(
+                        self.backend
+                            .query(redis::cmd("GET").arg(self.key(key)))
+                            .await?,
+                    );
+                }
+                values
+            }
+        };
+        values
+            .into_iter()
+            .map(|v| v.map(|b| C::decode(&b)).transpose())
+            .collect()
+    }
+
+    /// 批量写入 单节点模式下在一个事务中完成
+    pub async fn mset<T: Serialize>(
+        &self,
+        items: &[(&str, T)],
+        ttl: Option<Duration>,
+    ) -> CacheResult<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        match &self.backend {
+            AsyncCache::Single(_) => {
+                let mut pipe = redis::pipe();
+                pipe.atomic();
+                for (key, value) in items {
+                    pipe.add_command(self.set_cmd(key, C::encode(value)?, ttl))
+                        .ignore();
+                }
+                self.backend.query_pipe(&pipe).await
+            }
+            AsyncCache::Cluster(_) => {
+                for (key, value) in items {
+                    self.set(key, value, ttl).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// redis 过期时间最小 1 毫秒
+fn ttl_millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Site {
+        name: String,
+        visits: u64,
+        tags: Vec<String>,
+    }
+
+    fn site() -> Site {
+        Site {
+            name: "ynos".to_owned(),
+            visits: 42,
+            tags: vec!["a".to_owned()],
+        }
+    }
+
+    fn roundtrip<C: Codec>() {
+        let bytes = C::encode(&site()).unwrap();
+        assert_eq!(C::decode::<Site>(&bytes).unwrap(), site());
+        assert!(matches!(
+            C::decode::<Site>(b"\xff\x00"),
+            Err(CacheError::Codec(_))
+        ));
+    }
+
+    fn cache() -> Cache {
+        let client = Client::open("redis://127.0.0.1/").unwrap();
+        Cache::new(AsyncCache::Single(Arc::new(SinglePool::new(
+            client,
+            PoolConfig::new(),
+        ))))
+    }
+
+    #[test]
+    fn json_codec() {
+        roundtrip::<Json>();
+        assert_eq!(
+            Json::encode(&site()).unwrap(),
+            br#"{"name":"ynos","visits":42,"tags":["a"]}"#.to_vec()
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_codec() {
+        roundtrip::<Bincode>();
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_codec() {
+        roundtrip::<MsgPack>();
+        // 按字段名编码 新增字段不影响旧缓存
+        #[derive(Deserialize)]
+        struct Partial {
+            name: String,
+        }
+        let bytes = MsgPack::encode(&site()).unwrap();
+        assert_eq!(MsgPack::decode::<Partial>(&bytes).unwrap().name, "ynos");
+    }
+
+    #[test]
+    fn key_prefix_and_namespace() {
+        let cache = cache();
+        assert_eq!(cache.key("k"), "k");
+        let cache = cache.prefix("app").namespace("dao").namespace("member");
+        assert_eq!(cache.key("id:1"), "app:dao:member:id:1");
+        // prefix 替换已有前缀
+        assert_eq!(cache.prefix("other").key("k"), "other:k");
+        assert_eq!(self::cache().prefix("app").prefix("").key("k"), "k");
+    }
+
+    #[test]
+    fn set_command_ttl() {
+        let cache = cache().prefix("app");
+        let cmd = cache.set_cmd("k", b"v".to_vec(), Some(Duration::from_micros(10)));
+        let expected = redis::cmd("SET")
+            .arg("app:k")
+            .arg(b"v".to_vec())
+            .arg("PX")
+            .arg(1)
+            .get_packed_command();
+        assert_eq!(cmd.get_packed_command(), expected);
+        let cmd = cache.set_cmd("k", b"v".to_vec(), None);
+        let expected = redis::cmd("SET")
+            .arg("app:k")
+            .arg(b"v".to_vec())
+            .get_packed_command();
+        assert_eq!(cmd.get_packed_command(), expected);
+    }
+}
@@ -1,19 +1,32 @@
 use super::*;
+use crate::normalize::Normalizer;
 use crate::utils::*;
-use bson::{oid::ObjectId, Document};
+use bson::{oid::ObjectId, Bson, Document};
 use mongodb::{
     bson::doc,
     options::{
-        ClientOptions, CountOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+        AggregateOptions, ClientOptions, CountOptions, DistinctOptions,
+        EstimatedDocumentCountOptions, FindOneAndUpdateOptions, FindOneOptions, FindOptions,
         ReturnDocument,
     },
     Client, Collection, Database,
 };
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+mod cached;
+mod export;
+mod import;
+mod search;
+mod tree;
+pub use cached::*;
+pub use export::*;
+pub use import::*;
+pub use search::*;
+pub use tree::*;
+
 lazy_static! {
     // 单个数据库
     static ref DB: Mutex<Option<Database>> = Mutex::new(None);
@@ -58,6 +71,18 @@ pub async fn init_dbs(uri: &str) {
     }
 }
 
+/// 获取数据库
+pub fn database(db_name: &str) -> Database {
+    let db = match DBS.get(db_name) {
+        Some(name) => name.lock().unwrap(),
+        None => {
+            info!("{:?} 数据库连接失败~ 已连接 YNOS 数据库", db_name);
+            DBS.get("YNOS").unwrap().lock().unwrap()
+        }
+    };
+    (*db).as_ref().unwrap().clone()
+}
+
 pub fn collection(db_name: &str, name: &str) -> Collection {
     // let db = DBS.get(db_name).unwrap().lock().unwrap();
     let db = match DBS.get(db_name) {
@@ -70,14 +95,74 @@ pub fn collection(db_name: &str, name: &str) -> Collection {
     (*db).as_ref().unwrap().collection(name)
 }
 
+/// 读取数值 (聚合结果可能为 Int32 / Int64 / Double) 其他类型返回 0
+pub(crate) fn bson_i64(value: Option<&Bson>) -> i64 {
+    match value {
+        Some(Bson::Int32(v)) => *v as i64,
+        Some(Bson::Int64(v)) => *v,
+        Some(Bson::Double(v)) => *v as i64,
+        _ => 0,
+    }
+}
+
+/// 分组计数结果
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GroupCount<T> {
+    /// 字段值
+    pub value: T,
+    /// 数量
+    pub count: i64,
+}
+
+impl<T: DeserializeOwned> GroupCount<T> {
+    /// 分组计数的聚合管道 按数量倒序, 数量相同时按字段值排序
+    fn pipeline(field: &str, filter: Option<Document>) -> Vec<Document> {
+        vec![
+            doc! { "$match": filter.unwrap_or_default() },
+            doc! { "$group": { "_id": format!("${}", field), "count": { "$sum": 1 } } },
+            doc! { "$sort": { "count": -1, "_id": 1 } },
+        ]
+    }
+
+    fn from_document(mut d: Document) -> Result<Self, BusinessError> {
+        let count = bson_i64(d.get("count"));
+        let value = bson::from_bson(d.remove("_id").unwrap_or(Bson::Null))
+            .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+        Ok(GroupCount { value, count })
+    }
+}
+
 pub struct Dao {
     pub coll: Collection,
+    /// 读取结果的规范化方式
+    pub normalizer: Normalizer,
+    /// 读缓存 见 [`Dao::with_cache`]
+    pub cache: Option<DaoCache>,
 }
 
 impl Dao {
     pub fn new(db_name: &str, name: &str) -> Self {
-        let coll = collection(db_name, name);
-        Dao { coll }
+        Dao::from_collection(collection(db_name, name))
+    }
+
+    /// 使用已有的集合创建 其余配置使用默认值
+    pub fn from_collection(coll: Collection) -> Self {
+        Dao {
+            coll,
+            normalizer: Normalizer::default(),
+            cache: None,
+        }
+    }
+
+    fn namespace_key(&self) -> String {
+        let ns = self.coll.namespace();
+        format!("{}.{}", ns.db, ns.coll)
+    }
+
+    /// 自定义读取结果的规范化方式 (日期格式 / Int64 处理)
+    pub fn with_normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
     }
 
     /// 保存
@@ -98,6 +183,7 @@ impl Dao {
             .inserted_id
             .as_object_id()
             .expect("Retrieved _id should have been of type ObjectId");
+        self.after_write(&[]).await;
         Ok(oid.to_owned())
     }
 
@@ -124,13 +210,20 @@ impl Dao {
         // ];
         let ret = self.coll.insert_many(docs, None).await;
         match ret {
-            Ok(value) => Ok(value),
-            Err(e) => return Err(BusinessError::InternalError { source: anyhow!(e) }),
+            Ok(value) => {
+                self.after_write(&[]).await;
+                Ok(value)
+            }
+            Err(e) => Err(BusinessError::InternalError { source: anyhow!(e) }),
         }
     }
 
     /// 根据id 查询一条
     pub async fn find_by_id(&self, id: ObjectId) -> Result<Option<Document>, BusinessError> {
+        let key = id_key(&id);
+        if let Some(d) = self.cache_read(&key).await? {
+            return Ok(Some(self.normalizer.normalize(d)));
+        }
         let filter = doc! {"_id": id};
         let mut opt = FindOneOptions::default();
         opt.max_time = Some(Duration::from_secs(3));
@@ -141,8 +234,8 @@ impl Dao {
                 // let data: T = bson::from_document(d)
                 //     .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })
                 //     .unwrap();
-                let data = document_handle_id(d, None).unwrap();
-                Ok(Some(data))
+                self.cache_write(&key, &d, false).await?;
+                Ok(Some(self.normalizer.normalize(d)))
             }
             None => Ok(None),
         }
@@ -150,14 +243,18 @@ impl Dao {
 
     /// 根据条件查询一条
     pub async fn find_one(&self, filter: Document) -> Result<Option<Document>, BusinessError> {
+        let key = query_key(&filter);
+        if let Some(d) = self.cache_read(&key).await? {
+            return Ok(Some(self.normalizer.normalize(d)));
+        }
         let mut opt = FindOneOptions::default();
         opt.max_time = Some(Duration::from_secs(3));
         let data = self.coll.find_one(filter, opt).await.unwrap();
 
         match data {
             Some(d) => {
-                let data = document_handle_id(d, None).unwrap();
-                Ok(Some(data))
+                self.cache_write(&key, &d, true).await?;
+                Ok(Some(self.normalizer.normalize(d)))
             }
             None => Ok(None),
         }
@@ -165,6 +262,7 @@ impl Dao {
 
     /// 查询
     /// oid_type - objectid类型
+    #[allow(clippy::too_many_arguments)]
     pub async fn find(
         &self,
         filter: Document,
@@ -209,7 +307,7 @@ impl Dao {
 
         let hoids = vec!["_id"];
 
-        let hoids = [hoids, oid_type.unwrap_or_else(|| vec![])].concat();
+        let hoids = [hoids, oid_type.unwrap_or_default()].concat();
 
         for k in keys.into_iter() {
             if !hoids.contains(&k.as_str()) {
@@ -231,21 +329,15 @@ impl Dao {
                 d.insert(k, oid);
             }
         }
-        if list.len() > 0 {
+        if !list.is_empty() {
             d.insert("$and", bson::Bson::Array(list));
         }
         info!("d = {:?}", d);
         let mut cursor = self.coll.find(Some(d), opt).await.unwrap();
-        let list = cursor.as_vec(true).await;
+        let list = cursor.as_normalized_vec(&self.normalizer).await;
         match list {
-            Ok(list) => {
-                let mut v = vec![];
-                for item in list {
-                    v.push(document_handle_id(item, None).unwrap())
-                }
-                Ok(v)
-            }
-            Err(e) => Err(BusinessError::InternalError { source: anyhow!(e) })?,
+            Ok(list) => Ok(list),
+            Err(e) => Err(BusinessError::InternalError { source: anyhow!(e) }),
         }
     }
 
@@ -276,18 +368,74 @@ impl Dao {
                 d.insert("_id", oid);
             }
         }
-        if list.len() > 0 {
+        if !list.is_empty() {
             d.insert("$and", bson::Bson::Array(list));
         }
         let count = self.coll.count_documents(Some(d), opt).await;
         match count {
             Ok(count) => Ok(count),
-            Err(e) => {
-                return Err(BusinessError::InternalError { source: anyhow!(e) })?;
+            Err(e) => Err(BusinessError::InternalError { source: anyhow!(e) }),
+        }
+    }
+
+    /// 集合总数 (根据集合元数据估算, 不扫描文档)
+    pub async fn estimated_count(&self) -> Result<i64, BusinessError> {
+        let mut opt = EstimatedDocumentCountOptions::default();
+        opt.max_time = Some(Duration::from_secs(3));
+        Ok(self.coll.estimated_document_count(opt).await?)
+    }
+
+    /// 查询总数 无过滤条件时使用估算总数
+    pub async fn total(&self, filter: Option<Document>) -> Result<i64, BusinessError> {
+        match filter {
+            Some(filter) if !filter.is_empty() => {
+                let mut opt = CountOptions::default();
+                opt.max_time = Some(Duration::from_secs(3));
+                Ok(self.coll.count_documents(filter, opt).await?)
             }
+            _ => self.estimated_count().await,
         }
     }
 
+    /// 字段去重值 (用于下拉筛选)
+    pub async fn distinct<T>(
+        &self,
+        field: &str,
+        filter: Option<Document>,
+    ) -> Result<Vec<T>, BusinessError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut opt = DistinctOptions::default();
+        opt.max_time = Some(Duration::from_secs(3));
+        let list = self.coll.distinct(field, filter, opt).await?;
+        list.into_iter()
+            .map(|v| {
+                bson::from_bson(self.normalizer.normalize_bson(v))
+                    .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })
+            })
+            .collect()
+    }
+
+    /// 按字段分组计数 按数量倒序
+    pub async fn count_by<T>(
+        &self,
+        field: &str,
+        filter: Option<Document>,
+    ) -> Result<Vec<GroupCount<T>>, BusinessError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut opt = AggregateOptions::default();
+        opt.max_time = Some(Duration::from_secs(3));
+        let mut cursor = self
+            .coll
+            .aggregate(GroupCount::<T>::pipeline(field, filter), opt)
+            .await?;
+        let list = cursor.as_normalized_vec(&self.normalizer).await?;
+        list.into_iter().map(GroupCount::from_document).collect()
+    }
+
     /// 更新数据
     pub async fn update(&self, data: Document) -> Result<Option<Document>, BusinessError> {
         // let oid = match data.get_object_id("_id") {
@@ -296,7 +444,7 @@ impl Dao {
         // };
         let oid = data.get("_id").unwrap().as_str().unwrap();
         let oid = bson::oid::ObjectId::with_string(oid).unwrap();
-        let filter = doc! {"_id":oid};
+        let filter = doc! {"_id":oid.clone()};
 
         let mut doc = data;
         doc.insert("update_time", date_time::to_string());
@@ -322,6 +470,7 @@ impl Dao {
                 })
             }
         };
+        self.after_write(&[oid]).await;
 
         match data {
             Some(d) => {
@@ -329,8 +478,7 @@ impl Dao {
                 //     .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })
                 //     .unwrap();
                 // Ok(Some(data))
-                let data = document_handle_id(d, None).unwrap();
-                Ok(Some(data))
+                Ok(Some(self.normalizer.normalize(d)))
             }
             None => Ok(None),
         }
@@ -341,7 +489,7 @@ impl Dao {
         let arr: Vec<&str> = ids.rsplit(",").collect();
         let mut remids: Vec<ObjectId> = Vec::new();
         for id in arr.iter() {
-            let oid = match ObjectId::with_string(*id) {
+            let oid = match ObjectId::with_string(id) {
                 Ok(oid) => oid,
                 Err(_) => {
                     return Err(BusinessError::InternalError {
@@ -354,24 +502,53 @@ impl Dao {
             }
         }
         let mut doc: Document = doc! {};
-        doc.insert("$in", remids);
+        doc.insert("$in", remids.clone());
         let d = doc! {"_id":doc};
         let result = self.coll.delete_many(d, None).await;
         match result {
             Ok(res) => {
                 if res.deleted_count > 0 {
+                    self.after_write(&remids).await;
                     Ok(res.deleted_count)
                 } else {
-                    return Err(BusinessError::InternalError {
+                    Err(BusinessError::InternalError {
                         source: anyhow!("删除失败,请提供正确的id"),
-                    });
+                    })
                 }
             }
-            Err(_) => {
-                return Err(BusinessError::InternalError {
-                    source: anyhow!("删除失败"),
-                })?
-            }
+            Err(_) => Err(BusinessError::InternalError {
+                source: anyhow!("删除失败"),
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_count_pipeline() {
+        let pipeline = GroupCount::<String>::pipeline("status", Some(doc! { "deleted": 0 }));
+        assert_eq!(pipeline[0], doc! { "$match": { "deleted": 0 } });
+        assert_eq!(
+            pipeline[1],
+            doc! { "$group": { "_id": "$status", "count": { "$sum": 1 } } }
+        );
+        let pipeline = GroupCount::<String>::pipeline("status", None);
+        assert_eq!(pipeline[0], doc! { "$match": {} });
+    }
+
+    #[test]
+    fn group_count_from_document() {
+        let row = doc! { "_id": "paid", "count": 3 };
+        let group = GroupCount::<String>::from_document(row).unwrap();
+        assert_eq!((group.value.as_str(), group.count), ("paid", 3));
+        // 分组字段不存在的文档 _id 为 null
+        let row = doc! { "_id": Bson::Null, "count": 5i64 };
+        let group = GroupCount::<Option<i32>>::from_document(row).unwrap();
+        assert_eq!((group.value, group.count), (None, 5));
+        let row = doc! { "_id": "x", "count": 1 };
+        assert!(GroupCount::<i32>::from_document(row).is_err());
+    }
+}
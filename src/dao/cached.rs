@@ -0,0 +1,191 @@
+use super::*;
+use crate::caches::{degrade, Cache, Tags};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+lazy_static! {
+    // 读缓存命中统计  key: 库.集合
+    static ref STATS: Mutex<HashMap<String, Arc<Counters>>> = Mutex::new(HashMap::new());
+}
+
+/// 条件查询缓存的标签, 任意写操作后整体失效
+const QUERY_TAG: &str = "query";
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// 读缓存命中统计
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// 命中率 没有读取时为 0
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// Dao 读缓存配置
+#[derive(Clone)]
+pub struct DaoCache {
+    /// 已加上 `dao:库.集合:` 前缀的缓存
+    pub cache: Cache,
+    /// 缓存时长
+    pub ttl: Duration,
+    /// 条件查询缓存的标签 位于 `dao:库.集合:tag:` 下
+    pub tags: Tags,
+}
+
+impl Dao {
+    /// 开启读缓存
+    ///
+    /// `find_by_id` / `find_one` 优先读缓存, 未命中时查询数据库并写入缓存;
+    /// `save` / `save_many` / `update` / `remove` / `import_csv` 等写操作后自动失效.
+    /// 直接通过 `coll` 写入时需自行调用 [`Dao::invalidate`]
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use yn_util::caches::Cache;
+    /// use yn_util::dao::Dao;
+    ///
+    /// # async fn run(id: bson::oid::ObjectId) -> Result<(), yn_util::utils::BusinessError> {
+    /// let cache = Cache::named("default")?.prefix("ynos");
+    /// let dao = Dao::new("YNOS", "member").with_cache(cache, Duration::from_secs(300));
+    /// let member = dao.find_by_id(id).await?;
+    /// let stats = dao.cache_stats();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_cache(mut self, cache: Cache, ttl: Duration) -> Self {
+        let cache = cache.namespace(&format!("dao:{}", self.namespace_key()));
+        let tags = Tags::for_cache(&cache);
+        self.cache = Some(DaoCache { cache, ttl, tags });
+        self
+    }
+
+    /// 当前集合的读缓存命中统计
+    pub fn cache_stats(&self) -> CacheStats {
+        let counters = self.counters();
+        CacheStats {
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn counters(&self) -> Arc<Counters> {
+        STATS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(self.namespace_key())
+            .or_default()
+            .clone()
+    }
+
+    /// 读取缓存的原始文档 未开启缓存时返回 None
+    pub(crate) async fn cache_read(&self, key: &str) -> Result<Option<Document>, BusinessError> {
+        let dc = match &self.cache {
+            Some(dc) => dc,
+            None => return Ok(None),
+        };
+        let value = degrade(dc.cache.get::<serde_json::Value>(key).await)?.flatten();
+        let doc = value.and_then(|v| match Bson::try_from(v) {
+            Ok(Bson::Document(d)) => Some(d),
+            _ => None,
+        });
+        let counters = self.counters();
+        match doc {
+            Some(_) => counters.hits.fetch_add(1, Ordering::Relaxed),
+            None => counters.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        Ok(doc)
+    }
+
+    /// 写入缓存 tagged 为 true 时记入条件查询集合, 任意写操作后失效
+    pub(crate) async fn cache_write(
+        &self,
+        key: &str,
+        doc: &Document,
+        tagged: bool,
+    ) -> Result<(), BusinessError> {
+        let dc = match &self.cache {
+            Some(dc) => dc,
+            None => return Ok(()),
+        };
+        // 规范扩展 json 保留 ObjectId / 日期 / Int64 等类型, 读取时再按 normalizer 转换
+        let value = Bson::Document(doc.clone()).into_canonical_extjson();
+        if tagged {
+            let tagging = dc
+                .tags
+                .tag(&dc.cache.key(key), &[QUERY_TAG], Some(dc.ttl))
+                .await;
+            degrade(tagging)?;
+        }
+        degrade(dc.cache.set(key, &value, Some(dc.ttl)).await)?;
+        Ok(())
+    }
+
+    /// 失效指定 id 的缓存及全部条件查询缓存, 同时清除该集合的树缓存
+    pub async fn invalidate(&self, ids: &[ObjectId]) -> Result<(), BusinessError> {
+        invalidate_trees(&self.namespace_key());
+        let dc = match &self.cache {
+            Some(dc) => dc,
+            None => return Ok(()),
+        };
+        degrade(dc.tags.invalidate(QUERY_TAG).await)?;
+        let keys: Vec<String> = ids.iter().map(id_key).collect();
+        let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+        degrade(dc.cache.delete_many(&keys).await)?;
+        Ok(())
+    }
+
+    /// 写操作 (已提交) 之后失效缓存
+    ///
+    /// 失效失败只记录日志, 不影响写操作的结果, 避免调用方重试造成重复写入;
+    /// 残留的缓存在 ttl 后过期
+    pub(crate) async fn after_write(&self, ids: &[ObjectId]) {
+        if let Err(e) = self.invalidate(ids).await {
+            log::error!("{} 缓存失效失败, {}", self.namespace_key(), e);
+        }
+    }
+}
+
+pub(crate) fn id_key(id: &ObjectId) -> String {
+    format!("id:{}", id.to_hex())
+}
+
+pub(crate) fn query_key(filter: &Document) -> String {
+    format!("one:{:x}", md5::compute(filter.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_keys() {
+        let id = ObjectId::with_string("5fa0a0a0a0a0a0a0a0a0a0a0").unwrap();
+        assert_eq!(id_key(&id), "id:5fa0a0a0a0a0a0a0a0a0a0a0");
+        // 相同条件得到相同 key, 字段顺序不同视为不同条件
+        let a = query_key(&doc! { "phone": "13800000000", "status": 1 });
+        assert_eq!(a, query_key(&doc! { "phone": "13800000000", "status": 1 }));
+        assert_ne!(a, query_key(&doc! { "status": 1, "phone": "13800000000" }));
+        assert!(a.starts_with("one:"));
+    }
+
+    #[test]
+    fn hit_rate() {
+        assert_eq!(CacheStats::default().hit_rate(), 0.0);
+        let stats = CacheStats { hits: 3, misses: 1 };
+        assert_eq!(stats.hit_rate(), 0.75);
+    }
+}
@@ -0,0 +1,425 @@
+use super::*;
+use actix_web::{web::Bytes, HttpResponse};
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use futures::stream::{self, Stream, StreamExt};
+use mongodb::Cursor;
+
+/// utf-8 BOM, Excel 打开 csv 时据此识别编码
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// 导出格式
+#[derive(Clone, Debug)]
+pub enum ExportFormat {
+    Csv(CsvOptions),
+    /// 每行一个 json 文档
+    JsonLines,
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv(_) => "text/csv; charset=utf-8",
+            ExportFormat::JsonLines => "application/x-ndjson; charset=utf-8",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv(_) => "csv",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// csv 导出配置
+///
+/// 未指定列时只使用第一条文档的字段 (嵌套文档展开为 `a.b` 形式的列),
+/// 之后的文档中新出现的字段不会导出; 文档结构不一致时应通过 [`CsvOptions::column`] 指定列
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    /// 导出列 (字段路径, 表头)
+    pub columns: Vec<(String, String)>,
+    /// 是否写入表头
+    pub header: bool,
+    /// 是否写入 utf-8 BOM
+    pub bom: bool,
+    /// 分隔符
+    pub delimiter: u8,
+    /// 以 `=` `+` `-` `@` 等开头的文本前加 `'`, 防止 Excel 按公式执行 默认开启
+    pub escape_formulas: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            columns: vec![],
+            header: true,
+            bom: true,
+            delimiter: b',',
+            escape_formulas: true,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        CsvOptions::default()
+    }
+
+    /// 添加导出列 字段路径支持 `a.b.0.c`
+    pub fn column(mut self, field: &str, header: &str) -> Self {
+        self.columns.push((field.to_owned(), header.to_owned()));
+        self
+    }
+
+    /// 是否写入表头
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// 是否写入 utf-8 BOM
+    pub fn bom(mut self, bom: bool) -> Self {
+        self.bom = bom;
+        self
+    }
+
+    /// 设置分隔符
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// 是否转义以 `=` `+` `-` `@` 等开头的文本 默认开启
+    pub fn escape_formulas(mut self, escape: bool) -> Self {
+        self.escape_formulas = escape;
+        self
+    }
+}
+
+/// 逐行编码, 首行前写入 BOM 与表头
+struct RowEncoder {
+    format: ExportFormat,
+    normalizer: Normalizer,
+    started: bool,
+}
+
+impl RowEncoder {
+    fn encode(&mut self, doc: Document) -> Result<Vec<u8>, BusinessError> {
+        let doc = self.normalizer.normalize(doc);
+        let mut buf = self.prelude(Some(&doc))?;
+        match &self.format {
+            ExportFormat::Csv(opts) => {
+                let cells: Vec<String> = opts
+                    .columns
+                    .iter()
+                    .map(|(field, _)| cell(lookup(&doc, field), opts.escape_formulas))
+                    .collect();
+                buf.extend(csv_record(opts.delimiter, &cells)?);
+            }
+            ExportFormat::JsonLines => {
+                let value = Bson::Document(doc).into_relaxed_extjson();
+                serde_json::to_writer(&mut buf, &value)
+                    .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+                buf.push(b'\n');
+            }
+        }
+        Ok(buf)
+    }
+
+    /// BOM 与表头 只输出一次; 没有数据时也会输出
+    fn prelude(&mut self, first: Option<&Document>) -> Result<Vec<u8>, BusinessError> {
+        let mut buf = vec![];
+        if self.started {
+            return Ok(buf);
+        }
+        self.started = true;
+        if let ExportFormat::Csv(opts) = &mut self.format {
+            if opts.columns.is_empty() {
+                if let Some(doc) = first {
+                    opts.columns = flatten_keys(doc, "")
+                        .into_iter()
+                        .map(|k| (k.clone(), k))
+                        .collect();
+                }
+            }
+            if opts.bom {
+                buf.extend_from_slice(UTF8_BOM);
+            }
+            if opts.header && !opts.columns.is_empty() {
+                let headers: Vec<String> = opts.columns.iter().map(|(_, h)| h.clone()).collect();
+                buf.extend(csv_record(opts.delimiter, &headers)?);
+            }
+        }
+        Ok(buf)
+    }
+}
+
+fn csv_record(delimiter: u8, cells: &[String]) -> Result<Vec<u8>, BusinessError> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+    writer
+        .write_record(cells)
+        .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+    writer
+        .into_inner()
+        .map_err(|e| BusinessError::InternalError {
+            source: anyhow!(e.to_string()),
+        })
+}
+
+/// 嵌套文档展开为 `a.b` 形式的字段名, 数组保持为一列
+fn flatten_keys(doc: &Document, prefix: &str) -> Vec<String> {
+    let mut keys = vec![];
+    for (k, v) in doc.iter() {
+        let key = format!("{}{}", prefix, k);
+        match v {
+            Bson::Document(d) if !d.is_empty() => {
+                keys.extend(flatten_keys(d, &format!("{}.", key)));
+            }
+            _ => keys.push(key),
+        }
+    }
+    keys
+}
+
+/// 按字段路径取值 支持数组下标
+fn lookup<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut value = doc.get(parts.next()?)?;
+    for part in parts {
+        value = match value {
+            Bson::Document(d) => d.get(part)?,
+            Bson::Array(list) => list.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn cell(value: Option<&Bson>, escape_formulas: bool) -> String {
+    match value {
+        None | Some(Bson::Null) => "".to_owned(),
+        Some(Bson::String(s)) if escape_formulas && is_formula(s) => format!("'{}", s),
+        Some(Bson::String(s)) => s.to_owned(),
+        Some(Bson::Int32(v)) => v.to_string(),
+        Some(Bson::Int64(v)) => v.to_string(),
+        Some(Bson::Double(v)) => v.to_string(),
+        Some(Bson::Boolean(v)) => v.to_string(),
+        Some(v) => v.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+/// 以这些字符开头的单元格会被 Excel 当作公式 (OWASP CSV Injection)
+fn is_formula(text: &str) -> bool {
+    matches!(
+        text.chars().next(),
+        Some('=') | Some('+') | Some('-') | Some('@') | Some('\t') | Some('\r')
+    )
+}
+
+/// 游标转为字节流 逐条编码, 内存占用与数据量无关
+fn encode_stream(
+    cursor: Cursor,
+    encoder: RowEncoder,
+) -> impl Stream<Item = Result<Bytes, BusinessError>> {
+    stream::unfold(
+        (cursor, encoder, false),
+        |(mut cursor, mut encoder, done)| async move {
+            if done {
+                return None;
+            }
+            let item = match cursor.next().await {
+                Some(Ok(doc)) => encoder.encode(doc),
+                Some(Err(e)) => Err(BusinessError::from(e)),
+                None => {
+                    let prelude = encoder.prelude(None);
+                    return match prelude {
+                        Ok(buf) if buf.is_empty() => None,
+                        item => Some((item.map(Bytes::from), (cursor, encoder, true))),
+                    };
+                }
+            };
+            let done = item.is_err();
+            Some((item.map(Bytes::from), (cursor, encoder, done)))
+        },
+    )
+}
+
+impl Dao {
+    /// 按条件导出为字节流
+    pub async fn export_stream(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        format: ExportFormat,
+    ) -> Result<impl Stream<Item = Result<Bytes, BusinessError>>, BusinessError> {
+        let mut opt = FindOptions::default();
+        opt.sort = sort;
+        opt.no_cursor_timeout = Some(true);
+        let cursor = self.coll.find(filter, opt).await?;
+        let encoder = RowEncoder {
+            format,
+            normalizer: self.normalizer.clone(),
+            started: false,
+        };
+        Ok(encode_stream(cursor, encoder))
+    }
+
+    /// 按条件导出写入 writer, 返回写入的字节数
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use bson::doc;
+    /// use yn_util::dao::{CsvOptions, Dao, ExportFormat};
+    ///
+    /// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+    /// let dao = Dao::new("YNOS", "order");
+    /// let format = ExportFormat::Csv(
+    ///     CsvOptions::new()
+    ///         .column("order_no", "订单号")
+    ///         .column("buyer.name", "买家"),
+    /// );
+    /// let mut out = futures::io::Cursor::new(vec![]);
+    /// dao.export(Some(doc! {"status": 1}), None, format, &mut out).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export<W>(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> Result<u64, BusinessError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let stream = self.export_stream(filter, sort, format).await?;
+        futures::pin_mut!(stream);
+        let mut total = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            writer
+                .write_all(&chunk)
+                .await
+                .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+            total += chunk.len() as u64;
+        }
+        writer
+            .flush()
+            .await
+            .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+        Ok(total)
+    }
+
+    /// 导出为 actix 流式下载响应
+    ///
+    /// file_name - 下载文件名 (不含路径), 不支持 `filename*` 的客户端使用 `export.csv` / `export.jsonl`
+    pub async fn export_response(
+        &self,
+        filter: Option<Document>,
+        sort: Option<Document>,
+        format: ExportFormat,
+        file_name: &str,
+    ) -> Result<HttpResponse, BusinessError> {
+        let content_type = format.content_type();
+        let extension = format.extension();
+        let stream = self.export_stream(filter, sort, format).await?;
+        Ok(HttpResponse::Ok()
+            .content_type(content_type)
+            .header(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"export.{}\"; filename*=UTF-8''{}",
+                    extension,
+                    percent_encode(file_name)
+                ),
+            )
+            .streaming(Box::pin(stream)))
+    }
+}
+
+/// RFC 5987 文件名编码
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoder(format: ExportFormat) -> RowEncoder {
+        RowEncoder {
+            format,
+            normalizer: Normalizer::default(),
+            started: false,
+        }
+    }
+
+    #[test]
+    fn formula_cells_are_escaped() {
+        for text in &["=1+1", "+1", "-2", "@SUM(A1)", "\tx", "\rx"] {
+            let value = Bson::String(text.to_string());
+            assert_eq!(cell(Some(&value), true), format!("'{}", text));
+            assert_eq!(cell(Some(&value), false), *text);
+        }
+        assert_eq!(cell(Some(&Bson::String("a=b".into())), true), "a=b");
+        // 数值不是公式
+        assert_eq!(cell(Some(&Bson::Int32(-2)), true), "-2");
+        assert_eq!(cell(None, true), "");
+    }
+
+    #[test]
+    fn csv_rows() {
+        let opts = CsvOptions::new().bom(false);
+        let mut encoder = encoder(ExportFormat::Csv(opts));
+        let first = encoder
+            .encode(doc! { "name": "a,b", "buyer": { "name": "=cmd" }, "tags": [1, 2] })
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(first).unwrap(),
+            "name,buyer.name,tags\n\"a,b\",'=cmd,\"[1,2]\"\n"
+        );
+        // 列由第一条文档决定, 之后新增的字段不导出
+        let second = encoder.encode(doc! { "name": "c", "extra": 1 }).unwrap();
+        assert_eq!(String::from_utf8(second).unwrap(), "c,,\n");
+    }
+
+    #[test]
+    fn csv_prelude_without_rows() {
+        let opts = CsvOptions::new().column("a", "A").delimiter(b';');
+        let mut encoder = encoder(ExportFormat::Csv(opts));
+        let mut expected = UTF8_BOM.to_vec();
+        expected.extend_from_slice(b"A\n");
+        assert_eq!(encoder.prelude(None).unwrap(), expected);
+        assert!(encoder.prelude(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut encoder = encoder(ExportFormat::JsonLines);
+        let line = encoder.encode(doc! { "a": 1, "b": "=x" }).unwrap();
+        assert_eq!(String::from_utf8(line).unwrap(), "{\"a\":1,\"b\":\"=x\"}\n");
+    }
+
+    #[test]
+    fn lookup_paths() {
+        let doc = doc! { "a": { "b": [ { "c": 1 } ] } };
+        assert_eq!(lookup(&doc, "a.b.0.c"), Some(&Bson::Int32(1)));
+        assert_eq!(lookup(&doc, "a.b.1.c"), None);
+        assert_eq!(lookup(&doc, "a.x"), None);
+    }
+
+    #[test]
+    fn file_name_encoding() {
+        assert_eq!(percent_encode("订单 1.csv"), "%E8%AE%A2%E5%8D%95%201.csv");
+    }
+}
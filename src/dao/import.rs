@@ -0,0 +1,604 @@
+use super::*;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use mongodb::options::{FindOneOptions, UpdateOptions};
+use std::collections::HashSet;
+use std::io::Read;
+
+/// 校验函数 返回错误信息
+pub type RowValidator = Arc<dyn Fn(&Document) -> Result<(), String> + Send + Sync>;
+
+/// 导入字段类型
+#[derive(Clone, Debug)]
+pub enum FieldType {
+    String,
+    Int,
+    Float,
+    /// 支持 true/false 1/0 是/否
+    Bool,
+    /// 按格式解析为 BSON 日期 例如 `%Y-%m-%d %H:%M:%S`, 仅日期的格式按当天零点处理
+    Date(String),
+    ObjectId,
+}
+
+/// 表头与文档字段的映射
+#[derive(Clone, Debug)]
+pub struct ImportColumn {
+    /// 表头名称
+    pub header: String,
+    /// 文档字段
+    pub field: String,
+    /// 字段类型
+    pub field_type: FieldType,
+    /// 是否必填
+    pub required: bool,
+}
+
+impl ImportColumn {
+    pub fn new(header: &str, field: &str) -> Self {
+        ImportColumn {
+            header: header.to_owned(),
+            field: field.to_owned(),
+            field_type: FieldType::String,
+            required: false,
+        }
+    }
+
+    /// 设置字段类型
+    pub fn field_type(mut self, field_type: FieldType) -> Self {
+        self.field_type = field_type;
+        self
+    }
+
+    /// 设置为必填
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn coerce(&self, value: &str) -> Result<Bson, String> {
+        let err = |kind: &str| format!("{} 不是有效的{}: {}", self.header, kind, value);
+        match &self.field_type {
+            FieldType::String => Ok(Bson::String(value.to_owned())),
+            FieldType::Int => value
+                .parse::<i64>()
+                .map(|v| match v {
+                    v if v >= i32::MIN as i64 && v <= i32::MAX as i64 => Bson::Int32(v as i32),
+                    v => Bson::Int64(v),
+                })
+                .map_err(|_| err("整数")),
+            FieldType::Float => value
+                .parse::<f64>()
+                .map(Bson::Double)
+                .map_err(|_| err("数字")),
+            FieldType::Bool => match value.to_lowercase().as_str() {
+                "true" | "1" | "是" | "y" | "yes" => Ok(Bson::Boolean(true)),
+                "false" | "0" | "否" | "n" | "no" => Ok(Bson::Boolean(false)),
+                _ => Err(err("布尔值")),
+            },
+            FieldType::Date(format) => NaiveDateTime::parse_from_str(value, format)
+                .or_else(|_| NaiveDate::parse_from_str(value, format).map(|d| d.and_hms(0, 0, 0)))
+                .ok()
+                .and_then(|dt| Local.from_local_datetime(&dt).single())
+                .map(|dt| Bson::DateTime(dt.with_timezone(&Utc)))
+                .ok_or_else(|| err("日期")),
+            FieldType::ObjectId => ObjectId::with_string(value)
+                .map(Bson::ObjectId)
+                .map_err(|_| err("ObjectId")),
+        }
+    }
+}
+
+/// 导入配置
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::dao::{Dao, FieldType, ImportColumn, ImportOptions};
+///
+/// # async fn run(file: &[u8]) -> Result<(), yn_util::utils::BusinessError> {
+/// let opts = ImportOptions::new()
+///     .column(ImportColumn::new("手机号", "phone").required())
+///     .column(ImportColumn::new("姓名", "name"))
+///     .column(ImportColumn::new("年龄", "age").field_type(FieldType::Int))
+///     .key("phone")
+///     .validate(|doc| match doc.get_str("phone") {
+///         Ok(phone) if phone.len() == 11 => Ok(()),
+///         _ => Err("手机号格式错误".to_owned()),
+///     });
+/// let report = Dao::new("YNOS", "member").import_csv(file, &opts).await?;
+/// let resp = report.to_resp();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ImportOptions {
+    /// 列映射 未映射的列忽略
+    pub columns: Vec<ImportColumn>,
+    /// 自然键 用于去重与 upsert, 为空时全部新增; 行中缺少任一键时该行失败。
+    /// 并发导入同一集合时应在自然键上建立唯一索引 (upsert 冲突时该行失败)
+    pub keys: Vec<String>,
+    /// 数据已存在时 true.更新 / false.跳过
+    pub update_existing: bool,
+    /// 分隔符
+    pub delimiter: u8,
+    /// 行校验
+    pub validators: Vec<RowValidator>,
+    /// 只校验不写入
+    pub dry_run: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            columns: vec![],
+            keys: vec![],
+            update_existing: true,
+            delimiter: b',',
+            validators: vec![],
+            dry_run: false,
+        }
+    }
+}
+
+impl ImportOptions {
+    pub fn new() -> Self {
+        ImportOptions::default()
+    }
+
+    /// 添加列映射
+    pub fn column(mut self, column: ImportColumn) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// 添加自然键字段
+    pub fn key(mut self, field: &str) -> Self {
+        self.keys.push(field.to_owned());
+        self
+    }
+
+    /// 数据已存在时是否更新
+    pub fn update_existing(mut self, update: bool) -> Self {
+        self.update_existing = update;
+        self
+    }
+
+    /// 设置分隔符
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// 添加行校验
+    pub fn validate<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Document) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validators.push(Arc::new(f));
+        self
+    }
+
+    /// 只校验不写入
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// 行处理结果
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RowStatus {
+    Created,
+    Updated,
+    Skipped,
+    Failed,
+}
+
+/// 行报告
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RowResult {
+    /// 记录起始行号 (表头为第 1 行, 引号内换行的单元格占多行)
+    pub row: usize,
+    pub status: RowStatus,
+    /// 文档 id
+    pub id: Option<String>,
+    /// 跳过或失败原因
+    pub message: Option<String>,
+}
+
+/// 导入报告
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    pub total: usize,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<RowResult>,
+}
+
+impl ImportReport {
+    fn push(&mut self, row: usize, status: RowStatus, id: Option<String>, message: Option<String>) {
+        match status {
+            RowStatus::Created => self.created += 1,
+            RowStatus::Updated => self.updated += 1,
+            RowStatus::Skipped => self.skipped += 1,
+            RowStatus::Failed => self.failed += 1,
+        }
+        self.total += 1;
+        self.rows.push(RowResult {
+            row,
+            status,
+            id,
+            message,
+        });
+    }
+
+    /// 转为统一响应结构
+    pub fn to_resp(self) -> Resp<ImportReport> {
+        let message = format!(
+            "导入完成: 新增 {} 条, 更新 {} 条, 跳过 {} 条, 失败 {} 条",
+            self.created, self.updated, self.skipped, self.failed
+        );
+        let total = self.total as i64;
+        Resp::ok(Some(self), &message, None, None, Some(total))
+    }
+}
+
+impl Dao {
+    /// 导入 csv 表格 返回逐行报告
+    ///
+    /// 单行失败不会中断导入; 表头缺少必填列或文件无法解析时返回错误
+    pub async fn import_csv<R: Read>(
+        &self,
+        reader: R,
+        opts: &ImportOptions,
+    ) -> Result<ImportReport, BusinessError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(opts.delimiter)
+            .flexible(true)
+            .from_reader(reader);
+        let headers: Vec<String> = reader
+            .headers()
+            .map_err(|e| BusinessError::ArgumentError { source: anyhow!(e) })?
+            .iter()
+            .map(|h| h.trim_start_matches('\u{feff}').trim().to_owned())
+            .collect();
+        let columns = map_columns(&headers, opts)?;
+
+        let mut report = ImportReport::default();
+        let mut seen = HashSet::new();
+        let mut last_row = 1;
+        for record in reader.records() {
+            let (row, doc) = read_row(record, &columns, opts, last_row);
+            last_row = row;
+            let doc = match doc {
+                Ok(doc) => doc,
+                Err(msg) => {
+                    report.push(row, RowStatus::Failed, None, Some(msg));
+                    continue;
+                }
+            };
+            // 数据库错误 (例如唯一索引冲突) 只记为该行失败
+            match self.import_row(doc, opts, &mut seen).await {
+                Ok((status, id, message)) => report.push(row, status, id, message),
+                Err(e) => report.push(row, RowStatus::Failed, None, Some(e.to_string())),
+            }
+        }
+        if !opts.dry_run && report.created + report.updated > 0 {
+            let ids: Vec<ObjectId> = report
+                .rows
+                .iter()
+                .filter(|r| r.status == RowStatus::Updated)
+                .filter_map(|r| r.id.as_ref().and_then(|id| ObjectId::with_string(id).ok()))
+                .collect();
+            self.after_write(&ids).await;
+        }
+        Ok(report)
+    }
+
+    /// 写入单行 返回 (状态, 文档 id, 跳过原因)
+    async fn import_row(
+        &self,
+        doc: Document,
+        opts: &ImportOptions,
+        seen: &mut HashSet<String>,
+    ) -> Result<(RowStatus, Option<String>, Option<String>), BusinessError> {
+        if opts.keys.is_empty() {
+            let (status, id) = self.import_insert(doc, opts.dry_run).await?;
+            return Ok((status, id, None));
+        }
+        let filter = match key_filter(&doc, &opts.keys) {
+            Ok(filter) => filter,
+            Err(message) => return Ok((RowStatus::Failed, None, Some(message))),
+        };
+        if !seen.insert(filter.to_string()) {
+            let message = Some("与文件内前面的行重复".to_owned());
+            return Ok((RowStatus::Skipped, None, message));
+        }
+        if opts.dry_run {
+            return match self.coll.find_one(filter, None).await? {
+                Some(exist) if !opts.update_existing => {
+                    let id = exist.get_object_id("_id").ok().map(|id| id.to_hex());
+                    Ok((RowStatus::Skipped, id, Some("数据已存在".to_owned())))
+                }
+                Some(exist) => {
+                    let id = exist.get_object_id("_id").ok().map(|id| id.to_hex());
+                    Ok((RowStatus::Updated, id, None))
+                }
+                None => Ok((RowStatus::Created, None, None)),
+            };
+        }
+        // 单条 upsert 保证并发导入时不会重复新增
+        let options = UpdateOptions::builder().upsert(true).build();
+        let update = upsert_update(doc, opts.update_existing, &date_time::to_string());
+        let result = self
+            .coll
+            .update_one(filter.clone(), update, options)
+            .await?;
+        if let Some(id) = result.upserted_id {
+            let id = match id {
+                Bson::ObjectId(id) => id.to_hex(),
+                id => id.to_string(),
+            };
+            return Ok((RowStatus::Created, Some(id), None));
+        }
+        // 已存在的数据 读取 id 用于报告与缓存失效
+        let id = self
+            .coll
+            .find_one(
+                filter,
+                FindOneOptions::builder()
+                    .projection(doc! {"_id": 1})
+                    .build(),
+            )
+            .await?
+            .and_then(|exist| exist.get_object_id("_id").ok().map(|id| id.to_hex()));
+        match opts.update_existing {
+            true => Ok((RowStatus::Updated, id, None)),
+            false => Ok((RowStatus::Skipped, id, Some("数据已存在".to_owned()))),
+        }
+    }
+
+    async fn import_insert(
+        &self,
+        mut doc: Document,
+        dry_run: bool,
+    ) -> Result<(RowStatus, Option<String>), BusinessError> {
+        if dry_run {
+            return Ok((RowStatus::Created, None));
+        }
+        let oid = ObjectId::new();
+        doc.insert("create_time", date_time::to_string());
+        doc.insert("update_time", date_time::to_string());
+        doc.insert("_id", oid.clone());
+        self.coll.insert_one(doc, None).await?;
+        Ok((RowStatus::Created, Some(oid.to_hex())))
+    }
+}
+
+/// 自然键查询条件 任一键为空时返回错误
+///
+/// 空值不能作为条件: `{key: null}` 会匹配所有缺少该字段的文档
+fn key_filter(doc: &Document, keys: &[String]) -> Result<Document, String> {
+    let mut filter = doc! {};
+    for key in keys.iter() {
+        match doc.get(key) {
+            None | Some(Bson::Null) => return Err(format!("自然键 {} 不能为空", key)),
+            Some(Bson::String(value)) if value.trim().is_empty() => {
+                return Err(format!("自然键 {} 不能为空", key))
+            }
+            Some(value) => filter.insert(key.as_str(), value.clone()),
+        };
+    }
+    Ok(filter)
+}
+
+/// upsert 的更新文档 新增时写入 `create_time`; 不更新已存在的数据时全部放入 `$setOnInsert`
+fn upsert_update(mut doc: Document, update_existing: bool, now: &str) -> Document {
+    doc.insert("update_time", now);
+    if update_existing {
+        doc! {"$set": doc, "$setOnInsert": {"create_time": now}}
+    } else {
+        doc.insert("create_time", now);
+        doc! {"$setOnInsert": doc}
+    }
+}
+
+/// 列映射为 (表头位置, 列) 表头缺少必填列时返回错误
+fn map_columns<'a>(
+    headers: &[String],
+    opts: &'a ImportOptions,
+) -> Result<Vec<(usize, &'a ImportColumn)>, BusinessError> {
+    let mut columns = vec![];
+    for column in opts.columns.iter() {
+        match headers.iter().position(|h| h == &column.header) {
+            Some(index) => columns.push((index, column)),
+            None if column.required => {
+                return Err(BusinessError::ValidationError {
+                    field: column.header.clone(),
+                })
+            }
+            None => {}
+        }
+    }
+    Ok(columns)
+}
+
+/// 读取一条记录 返回 (起始行号, 文档或错误信息)
+///
+/// 行号取自解析位置, 无法确定时使用上一条记录的下一行
+fn read_row(
+    record: csv::Result<csv::StringRecord>,
+    columns: &[(usize, &ImportColumn)],
+    opts: &ImportOptions,
+    last_row: usize,
+) -> (usize, Result<Document, String>) {
+    match record {
+        Ok(record) => {
+            let row = record
+                .position()
+                .map(|p| p.line() as usize)
+                .unwrap_or(last_row + 1);
+            (row, build_row(&record, columns, opts))
+        }
+        Err(e) => {
+            let row = e
+                .position()
+                .map(|p| p.line() as usize)
+                .unwrap_or(last_row + 1);
+            (row, Err(e.to_string()))
+        }
+    }
+}
+
+/// 单行转文档: 类型转换 -> 必填检查 -> 自定义校验
+fn build_row(
+    record: &csv::StringRecord,
+    columns: &[(usize, &ImportColumn)],
+    opts: &ImportOptions,
+) -> Result<Document, String> {
+    let mut doc = doc! {};
+    for (index, column) in columns.iter() {
+        let value = record.get(*index).unwrap_or("").trim();
+        if value.is_empty() {
+            if column.required {
+                return Err(format!("{} 不能为空", column.header));
+            }
+            continue;
+        }
+        doc.insert(column.field.as_str(), column.coerce(value)?);
+    }
+    for validator in opts.validators.iter() {
+        validator(&doc)?;
+    }
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> ImportOptions {
+        ImportOptions::new()
+            .column(ImportColumn::new("手机号", "phone").required())
+            .column(ImportColumn::new("备注", "remark"))
+            .column(ImportColumn::new("年龄", "age").field_type(FieldType::Int))
+            .validate(|doc| match doc.get_str("phone") {
+                Ok(phone) if phone.len() == 11 => Ok(()),
+                _ => Err("手机号格式错误".to_owned()),
+            })
+    }
+
+    fn rows(data: &str, opts: &ImportOptions) -> Vec<(usize, Result<Document, String>)> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()
+            .unwrap()
+            .iter()
+            .map(|h| h.to_owned())
+            .collect();
+        let columns = map_columns(&headers, opts).unwrap();
+        let mut last_row = 1;
+        reader
+            .records()
+            .map(|record| {
+                let (row, doc) = read_row(record, &columns, opts, last_row);
+                last_row = row;
+                (row, doc)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn row_numbers_follow_multiline_cells() {
+        let data = "手机号,备注,年龄\n13800000000,\"第一行\n第二行\",20\n13800000001,,x\n123,,1\n";
+        let rows = rows(data, &options());
+        assert_eq!(
+            rows.iter().map(|(row, _)| *row).collect::<Vec<_>>(),
+            vec![2, 4, 5]
+        );
+        let first = rows[0].1.as_ref().unwrap();
+        assert_eq!(first.get_str("remark").unwrap(), "第一行\n第二行");
+        assert_eq!(first.get_i32("age").unwrap(), 20);
+        assert_eq!(rows[1].1, Err("年龄 不是有效的整数: x".to_owned()));
+        assert_eq!(rows[2].1, Err("手机号格式错误".to_owned()));
+    }
+
+    #[test]
+    fn required_columns() {
+        let headers = vec!["备注".to_owned()];
+        let opts = options();
+        assert!(matches!(
+            map_columns(&headers, &opts),
+            Err(BusinessError::ValidationError { field }) if field == "手机号"
+        ));
+        let rows = rows("手机号,备注\n,a\n", &opts);
+        assert_eq!(rows[0].1, Err("手机号 不能为空".to_owned()));
+    }
+
+    #[test]
+    fn coerce_types() {
+        let column = |t: FieldType| ImportColumn::new("c", "c").field_type(t);
+        assert_eq!(
+            column(FieldType::Int).coerce("5000000000"),
+            Ok(Bson::Int64(5_000_000_000))
+        );
+        assert_eq!(
+            column(FieldType::Bool).coerce("是"),
+            Ok(Bson::Boolean(true))
+        );
+        assert_eq!(
+            column(FieldType::Float).coerce("1.5"),
+            Ok(Bson::Double(1.5))
+        );
+        assert!(column(FieldType::ObjectId).coerce("xyz").is_err());
+        let date = column(FieldType::Date("%Y-%m-%d".to_owned()))
+            .coerce("2020-01-02")
+            .unwrap();
+        assert!(matches!(date, Bson::DateTime(_)));
+    }
+
+    #[test]
+    fn natural_keys_must_be_present() {
+        let keys = vec!["phone".to_owned(), "org".to_owned()];
+        let filter = key_filter(&doc! {"phone": "138", "org": 1, "name": "a"}, &keys);
+        assert_eq!(filter, Ok(doc! {"phone": "138", "org": 1}));
+        assert_eq!(
+            key_filter(&doc! {"phone": "138"}, &keys),
+            Err("自然键 org 不能为空".to_owned())
+        );
+        assert!(key_filter(&doc! {"phone": " ", "org": 1}, &keys).is_err());
+        assert!(key_filter(&doc! {"phone": Bson::Null, "org": 1}, &keys).is_err());
+    }
+
+    #[test]
+    fn upsert_documents() {
+        let now = "2021-01-01 00:00:00";
+        assert_eq!(
+            upsert_update(doc! {"phone": "138"}, true, now),
+            doc! {
+                "$set": {"phone": "138", "update_time": now},
+                "$setOnInsert": {"create_time": now},
+            }
+        );
+        assert_eq!(
+            upsert_update(doc! {"phone": "138"}, false, now),
+            doc! {"$setOnInsert": {"phone": "138", "update_time": now, "create_time": now}}
+        );
+    }
+
+    #[test]
+    fn report_counts() {
+        let mut report = ImportReport::default();
+        report.push(2, RowStatus::Created, None, None);
+        report.push(3, RowStatus::Failed, None, Some("E11000".to_owned()));
+        report.push(4, RowStatus::Skipped, None, None);
+        assert_eq!(
+            (report.total, report.created, report.failed, report.skipped),
+            (3, 1, 1, 1)
+        );
+    }
+}
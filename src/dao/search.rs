@@ -0,0 +1,460 @@
+use super::*;
+use bson::Bson;
+use futures::StreamExt;
+use mongodb::options::AggregateOptions;
+
+lazy_static! {
+    // 全文索引字段  key: 库.集合
+    static ref TEXT_FIELDS: Mutex<HashMap<String, Vec<String>>> = Mutex::new(HashMap::new());
+}
+
+/// 搜索结果中的相关度字段
+pub const SCORE_FIELD: &str = "_score";
+/// 搜索结果中的高亮字段
+pub const HIGHLIGHT_FIELD: &str = "_highlight";
+
+/// 全文索引声明
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::dao::{Dao, TextIndex, TextSearch};
+///
+/// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+/// let dao = Dao::new("YNOS", "article");
+/// dao.text_index(TextIndex::new().field("title", 10).field("content", 1))
+///     .await?;
+/// let list = dao.search(TextSearch::new("rust mongodb").page(1, 20)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TextIndex {
+    /// 索引名称
+    pub name: String,
+    /// 字段与权重
+    pub fields: Vec<(String, i32)>,
+    /// 默认语言 中文等不支持分词的语言使用 `none`
+    pub default_language: String,
+    /// 文档中指定语言的字段
+    pub language_override: Option<String>,
+}
+
+impl Default for TextIndex {
+    fn default() -> Self {
+        TextIndex {
+            name: "text_search".to_owned(),
+            fields: vec![],
+            default_language: "none".to_owned(),
+            language_override: None,
+        }
+    }
+}
+
+impl TextIndex {
+    pub fn new() -> Self {
+        TextIndex::default()
+    }
+
+    /// 添加索引字段
+    pub fn field(mut self, name: &str, weight: i32) -> Self {
+        self.fields.push((name.to_owned(), weight));
+        self
+    }
+
+    /// 设置索引名称
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// 设置默认语言
+    pub fn default_language(mut self, language: &str) -> Self {
+        self.default_language = language.to_owned();
+        self
+    }
+
+    /// 设置文档语言字段
+    pub fn language_override(mut self, field: &str) -> Self {
+        self.language_override = Some(field.to_owned());
+        self
+    }
+}
+
+/// 全文搜索条件
+#[derive(Clone, Debug)]
+pub struct TextSearch {
+    /// 搜索内容 支持 `"短语"` 与 `-排除词`
+    pub query: String,
+    /// 搜索语言 默认使用索引语言
+    pub language: Option<String>,
+    /// 额外的过滤条件
+    pub filter: Document,
+    /// 每页条数
+    pub limit: i64,
+    /// 页数 从 1 开始
+    pub page: i64,
+    /// 最低相关度
+    pub min_score: Option<f64>,
+    /// 高亮标签 None 不高亮
+    pub highlight: Option<(String, String)>,
+    /// 高亮字段 None 时使用全文索引的字段
+    pub highlight_fields: Option<Vec<String>>,
+}
+
+impl TextSearch {
+    pub fn new(query: &str) -> Self {
+        TextSearch {
+            query: query.to_owned(),
+            language: None,
+            filter: doc! {},
+            limit: 10,
+            page: 1,
+            min_score: None,
+            highlight: None,
+            highlight_fields: None,
+        }
+    }
+
+    /// 设置搜索语言
+    pub fn language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_owned());
+        self
+    }
+
+    /// 设置额外过滤条件
+    pub fn filter(mut self, filter: Document) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// 设置分页
+    pub fn page(mut self, page: i64, limit: i64) -> Self {
+        self.page = page;
+        self.limit = limit;
+        self
+    }
+
+    /// 设置最低相关度
+    pub fn min_score(mut self, score: f64) -> Self {
+        self.min_score = Some(score);
+        self
+    }
+
+    /// 高亮匹配词 例如 `("<em>", "</em>")`
+    pub fn highlight(mut self, pre: &str, post: &str) -> Self {
+        self.highlight = Some((pre.to_owned(), post.to_owned()));
+        self
+    }
+
+    /// 指定高亮字段 默认使用全文索引的字段
+    pub fn highlight_fields(mut self, fields: &[&str]) -> Self {
+        self.highlight_fields = Some(fields.iter().map(|f| (*f).to_owned()).collect());
+        self
+    }
+
+    /// 跳过的数量 页码过大时取 i64::MAX
+    fn skip(&self) -> i64 {
+        self.limit.saturating_mul(self.page.max(1) - 1)
+    }
+
+    fn to_filter(&self) -> Document {
+        let mut text = doc! { "$search": self.query.as_str() };
+        if let Some(language) = &self.language {
+            text.insert("$language", language.as_str());
+        }
+        let mut filter = self.filter.clone();
+        filter.insert("$text", text);
+        filter
+    }
+
+    /// 搜索词 (去掉排除词与引号)
+    fn terms(&self) -> Vec<String> {
+        self.query
+            .split_whitespace()
+            .filter(|t| !t.starts_with('-'))
+            .map(|t| t.trim_matches('"').to_owned())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+}
+
+impl Dao {
+    /// 声明并创建集合的全文索引 (一个集合只能有一个全文索引)
+    pub async fn text_index(&self, index: TextIndex) -> Result<(), BusinessError> {
+        if index.fields.is_empty() {
+            return Err(BusinessError::ValidationError {
+                field: "fields".to_owned(),
+            });
+        }
+        let mut key = doc! {};
+        let mut weights = doc! {};
+        for (name, weight) in index.fields.iter() {
+            key.insert(name.as_str(), "text");
+            weights.insert(name.as_str(), *weight);
+        }
+        let mut spec = doc! {
+            "key": key,
+            "name": index.name.as_str(),
+            "weights": weights,
+            "default_language": index.default_language.as_str(),
+        };
+        if let Some(field) = &index.language_override {
+            spec.insert("language_override", field.as_str());
+        }
+        let ns = self.coll.namespace();
+        database(&ns.db)
+            .run_command(
+                doc! { "createIndexes": ns.coll.as_str(), "indexes": [spec] },
+                None,
+            )
+            .await?;
+        let fields = index.fields.into_iter().map(|(name, _)| name).collect();
+        TEXT_FIELDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(self.namespace_key(), fields);
+        Ok(())
+    }
+
+    /// 全文索引的字段 未在本进程声明时从 `listIndexes` 读取
+    pub async fn text_fields(&self) -> Result<Vec<String>, BusinessError> {
+        let key = self.namespace_key();
+        if let Some(fields) = TEXT_FIELDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return Ok(fields.clone());
+        }
+        let ns = self.coll.namespace();
+        let ret = database(&ns.db)
+            .run_command(doc! { "listIndexes": ns.coll.as_str() }, None)
+            .await?;
+        let indexes = ret
+            .get_document("cursor")
+            .and_then(|c| c.get_array("firstBatch"))
+            .map(|list| {
+                list.iter()
+                    .filter_map(Bson::as_document)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let fields = text_index_fields(&indexes).unwrap_or_default();
+        // 没有全文索引时不缓存, 其他进程随后创建的索引仍可读取
+        if !fields.is_empty() {
+            TEXT_FIELDS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key, fields.clone());
+        }
+        Ok(fields)
+    }
+
+    /// 全文搜索 按相关度排序, 每条结果带 [`SCORE_FIELD`] 字段
+    ///
+    /// 开启高亮时在 [`HIGHLIGHT_FIELD`] 字段返回各字段转义后的高亮文本 (仅匹配原词, 不处理词干)
+    pub async fn search(&self, search: TextSearch) -> Result<Vec<Document>, BusinessError> {
+        if search.limit <= 0 {
+            return Err(BusinessError::ValidationError {
+                field: "limit".to_owned(),
+            });
+        }
+        let mut pipeline = vec![
+            doc! { "$match": search.to_filter() },
+            doc! { "$addFields": { SCORE_FIELD: { "$meta": "textScore" } } },
+        ];
+        if let Some(score) = search.min_score {
+            pipeline.push(doc! { "$match": { SCORE_FIELD: { "$gte": score } } });
+        }
+        pipeline.push(doc! { "$sort": { SCORE_FIELD: { "$meta": "textScore" } } });
+        pipeline.push(doc! { "$skip": search.skip() });
+        pipeline.push(doc! { "$limit": search.limit });
+        let mut opt = AggregateOptions::default();
+        opt.max_time = Some(Duration::from_secs(3));
+
+        let mut cursor = self.coll.aggregate(pipeline, opt).await?;
+        let list = cursor.as_normalized_vec(&self.normalizer).await?;
+        let (pre, post) = match &search.highlight {
+            Some(tags) => tags,
+            None => return Ok(list),
+        };
+
+        let fields = match &search.highlight_fields {
+            Some(fields) => fields.clone(),
+            None => self.text_fields().await?,
+        };
+        let terms = search.terms();
+        Ok(list
+            .into_iter()
+            .map(|mut d| {
+                let mut marks = doc! {};
+                for field in fields.iter() {
+                    if let Ok(text) = d.get_str(field) {
+                        marks.insert(field.as_str(), highlight(text, &terms, pre, post));
+                    }
+                }
+                d.insert(HIGHLIGHT_FIELD, marks);
+                d
+            })
+            .collect())
+    }
+
+    /// 全文搜索总数
+    pub async fn search_count(&self, search: &TextSearch) -> Result<i64, BusinessError> {
+        let mut pipeline = vec![doc! { "$match": search.to_filter() }];
+        if let Some(score) = search.min_score {
+            pipeline.push(doc! { "$addFields": { SCORE_FIELD: { "$meta": "textScore" } } });
+            pipeline.push(doc! { "$match": { SCORE_FIELD: { "$gte": score } } });
+        }
+        pipeline.push(doc! { "$count": "total" });
+        let mut cursor = self.coll.aggregate(pipeline, None).await?;
+        match cursor.next().await {
+            Some(d) => Ok(bson_i64(d?.get("total"))),
+            None => Ok(0),
+        }
+    }
+}
+
+/// `listIndexes` 结果中全文索引的字段 (通配符 `$**` 索引无法确定字段, 不返回)
+fn text_index_fields(indexes: &[&Document]) -> Option<Vec<String>> {
+    let index = indexes.iter().find(|index| {
+        index
+            .get_document("key")
+            .map(|key| key.get_str("_fts") == Ok("text"))
+            .unwrap_or_default()
+    })?;
+    let weights = index.get_document("weights").ok()?;
+    Some(
+        weights
+            .keys()
+            .filter(|k| k.as_str() != "$**")
+            .cloned()
+            .collect(),
+    )
+}
+
+/// html 转义
+fn escape(out: &mut String, chars: &[char]) {
+    for c in chars {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(*c),
+        }
+    }
+}
+
+/// 高亮文本中的搜索词 忽略大小写
+///
+/// 文本按 html 转义, 高亮标签原样输出
+///
+/// ```rust
+/// use yn_util::dao::highlight;
+///
+/// let text = highlight("Rust 与 <rust>", &["rust".to_owned()], "<em>", "</em>");
+/// assert_eq!(text, "<em>Rust</em> 与 &lt;<em>rust</em>&gt;");
+/// ```
+pub fn highlight(text: &str, terms: &[String], pre: &str, post: &str) -> String {
+    let terms: Vec<Vec<char>> = terms
+        .iter()
+        .map(|t| t.chars().flat_map(char::to_lowercase).collect())
+        .filter(|t: &Vec<char>| !t.is_empty())
+        .collect();
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let len = terms
+            .iter()
+            .filter(|t| {
+                i + t.len() <= chars.len()
+                    && chars[i..i + t.len()]
+                        .iter()
+                        .zip(t.iter())
+                        .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+            })
+            .map(|t| t.len())
+            .max();
+        match len {
+            Some(len) => {
+                out.push_str(pre);
+                escape(&mut out, &chars[i..i + len]);
+                out.push_str(post);
+                i += len;
+            }
+            None => {
+                escape(&mut out, &chars[i..i + 1]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_escapes_text() {
+        let terms = vec!["rust".to_owned(), "rust 语言".to_owned()];
+        let text = highlight("<script>Rust</script> & rust 语言", &terms, "<em>", "</em>");
+        assert_eq!(
+            text,
+            "&lt;script&gt;<em>Rust</em>&lt;/script&gt; &amp; <em>rust 语言</em>"
+        );
+        assert_eq!(
+            highlight("\"a\" 'b'", &[], "<em>", "</em>"),
+            "&quot;a&quot; &#39;b&#39;"
+        );
+    }
+
+    #[test]
+    fn skip_saturates() {
+        let search = |page, limit| TextSearch::new("rust").page(page, limit).skip();
+        assert_eq!(search(3, 20), 40);
+        assert_eq!(search(0, 20), 0);
+        assert_eq!(search(i64::MAX, 20), i64::MAX);
+    }
+
+    #[test]
+    fn terms_and_filter() {
+        let search = TextSearch::new("\"rust\" -java  mongo")
+            .language("en")
+            .filter(doc! { "status": 1 });
+        assert_eq!(search.terms(), vec!["rust", "mongo"]);
+        let filter = search.to_filter();
+        assert_eq!(filter.get_i32("status").unwrap(), 1);
+        let text = filter.get_document("$text").unwrap();
+        assert_eq!(text.get_str("$language").unwrap(), "en");
+    }
+
+    #[test]
+    fn fields_from_list_indexes() {
+        let id = doc! { "v": 2, "key": { "_id": 1 }, "name": "_id_" };
+        let text = doc! {
+            "v": 2,
+            "key": { "_fts": "text", "_ftsx": 1 },
+            "name": "text_search",
+            "weights": { "title": 10, "content": 1 },
+        };
+        assert_eq!(
+            text_index_fields(&[&id, &text]),
+            Some(vec!["title".to_owned(), "content".to_owned()])
+        );
+        assert_eq!(text_index_fields(&[&id]), None);
+        let wildcard = doc! { "key": { "_fts": "text" }, "weights": { "$**": 1 } };
+        assert_eq!(text_index_fields(&[&wildcard]), Some(vec![]));
+    }
+
+    #[test]
+    fn numeric_total() {
+        assert_eq!(bson_i64(Some(&Bson::Int32(3))), 3);
+        assert_eq!(bson_i64(Some(&Bson::Int64(1 << 40))), 1 << 40);
+        assert_eq!(bson_i64(Some(&Bson::Double(2.0))), 2);
+        assert_eq!(bson_i64(None), 0);
+    }
+}
@@ -0,0 +1,452 @@
+use super::*;
+use bson::Bson;
+use futures::StreamExt;
+use mongodb::options::UpdateOptions;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::time::Instant;
+
+lazy_static! {
+    // 整棵树缓存  key: 库.集合:配置
+    static ref TREE_CACHE: Mutex<HashMap<String, (Instant, Vec<Value>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// 树形集合配置 (父节点引用模型)
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::dao::{Dao, TreeOptions};
+/// use std::time::Duration;
+///
+/// # async fn run() -> Result<(), yn_util::utils::BusinessError> {
+/// let dao = Dao::new("position", "region");
+/// let opts = TreeOptions::new("code", "parent_code").cache_ttl(Duration::from_secs(600));
+/// let tree = dao.tree(opts);
+/// // 省 -> 市 -> 区
+/// let cities = tree.children("440000").await?;
+/// let breadcrumb = tree.ancestors("440305").await?;
+/// let province = tree.subtree("440000", Some(2)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TreeOptions {
+    /// 节点标识字段 默认 `_id`
+    pub id_field: String,
+    /// 父节点字段 默认 `parent_id`
+    pub parent_field: String,
+    /// 返回 json 中子节点字段名 默认 `children`
+    pub children_field: String,
+    /// 整棵树缓存时长 None 不缓存
+    pub cache_ttl: Option<Duration>,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        TreeOptions::new("_id", "parent_id")
+    }
+}
+
+impl TreeOptions {
+    pub fn new(id_field: &str, parent_field: &str) -> Self {
+        TreeOptions {
+            id_field: id_field.to_owned(),
+            parent_field: parent_field.to_owned(),
+            children_field: "children".to_owned(),
+            cache_ttl: None,
+        }
+    }
+
+    /// 设置子节点字段名
+    pub fn children_field(mut self, name: &str) -> Self {
+        self.children_field = name.to_owned();
+        self
+    }
+
+    /// 开启整棵树缓存
+    ///
+    /// 通过 Dao 的写操作 (`save` / `update` / `remove` 等) 会清除该集合的树缓存,
+    /// 直接通过 `coll` 写入时需调用 [`Tree::invalidate`] 或 [`Dao::invalidate`]
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// 缓存 key 包含全部配置, 不同配置的树互不共用
+    fn cache_key(&self, ns: &str) -> String {
+        format!(
+            "{}:{}:{}:{}:{}",
+            ns,
+            self.id_field,
+            self.parent_field,
+            self.children_field,
+            self.cache_ttl.map(|t| t.as_millis()).unwrap_or_default()
+        )
+    }
+
+    /// 平铺节点组装为嵌套结构, 父节点为空或不在列表中的节点作为根 (按原顺序)
+    fn build(&self, nodes: Vec<Document>) -> Vec<Value> {
+        let ids: HashSet<String> = nodes
+            .iter()
+            .filter_map(|d| d.get(&self.id_field).map(node_key))
+            .collect();
+        let mut roots = vec![];
+        let mut groups: HashMap<String, Vec<Document>> = HashMap::new();
+        for d in nodes {
+            match d.get(&self.parent_field).map(node_key) {
+                Some(p) if ids.contains(&p) => groups.entry(p).or_default().push(d),
+                _ => roots.push(d),
+            }
+        }
+        let mut visited = HashSet::new();
+        roots
+            .into_iter()
+            .map(|d| self.build_node(d, &mut groups, &mut visited))
+            .collect()
+    }
+
+    fn build_node(
+        &self,
+        node: Document,
+        groups: &mut HashMap<String, Vec<Document>>,
+        visited: &mut HashSet<String>,
+    ) -> Value {
+        let key = node.get(&self.id_field).map(node_key).unwrap_or_default();
+        let mut children = vec![];
+        // 数据异常出现环时只展开一次
+        if visited.insert(key.clone()) {
+            for child in groups.remove(&key).unwrap_or_default() {
+                children.push(self.build_node(child, groups, visited));
+            }
+        }
+        let mut value = match Bson::Document(node).into_relaxed_extjson() {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+        value.insert(self.children_field.clone(), Value::Array(children));
+        Value::Object(value)
+    }
+}
+
+/// 清除集合的全部树缓存 (任意配置)
+pub(crate) fn invalidate_trees(ns: &str) {
+    let prefix = format!("{}:", ns);
+    TREE_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|k, _| !k.starts_with(&prefix));
+}
+
+/// 树形查询
+pub struct Tree<'a> {
+    dao: &'a Dao,
+    opts: TreeOptions,
+}
+
+impl Dao {
+    /// 树形查询 适用于以父节点字段关联的集合 (例如 position 库的省市区)
+    pub fn tree(&self, opts: TreeOptions) -> Tree<'_> {
+        Tree { dao: self, opts }
+    }
+}
+
+impl<'a> Tree<'a> {
+    /// 直接子节点
+    pub async fn children(&self, parent: impl Into<Bson>) -> Result<Vec<Document>, BusinessError> {
+        let filter = doc! { self.opts.parent_field.as_str(): parent.into() };
+        self.find_nodes(filter).await
+    }
+
+    /// 根节点 (父节点字段为空)
+    pub async fn roots(&self) -> Result<Vec<Document>, BusinessError> {
+        let filter = doc! { self.opts.parent_field.as_str(): { "$in": [Bson::Null, ""] } };
+        self.find_nodes(filter).await
+    }
+
+    /// 祖先节点 从根节点开始排列 (面包屑, 不含自身)
+    pub async fn ancestors(&self, id: impl Into<Bson>) -> Result<Vec<Document>, BusinessError> {
+        let lookup = doc! {
+            "from": self.dao.coll.name(),
+            "startWith": format!("${}", self.opts.parent_field),
+            "connectFromField": self.opts.parent_field.as_str(),
+            "connectToField": self.opts.id_field.as_str(),
+            "as": "__nodes",
+            "depthField": "__depth",
+        };
+        let mut list = self.graph_lookup(id.into(), lookup).await?;
+        // depth 越大离根越近
+        list.sort_by_key(|d| -d.get_i64("__depth").unwrap_or_default());
+        Ok(self.finish(list))
+    }
+
+    /// 子孙节点 平铺返回, 每个节点带 `depth` (1 为直接子节点)
+    ///
+    /// max_depth - 最大层数 None 不限制
+    pub async fn descendants(
+        &self,
+        id: impl Into<Bson>,
+        max_depth: Option<u32>,
+    ) -> Result<Vec<Document>, BusinessError> {
+        if max_depth == Some(0) {
+            return Ok(vec![]);
+        }
+        let mut lookup = doc! {
+            "from": self.dao.coll.name(),
+            "startWith": format!("${}", self.opts.id_field),
+            "connectFromField": self.opts.id_field.as_str(),
+            "connectToField": self.opts.parent_field.as_str(),
+            "as": "__nodes",
+            "depthField": "__depth",
+        };
+        if let Some(depth) = max_depth {
+            lookup.insert("maxDepth", depth as i64 - 1);
+        }
+        let mut list = self.graph_lookup(id.into(), lookup).await?;
+        list.sort_by_key(|d| d.get_i64("__depth").unwrap_or_default());
+        let list = list
+            .into_iter()
+            .map(|mut d| {
+                let depth = d.get_i64("__depth").unwrap_or_default() + 1;
+                d.insert("depth", depth);
+                d
+            })
+            .collect();
+        Ok(self.finish(list))
+    }
+
+    /// 子树 以嵌套 json 返回, 根为当前节点
+    pub async fn subtree(
+        &self,
+        id: impl Into<Bson>,
+        max_depth: Option<u32>,
+    ) -> Result<Option<Value>, BusinessError> {
+        let id = id.into();
+        let node = match self.find_node(id.clone()).await? {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let mut nodes = self.descendants(id, max_depth).await?;
+        for d in nodes.iter_mut() {
+            d.remove("depth");
+        }
+        nodes.insert(0, node);
+        Ok(self.opts.build(nodes).into_iter().next())
+    }
+
+    /// 整棵树 以嵌套 json 返回, 开启缓存时优先读取缓存
+    pub async fn all(&self) -> Result<Vec<Value>, BusinessError> {
+        let key = self.cache_key();
+        if let Some(ttl) = self.opts.cache_ttl {
+            let cache = TREE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((at, tree)) = cache.get(&key) {
+                if at.elapsed() < ttl {
+                    return Ok(tree.clone());
+                }
+            }
+        }
+        let nodes = self.find_nodes(doc! {}).await?;
+        let tree = self.opts.build(nodes);
+        if self.opts.cache_ttl.is_some() {
+            let mut cache = TREE_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+            cache.insert(key, (Instant::now(), tree.clone()));
+        }
+        Ok(tree)
+    }
+
+    /// 移动节点到新的父节点下 (parent 为 Null 时移动为根节点)
+    pub async fn move_node(
+        &self,
+        id: impl Into<Bson>,
+        parent: impl Into<Bson>,
+    ) -> Result<(), BusinessError> {
+        let id = id.into();
+        let parent = parent.into();
+        let node = match self.find_node(id.clone()).await? {
+            Some(node) => node,
+            None => {
+                return Err(BusinessError::ArgumentError {
+                    source: anyhow!("节点不存在"),
+                })
+            }
+        };
+        if parent != Bson::Null {
+            if self.find_node(parent.clone()).await?.is_none() {
+                return Err(BusinessError::ArgumentError {
+                    source: anyhow!("父节点不存在"),
+                });
+            }
+            // 不能移动到自身或子孙节点下
+            let parent_key = node_key(&self.dao.normalizer.normalize_bson(parent.clone()));
+            let mut keys: HashSet<String> = self
+                .descendants(id.clone(), None)
+                .await?
+                .iter()
+                .filter_map(|d| d.get(&self.opts.id_field).map(node_key))
+                .collect();
+            keys.insert(node_key(&self.dao.normalizer.normalize_bson(id.clone())));
+            if keys.contains(&parent_key) {
+                return Err(BusinessError::ArgumentError {
+                    source: anyhow!("不能移动到自身或子节点下"),
+                });
+            }
+        }
+        let filter = doc! { self.opts.id_field.as_str(): id };
+        let update = doc! {
+            "$set": {
+                self.opts.parent_field.as_str(): parent,
+                "update_time": date_time::to_string(),
+            }
+        };
+        self.dao
+            .coll
+            .update_one(filter, update, UpdateOptions::default())
+            .await?;
+        let oid = node
+            .get_str("_id")
+            .ok()
+            .and_then(|id| ObjectId::with_string(id).ok());
+        self.dao
+            .after_write(&oid.into_iter().collect::<Vec<_>>())
+            .await;
+        Ok(())
+    }
+
+    /// 清除该集合的整棵树缓存 (包括其他配置的树)
+    pub fn invalidate(&self) {
+        invalidate_trees(&self.dao.namespace_key());
+    }
+
+    fn cache_key(&self) -> String {
+        self.opts.cache_key(&self.dao.namespace_key())
+    }
+
+    async fn find_node(&self, id: Bson) -> Result<Option<Document>, BusinessError> {
+        let filter = doc! { self.opts.id_field.as_str(): id };
+        let data = self.dao.coll.find_one(filter, None).await?;
+        Ok(data.map(|d| self.dao.normalizer.normalize(d)))
+    }
+
+    async fn find_nodes(&self, filter: Document) -> Result<Vec<Document>, BusinessError> {
+        let mut opt = FindOptions::default();
+        opt.sort = Some(doc! { self.opts.id_field.as_str(): 1 });
+        let mut cursor = self.dao.coll.find(filter, opt).await?;
+        cursor.as_normalized_vec(&self.dao.normalizer).await
+    }
+
+    async fn graph_lookup(
+        &self,
+        id: Bson,
+        lookup: Document,
+    ) -> Result<Vec<Document>, BusinessError> {
+        let pipeline = vec![
+            doc! { "$match": { self.opts.id_field.as_str(): id } },
+            doc! { "$graphLookup": lookup },
+            doc! { "$project": { "__nodes": 1 } },
+        ];
+        let mut cursor = self.dao.coll.aggregate(pipeline, None).await?;
+        let mut list = vec![];
+        if let Some(result) = cursor.next().await {
+            if let Ok(nodes) = result?.get_array("__nodes") {
+                for node in nodes {
+                    if let Bson::Document(d) = node {
+                        list.push(d.clone());
+                    }
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    fn finish(&self, list: Vec<Document>) -> Vec<Document> {
+        list.into_iter()
+            .map(|mut d| {
+                d.remove("__depth");
+                self.dao.normalizer.normalize(d)
+            })
+            .collect()
+    }
+}
+
+fn node_key(value: &Bson) -> String {
+    match value {
+        Bson::String(s) => s.to_owned(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes() -> Vec<Document> {
+        vec![
+            doc! { "code": "44", "parent_code": Bson::Null, "name": "广东" },
+            doc! { "code": "4403", "parent_code": "44", "name": "深圳" },
+            doc! { "code": "440305", "parent_code": "4403", "name": "南山" },
+            doc! { "code": "4401", "parent_code": "44", "name": "广州" },
+        ]
+    }
+
+    #[test]
+    fn build_nested() {
+        let opts = TreeOptions::new("code", "parent_code").children_field("items");
+        let tree = opts.build(nodes());
+        assert_eq!(tree.len(), 1);
+        let items = tree[0]["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["name"], "深圳");
+        assert_eq!(items[0]["items"][0]["name"], "南山");
+        assert_eq!(items[1]["items"].as_array().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn build_orphans_and_cycles() {
+        let opts = TreeOptions::new("code", "parent_code");
+        // 父节点不在列表中的作为根
+        let tree = opts.build(nodes().split_off(1));
+        assert_eq!(tree.len(), 2);
+        // 环中的节点没有根, 不会死循环
+        let cycle = vec![
+            doc! { "code": "a", "parent_code": "b" },
+            doc! { "code": "b", "parent_code": "a" },
+        ];
+        assert!(opts.build(cycle).is_empty());
+    }
+
+    #[test]
+    fn cache_key_covers_options() {
+        let a = TreeOptions::new("code", "parent_code");
+        let keys = [
+            a.cache_key("db.region"),
+            a.clone().children_field("items").cache_key("db.region"),
+            TreeOptions::new("_id", "parent_code").cache_key("db.region"),
+            a.clone()
+                .cache_ttl(Duration::from_secs(60))
+                .cache_key("db.region"),
+            a.cache_key("db.area"),
+        ];
+        let unique: HashSet<&String> = keys.iter().collect();
+        assert_eq!(unique.len(), keys.len());
+    }
+
+    #[test]
+    fn invalidate_namespace() {
+        let opts = TreeOptions::new("code", "parent_code");
+        {
+            let mut cache = TREE_CACHE.lock().unwrap();
+            for ns in &["t.tree", "t.tree2"] {
+                cache.insert(opts.cache_key(ns), (Instant::now(), vec![]));
+                let other = opts.clone().children_field("items");
+                cache.insert(other.cache_key(ns), (Instant::now(), vec![]));
+            }
+        }
+        invalidate_trees("t.tree");
+        let cache = TREE_CACHE.lock().unwrap();
+        assert!(!cache.keys().any(|k| k.starts_with("t.tree:")));
+        assert_eq!(
+            cache.keys().filter(|k| k.starts_with("t.tree2:")).count(),
+            2
+        );
+    }
+}
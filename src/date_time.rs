@@ -13,43 +13,21 @@ pub fn timestamp() -> u64 {
 /// 传入格式必须  YYYY-MM-DD HH:mm::ss
 /// # Examples
 /// ```
-/// from_str("1970-01-01 00:00:00")
+/// use yn_util::date_time::from_str;
+///
+/// from_str("1970-01-01 00:00:00");
 /// ```
 #[inline]
 pub fn from_str(datetime_str: &str) -> DateTime<Local> {
     let date_time_arr = datetime_str.split(" ").collect::<Vec<&str>>();
     let y_m_d = date_time_arr[0].split("-").collect::<Vec<&str>>();
     let h_m_s = date_time_arr[1].split(":").collect::<Vec<&str>>();
-    let year = if let Ok(v) = y_m_d[0].parse::<i32>() {
-        v
-    } else {
-        1970
-    };
-    let month = if let Ok(v) = y_m_d[1].parse::<u32>() {
-        v
-    } else {
-        1
-    };
-    let day = if let Ok(v) = y_m_d[2].parse::<u32>() {
-        v
-    } else {
-        1
-    };
-    let hour = if let Ok(v) = h_m_s[0].parse::<u32>() {
-        v
-    } else {
-        0
-    };
-    let minute = if let Ok(v) = h_m_s[1].parse::<u32>() {
-        v
-    } else {
-        0
-    };
-    let second = if let Ok(v) = h_m_s[2].parse::<u32>() {
-        v
-    } else {
-        0
-    };
+    let year = y_m_d[0].parse::<i32>().unwrap_or(1970);
+    let month = y_m_d[1].parse::<u32>().unwrap_or(1);
+    let day = y_m_d[2].parse::<u32>().unwrap_or(1);
+    let hour = h_m_s[0].parse::<u32>().unwrap_or_default();
+    let minute = h_m_s[1].parse::<u32>().unwrap_or_default();
+    let second = h_m_s[2].parse::<u32>().unwrap_or_default();
     Local.ymd(year, month, day).and_hms(hour, minute, second)
 }
 
@@ -1,73 +1,162 @@
 use super::*;
 use crate::date_time;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, TokenData, Validation};
+use crate::utils::BusinessError;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData};
 use serde::{de, ser, Serialize};
+use std::time::Duration;
 
-/// secret key
-pub const SECRET_KEY: &[u8] = b"ynos";
-/// 有效期一天
+mod config;
+mod jwks;
+mod keys;
+mod refresh;
+mod revocation;
+pub use config::*;
+pub use jwks::*;
+pub use keys::*;
+pub use refresh::*;
+pub use revocation::*;
+
+/// 默认有效期一天
 pub const DAY_ONE: u64 = 86400;
 
 /// 默认结构 用户token
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct UserToken {
     /// 过期时间
     pub exp: u64,
+    /// 签发时间 (早期签发的 token 中没有)
+    #[serde(default)]
+    pub iat: u64,
+    /// token 唯一标识 用于注销 (早期签发的 token 中没有)
+    #[serde(default)]
+    pub jti: String,
     /// 用户id
     pub id: String,
     /// 用户名称
     pub name: String,
+    /// 签发者
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// 接收方
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
-/// 生成token 默认模式
+/// 生成token 默认模式 使用全局配置 ([`init`]) 的当前签名密钥与有效期
+///
+/// 全局密钥环中没有私钥时 (只验签或配置无效) 返回 [`JwtError::MissingSecret`]
 /// # Examples
 /// ```rust
-/// let key = b"ynos";
-/// let token = encode_by("id","name");
+/// let token = yn_util::jwt::try_encode("id", "name")?;
+/// # Ok::<(), yn_util::jwt::JwtError>(())
 /// ```
+pub fn try_encode(id: &str, name: &str) -> Result<String, JwtError> {
+    let config = config();
+    if !config.keys.can_sign() {
+        return Err(JwtError::MissingSecret);
+    }
+    let playload = UserToken::new(id, name, config.ttl);
+    Ok(config.keys.encode(&playload)?)
+}
+
+/// 生成token 默认模式
+///
+/// 全局密钥环中没有私钥时 (只验签或配置无效) panic
+#[deprecated(since = "0.2.0", note = "没有私钥时 panic, 请使用 try_encode")]
 pub fn encode(id: &str, name: &str) -> String {
-    let now = date_time::timestamp();
-    let playload = UserToken {
-        exp: now + DAY_ONE,
-        id: id.to_string(),
-        name: name.to_string(),
-    };
-    jsonwebtoken::encode(
-        &Header::default(),
-        &playload,
-        &EncodingKey::from_secret(&*SECRET_KEY),
-    )
-    .unwrap()
+    try_encode(id, name).unwrap()
+}
+
+impl UserToken {
+    /// 使用全局配置的签发者与接收方 `ttl` 后过期
+    pub fn new(id: &str, name: &str, ttl: Duration) -> Self {
+        let config = config();
+        let now = date_time::timestamp();
+        UserToken {
+            exp: now + ttl.as_secs(),
+            iat: now,
+            jti: crate::session::new_id(),
+            id: id.to_string(),
+            name: name.to_string(),
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+        }
+    }
+}
+
+/// 使用全局配置生成自定义 token (配置了签发者 / 接收方时 claims 中须包含 `iss` / `aud`)
+pub fn encode_claims<T: ser::Serialize>(claims: &T) -> jsonwebtoken::errors::Result<String> {
+    config().keys.encode(claims)
+}
+
+/// 自定义 HMAC 密钥使用的算法 全局配置为非对称算法时使用 HS256
+fn hmac_algorithm() -> Algorithm {
+    match config().algorithm {
+        alg @ (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) => alg,
+        _ => Algorithm::HS256,
+    }
 }
 
 /// 生成自定义token
 /// # Examples
 /// ```rust
+/// use serde::{Deserialize, Serialize};
+///
 /// #[derive(Deserialize, Serialize)]
-/// let struct usertoken {
-///    exp:u64,
-///    id:String,
+/// struct UserToken {
+///     exp: u64,
+///     id: String,
 /// }
 /// let key = b"ynos";
-/// let user = usertoken {exp:1000000,id:"String"};
-/// let token = encode_by(user,&key);
+/// let user = UserToken { exp: 1000000, id: "String".to_owned() };
+/// let token = yn_util::jwt::encode_by(&user, key);
 /// ```
 pub fn encode_by<T: ser::Serialize>(data: &T, secret_key: &[u8]) -> String {
     jsonwebtoken::encode::<T>(
-        &Header::default(),
-        &data,
-        &EncodingKey::from_secret(&*secret_key),
+        &Header::new(hmac_algorithm()),
+        data,
+        &EncodingKey::from_secret(secret_key),
     )
     .unwrap()
 }
 
 /// 解码authorization字段 - 默认方式
+///
+/// 只检查本进程中的注销记录, 其他实例注销的 token 仍然有效; 鉴权请使用 [`verify`]
+#[deprecated(since = "0.2.0", note = "只检查本进程的注销记录, 鉴权请使用 verify")]
 pub fn decode(token: &str) -> jsonwebtoken::errors::Result<TokenData<UserToken>> {
-    jsonwebtoken::decode::<UserToken>(
-        token,
-        &DecodingKey::from_secret(&*SECRET_KEY),
-        &Validation::default(),
-    )
+    let data = decode_claims::<UserToken>(token)?;
+    if revoked_locally(&data.claims) {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+    Ok(data)
+}
+
+/// 解码并检查注销记录 ([`Revocations`])
+pub async fn verify(token: &str) -> Result<TokenData<UserToken>, BusinessError> {
+    verify_claims::<UserToken>(token).await
+}
+
+/// 解码自定义 token 并按其中的 `id` / `jti` / `iat` 检查注销记录
+pub async fn verify_claims<T: de::DeserializeOwned>(
+    token: &str,
+) -> Result<TokenData<T>, BusinessError> {
+    let data = decode_claims::<serde_json::Value>(token).map_err(JwtError::from)?;
+    ensure_active(&data.claims).await?;
+    let claims = serde_json::from_value(data.claims).map_err(|_| BusinessError::Unauthorized)?;
+    Ok(TokenData {
+        header: data.header,
+        claims,
+    })
+}
+
+/// 使用全局配置解码自定义 token
+pub fn decode_claims<T: de::DeserializeOwned>(
+    token: &str,
+) -> jsonwebtoken::errors::Result<TokenData<T>> {
+    let config = config();
+    config.keys.decode(token, &config.validation())
 }
 
 /// 解码authorization字段 - 自定义方式
@@ -75,9 +164,7 @@ pub fn decode_by<T: de::DeserializeOwned>(
     token: &str,
     secret_key: &[u8],
 ) -> jsonwebtoken::errors::Result<TokenData<T>> {
-    jsonwebtoken::decode::<T>(
-        token,
-        &DecodingKey::from_secret(&*secret_key),
-        &Validation::default(),
-    )
+    let mut validation = config().validation();
+    validation.algorithms = vec![hmac_algorithm()];
+    jsonwebtoken::decode::<T>(token, &DecodingKey::from_secret(secret_key), &validation)
 }
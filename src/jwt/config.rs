@@ -0,0 +1,420 @@
+use super::*;
+use crate::utils::BusinessError;
+use jsonwebtoken::{Algorithm, Validation};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+
+lazy_static! {
+    // 全局配置 未初始化时按环境变量创建
+    static ref CONFIG: RwLock<Option<Arc<JwtConfig>>> = RwLock::new(None);
+}
+
+#[derive(Error, Debug)]
+pub enum JwtError {
+    #[error("未配置 jwt 密钥")]
+    MissingSecret,
+    #[error("不支持的 jwt 算法: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("jwt 配置错误: {0}")]
+    Config(String),
+    #[error("token 无效: {0}")]
+    Token(#[from] jsonwebtoken::errors::Error),
+}
+
+impl From<JwtError> for BusinessError {
+    fn from(e: JwtError) -> Self {
+        match e {
+            JwtError::Token(_) => BusinessError::Unauthorized,
+            e => {
+                log::error!("jwt error, {}", e);
+                BusinessError::InternalError { source: anyhow!(e) }
+            }
+        }
+    }
+}
+
+/// 是否为生产环境 环境变量 `APP_ENV` 为 `production` / `prod`
+pub fn is_production() -> bool {
+    std::env::var("APP_ENV")
+        .map(|env| matches!(env.to_lowercase().as_str(), "production" | "prod"))
+        .unwrap_or(false)
+}
+
+/// jwt 配置
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::jwt::{self, JwtConfig};
+///
+/// // 从环境变量读取 JWT_SECRET / JWT_SECRET_FILE / JWT_KEY_FILE / JWT_KID / JWT_ALGORITHM
+/// // JWT_ISSUER / JWT_AUDIENCE / JWT_LEEWAY / JWT_TTL
+/// jwt::init_from_env()?;
+///
+/// // 或手动配置
+/// let config = JwtConfig::new()
+///     .secret_file("/run/secrets/jwt")?
+///     .algorithm(jsonwebtoken::Algorithm::HS512)
+///     .issuer("ynos")
+///     .ttl(Duration::from_secs(2 * 3600));
+/// jwt::init(config)?;
+/// # Ok::<(), yn_util::jwt::JwtError>(())
+/// ```
+#[derive(Clone)]
+pub struct JwtConfig {
+    /// HMAC 密钥 未配置 `keys` 时使用
+    pub secret: Vec<u8>,
+    /// 签名算法 使用 HMAC 密钥时为 HS256 / HS384 / HS512
+    pub algorithm: Algorithm,
+    /// 签名 / 验签密钥环 为空时由 `secret` 与 `algorithm` 生成
+    pub keys: KeyRing,
+    /// 注销记录 [`verify`] 时检查
+    pub revocations: Revocations,
+    /// 签发者 设置后签发的 token 带有 `iss`, 解码时校验
+    pub issuer: Option<String>,
+    /// 接收方 设置后签发的 token 带有 `aud`, 解码时校验
+    pub audience: Option<String>,
+    /// 校验过期时间时允许的时钟误差 (秒)
+    pub leeway: u64,
+    /// token 有效期
+    pub ttl: Duration,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            secret: vec![],
+            algorithm: Algorithm::HS256,
+            keys: KeyRing::new(),
+            revocations: Revocations::new(),
+            issuer: None,
+            audience: None,
+            leeway: 0,
+            ttl: Duration::from_secs(DAY_ONE),
+        }
+    }
+}
+
+impl fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("secret", &"***")
+            .field("algorithm", &self.algorithm)
+            .field("keys", &self.keys)
+            .field("revocations", &self.revocations)
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("leeway", &self.leeway)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl JwtConfig {
+    pub fn new() -> Self {
+        JwtConfig::default()
+    }
+
+    /// 从环境变量读取配置
+    ///
+    /// `JWT_SECRET` 或 `JWT_SECRET_FILE` (密钥文件路径), `JWT_ALGORITHM`, `JWT_ISSUER`,
+    /// `JWT_AUDIENCE`, `JWT_LEEWAY` (秒), `JWT_TTL` (秒);
+    /// 非对称算法使用 `JWT_KEY_FILE` (PEM / DER 私钥, 只验签时为公钥) 与可选的 `JWT_KID`
+    pub fn from_env() -> Result<Self, JwtError> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let secs = |name: &str| -> Result<Option<u64>, JwtError> {
+            var(name)
+                .map(|v| {
+                    v.trim()
+                        .parse()
+                        .map_err(|_| JwtError::Config(format!("{} 不是有效的秒数", name)))
+                })
+                .transpose()
+        };
+        let mut config = JwtConfig::new();
+        if let Some(secret) = var("JWT_SECRET") {
+            config = config.secret(secret.as_bytes());
+        } else if let Some(path) = var("JWT_SECRET_FILE") {
+            config = config.secret_file(&path)?;
+        }
+        if let Some(algorithm) = var("JWT_ALGORITHM") {
+            config.algorithm = algorithm
+                .trim()
+                .parse()
+                .map_err(|_| JwtError::UnsupportedAlgorithm(algorithm))?;
+        }
+        if let Some(path) = var("JWT_KEY_FILE") {
+            let mut key = JwtKey::from_file(config.algorithm, &path)?;
+            if let Some(kid) = var("JWT_KID") {
+                key = key.with_kid(&kid);
+            }
+            config = config.key(key);
+        }
+        config.issuer = var("JWT_ISSUER");
+        config.audience = var("JWT_AUDIENCE");
+        if let Some(leeway) = secs("JWT_LEEWAY")? {
+            config.leeway = leeway;
+        }
+        if let Some(ttl) = secs("JWT_TTL")? {
+            config.ttl = Duration::from_secs(ttl);
+        }
+        Ok(config)
+    }
+
+    /// 设置密钥
+    pub fn secret(mut self, secret: &[u8]) -> Self {
+        self.secret = secret.to_vec();
+        self
+    }
+
+    /// 从文件读取密钥 (忽略首尾空白)
+    pub fn secret_file(mut self, path: &str) -> Result<Self, JwtError> {
+        let secret = std::fs::read(path)
+            .map_err(|e| JwtError::Config(format!("读取密钥文件 {} 失败, {}", path, e)))?;
+        let text = String::from_utf8_lossy(&secret);
+        self.secret = text.trim().as_bytes().to_vec();
+        Ok(self)
+    }
+
+    /// 设置签名算法
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// 设置密钥环
+    pub fn keys(mut self, keys: KeyRing) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// 加入签名密钥 (成为当前密钥)
+    pub fn key(mut self, key: JwtKey) -> Self {
+        self.keys.rotate(key);
+        self
+    }
+
+    /// 设置注销记录
+    pub fn revocations(mut self, revocations: Revocations) -> Self {
+        self.revocations = revocations;
+        self
+    }
+
+    /// 设置签发者
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_owned());
+        self
+    }
+
+    /// 设置接收方
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_owned());
+        self
+    }
+
+    /// 设置允许的时钟误差
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway.as_secs();
+        self
+    }
+
+    /// 设置 token 有效期
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 解码时的校验规则
+    pub fn validation(&self) -> Validation {
+        let mut validation = Validation::new(self.algorithm);
+        validation.leeway = self.leeway;
+        // set_issuer / set_audience 只在 token 带有该字段时校验, 须同时设为必填
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            validation.required_spec_claims.insert("iss".to_owned());
+        }
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+            validation.required_spec_claims.insert("aud".to_owned());
+        }
+        validation
+    }
+
+    fn check(self) -> Result<Self, JwtError> {
+        self.check_for(is_production())
+    }
+
+    fn check_for(mut self, production: bool) -> Result<Self, JwtError> {
+        if self.keys.is_empty() {
+            if self.secret.is_empty() && !production {
+                log::warn!("未配置 jwt 密钥, 使用随机密钥 (重启后已签发的 token 失效)");
+                self.secret = random_secret();
+            }
+            self.keys = KeyRing::new().signing(JwtKey::hmac(self.algorithm, &self.secret)?);
+        } else if !self.secret.is_empty() {
+            log::warn!("已配置 jwt 密钥环, 忽略 secret");
+        }
+        Ok(self)
+    }
+}
+
+fn random_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .expect("系统随机数不可用");
+    secret
+}
+
+/// 设置全局配置 生产环境 (`APP_ENV=production`) 未配置密钥时返回错误
+pub fn init(config: JwtConfig) -> Result<(), JwtError> {
+    let config = config.check()?;
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(config));
+    Ok(())
+}
+
+/// 从环境变量初始化全局配置 应在启动时调用
+pub fn init_from_env() -> Result<(), JwtError> {
+    init(JwtConfig::from_env()?)
+}
+
+/// 当前全局配置 未调用 [`init`] 时按环境变量创建 (同 [`init_from_env`])
+///
+/// 环境变量中的配置无效时 (包括生产环境未配置密钥) 不生成随机密钥,
+/// 密钥环为空, 签发与解码 token 均返回错误
+pub fn config() -> Arc<JwtConfig> {
+    if let Some(config) = CONFIG.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return config.clone();
+    }
+    let mut slot = CONFIG.write().unwrap_or_else(|e| e.into_inner());
+    slot.get_or_insert_with(|| Arc::new(env_config())).clone()
+}
+
+fn env_config() -> JwtConfig {
+    match JwtConfig::from_env().and_then(JwtConfig::check) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("jwt 配置无效, 签发与解码 token 将失败, {}", e);
+            JwtConfig::new()
+        }
+    }
+}
+
+/// 轮换全局签名密钥 原有密钥保留用于验签, 已签发的 token 不会失效
+pub fn rotate(key: JwtKey) -> Result<(), JwtError> {
+    update(|config| {
+        config.keys.rotate(key);
+        Ok(())
+    })
+}
+
+/// 移除全局密钥环中的旧密钥 使用该密钥签发的 token 随即失效
+pub fn retire(kid: &str) -> Result<(), JwtError> {
+    update(|config| {
+        if config.keys.current().map(|k| k.kid()) == Some(kid) {
+            return Err(JwtError::Config(format!("不能移除当前签名密钥 {}", kid)));
+        }
+        if config.keys.remove(kid).is_none() {
+            return Err(JwtError::Config(format!("密钥 {} 不存在", kid)));
+        }
+        Ok(())
+    })
+}
+
+/// 持有写锁修改全局配置 并发的轮换 / 移除不会互相覆盖
+fn update<F>(f: F) -> Result<(), JwtError>
+where
+    F: FnOnce(&mut JwtConfig) -> Result<(), JwtError>,
+{
+    let mut slot = CONFIG.write().unwrap_or_else(|e| e.into_inner());
+    let mut config = match slot.as_ref() {
+        Some(config) => (**config).clone(),
+        None => env_config(),
+    };
+    f(&mut config)?;
+    *slot = Some(Arc::new(config.check()?));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn production_requires_secret() {
+        assert!(matches!(
+            JwtConfig::new().check_for(true),
+            Err(JwtError::MissingSecret)
+        ));
+        let config = JwtConfig::new().secret(b"0123456789abcdef").check_for(true);
+        assert_eq!(config.unwrap().keys.keys().len(), 1);
+    }
+
+    #[test]
+    fn production_checks_configured_keys() {
+        assert!(matches!(
+            JwtKey::hmac(Algorithm::HS256, b""),
+            Err(JwtError::MissingSecret)
+        ));
+        let key = JwtKey::hmac(Algorithm::HS384, b"0123456789abcdef").unwrap();
+        let config = JwtConfig::new()
+            .secret(b"ignored")
+            .key(key.with_kid("k1"))
+            .check_for(true)
+            .unwrap();
+        assert_eq!(config.keys.current().unwrap().kid(), "k1");
+        assert_eq!(config.keys.keys().len(), 1);
+    }
+
+    #[test]
+    fn configured_issuer_and_audience_are_required() {
+        let config = JwtConfig::new()
+            .secret(b"0123456789abcdef")
+            .issuer("ynos")
+            .audience("web")
+            .check_for(false)
+            .unwrap();
+        let decode = |claims: serde_json::Value| {
+            let token = config.keys.encode(&claims).unwrap();
+            config
+                .keys
+                .decode::<serde_json::Value>(&token, &config.validation())
+        };
+        let exp = u64::MAX;
+        assert!(decode(serde_json::json!({ "exp": exp, "iss": "ynos", "aud": "web" })).is_ok());
+        assert!(decode(serde_json::json!({ "exp": exp, "iss": "ynos" })).is_err());
+        assert!(decode(serde_json::json!({ "exp": exp, "aud": "web" })).is_err());
+        assert!(decode(serde_json::json!({ "exp": exp, "iss": "ynos", "aud": "app" })).is_err());
+    }
+
+    #[test]
+    fn development_uses_random_secret() {
+        let a = JwtConfig::new().check_for(false).unwrap();
+        let b = JwtConfig::new().check_for(false).unwrap();
+        assert_eq!(a.secret.len(), 32);
+        assert_ne!(a.secret, b.secret);
+        assert!(a.keys.current().is_some());
+    }
+
+    #[test]
+    fn unconfigured_keys_refuse_tokens() {
+        // env_config 失败时使用的配置
+        let config = JwtConfig::new();
+        let claims = serde_json::json!({ "exp": u64::MAX });
+        assert!(config.keys.encode(&claims).is_err());
+        let token = JwtConfig::new()
+            .secret(b"secret")
+            .check_for(false)
+            .unwrap()
+            .keys
+            .encode(&claims)
+            .unwrap();
+        assert!(config
+            .keys
+            .decode::<serde_json::Value>(&token, &config.validation())
+            .is_err());
+    }
+}
@@ -0,0 +1,448 @@
+use super::*;
+use actix_web::client::Client;
+use actix_web::http::header;
+use actix_web::{web, HttpResponse, Resource};
+use jsonwebtoken::errors::Result as TokenResult;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, Validation};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// JWKS 发布路径
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+
+fn b64(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn unb64(value: &str) -> Result<Vec<u8>, JwtError> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| JwtError::Config("JWK 参数不是有效的 base64url".to_owned()))
+}
+
+impl JwtKey {
+    /// 公钥的 JWK HMAC 密钥不公开, 返回 `None`
+    pub fn to_jwk(&self) -> Option<Jwk> {
+        let algorithm = match &self.public {
+            PublicKey::Secret => return None,
+            PublicKey::Rsa { n, e } => AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: b64(n),
+                e: b64(e),
+            }),
+            PublicKey::Ec { curve, x, y } => {
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: match *curve {
+                        "P-384" => EllipticCurve::P384,
+                        _ => EllipticCurve::P256,
+                    },
+                    x: b64(x),
+                    y: b64(y),
+                })
+            }
+            PublicKey::Ed { x } => AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: b64(x),
+            }),
+        };
+        Some(Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                algorithm: Some(self.algorithm()),
+                key_id: Some(self.kid().to_owned()),
+                ..Default::default()
+            },
+            algorithm,
+        })
+    }
+
+    /// 从 JWK 读取公钥 未指定 `alg` 时按密钥类型取 RS256 / ES256 / ES384 / EdDSA
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self, JwtError> {
+        let mismatch = |algorithm: Algorithm| {
+            JwtError::UnsupportedAlgorithm(format!("{:?} 与 JWK 密钥类型不符", algorithm))
+        };
+        let (algorithm, public) = match &jwk.algorithm {
+            AlgorithmParameters::RSA(params) => {
+                let algorithm = jwk.common.algorithm.unwrap_or(Algorithm::RS256);
+                match algorithm {
+                    Algorithm::RS256
+                    | Algorithm::RS384
+                    | Algorithm::RS512
+                    | Algorithm::PS256
+                    | Algorithm::PS384
+                    | Algorithm::PS512 => {}
+                    other => return Err(mismatch(other)),
+                }
+                let public = PublicKey::Rsa {
+                    n: unb64(&params.n)?,
+                    e: unb64(&params.e)?,
+                };
+                (algorithm, public)
+            }
+            AlgorithmParameters::EllipticCurve(params) => {
+                let algorithm =
+                    match (&params.curve, jwk.common.algorithm) {
+                        (EllipticCurve::P256, None)
+                        | (EllipticCurve::P256, Some(Algorithm::ES256)) => Algorithm::ES256,
+                        (EllipticCurve::P384, None)
+                        | (EllipticCurve::P384, Some(Algorithm::ES384)) => Algorithm::ES384,
+                        (curve, _) => {
+                            return Err(JwtError::Config(format!("不支持的 JWK 曲线 {:?}", curve)))
+                        }
+                    };
+                let point = [vec![4u8], unb64(&params.x)?, unb64(&params.y)?].concat();
+                (algorithm, ec_point(algorithm, &point)?)
+            }
+            AlgorithmParameters::OctetKeyPair(params) => {
+                match (&params.curve, jwk.common.algorithm) {
+                    (EllipticCurve::Ed25519, None)
+                    | (EllipticCurve::Ed25519, Some(Algorithm::EdDSA)) => {}
+                    (curve, _) => {
+                        return Err(JwtError::Config(format!("不支持的 JWK 曲线 {:?}", curve)))
+                    }
+                }
+                let x = unb64(&params.x)?;
+                if x.len() != 32 {
+                    return Err(JwtError::Config("Ed25519 公钥长度无效".to_owned()));
+                }
+                (Algorithm::EdDSA, PublicKey::Ed { x })
+            }
+            AlgorithmParameters::OctetKey(_) => {
+                return Err(JwtError::Config("不接受 JWKS 中的对称密钥".to_owned()))
+            }
+        };
+        let key = JwtKey::build(algorithm, None, public);
+        Ok(match &jwk.common.key_id {
+            Some(kid) => key.with_kid(kid),
+            None => key,
+        })
+    }
+}
+
+impl KeyRing {
+    /// 全部公钥的 JWKS (不含 HMAC 密钥)
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys().iter().filter_map(JwtKey::to_jwk).collect(),
+        }
+    }
+
+    /// 从 JWKS 文档读取验签密钥 跳过不支持或用于加密 (`use: enc`) 的密钥
+    pub fn from_jwks(document: &[u8]) -> Result<Self, JwtError> {
+        #[derive(Deserialize)]
+        struct Document {
+            keys: Vec<serde_json::Value>,
+        }
+        let document: Document = serde_json::from_slice(document)
+            .map_err(|e| JwtError::Config(format!("JWKS 格式无效, {}", e)))?;
+        let mut ring = KeyRing::new();
+        for value in document.keys {
+            let key = serde_json::from_value::<Jwk>(value)
+                .map_err(|e| JwtError::Config(e.to_string()))
+                .and_then(|jwk| match jwk.common.public_key_use {
+                    Some(PublicKeyUse::Encryption) => {
+                        Err(JwtError::Config("用于加密的密钥".to_owned()))
+                    }
+                    _ => JwtKey::from_jwk(&jwk),
+                });
+            match key {
+                Ok(key) => ring.insert(key),
+                Err(e) => log::warn!("忽略 JWKS 中的密钥, {}", e),
+            }
+        }
+        Ok(ring)
+    }
+}
+
+/// 全局密钥环的 JWKS
+pub fn jwks() -> JwkSet {
+    config().keys.jwks()
+}
+
+/// 发布 JWKS 的 handler
+pub async fn jwks_handler() -> HttpResponse {
+    HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "public, max-age=300")
+        .json(jwks())
+}
+
+/// 在 [`JWKS_PATH`] 发布 JWKS
+///
+/// # Examples
+/// ```rust,no_run
+/// use actix_web::{App, HttpServer};
+///
+/// # async fn run() -> std::io::Result<()> {
+/// HttpServer::new(|| App::new().service(yn_util::jwt::jwks_service()))
+///     .bind("0.0.0.0:8080")?
+///     .run()
+///     .await
+/// # }
+/// ```
+pub fn jwks_service() -> Resource {
+    web::resource(JWKS_PATH).route(web::get().to(jwks_handler))
+}
+
+/// JWKS 来源
+#[derive(Clone, Debug)]
+pub enum JwksSource {
+    File(String),
+    Url(String),
+}
+
+struct JwksState {
+    keys: Arc<KeyRing>,
+    /// 上次加载 (无论成功与否) 的时间
+    checked: Option<Instant>,
+}
+
+/// 使用 JWKS 验签 (例如其他服务签发的 token)
+///
+/// 密钥缓存 `refresh` 时间后重新加载; 遇到未知 kid 时立即重新加载 (间隔不小于 `cooldown`)。
+/// 加载失败或没有可用的密钥时继续使用已缓存的密钥
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use yn_util::jwt::{Jwks, UserToken};
+///
+/// # async fn run() -> Result<(), yn_util::jwt::JwtError> {
+/// let jwks = Jwks::url("https://auth.example.com/.well-known/jwks.json")
+///     .refresh(Duration::from_secs(600));
+/// // 或使用本地文件
+/// let jwks = Jwks::file("config/jwks.json");
+///
+/// jwks.load().await?;
+/// let token = jwks.decode::<UserToken>("eyJ...").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Jwks {
+    source: JwksSource,
+    refresh: Duration,
+    cooldown: Duration,
+    timeout: Duration,
+    validation: Option<Validation>,
+    state: Arc<RwLock<JwksState>>,
+    loading: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Jwks {
+    pub fn new(source: JwksSource) -> Self {
+        Jwks {
+            source,
+            refresh: Duration::from_secs(300),
+            cooldown: Duration::from_secs(30),
+            timeout: Duration::from_secs(5),
+            validation: None,
+            state: Arc::new(RwLock::new(JwksState {
+                keys: Arc::new(KeyRing::new()),
+                checked: None,
+            })),
+            loading: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// 本地 JWKS 文件
+    pub fn file(path: &str) -> Self {
+        Jwks::new(JwksSource::File(path.to_owned()))
+    }
+
+    /// 远程 JWKS 地址
+    pub fn url(url: &str) -> Self {
+        Jwks::new(JwksSource::Url(url.to_owned()))
+    }
+
+    /// 缓存时间 默认 5 分钟
+    pub fn refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// 未知 kid 触发重新加载的最小间隔 默认 30 秒
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// 请求超时 默认 5 秒
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 校验规则 默认使用全局配置的签发者、接收方与时钟误差
+    pub fn validation(mut self, validation: Validation) -> Self {
+        self.validation = Some(validation);
+        self
+    }
+
+    /// 当前缓存的密钥
+    pub fn keys(&self) -> Arc<KeyRing> {
+        self.state
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys
+            .clone()
+    }
+
+    /// 立即加载 可在启动时调用以尽早发现配置错误
+    pub async fn load(&self) -> Result<Arc<KeyRing>, JwtError> {
+        let _loading = self.loading.lock().await;
+        self.fetch().await
+    }
+
+    /// 验签并解码
+    pub async fn decode<T: de::DeserializeOwned>(&self, token: &str) -> TokenResult<TokenData<T>> {
+        let kid = jsonwebtoken::decode_header(token)?.kid;
+        let keys = match self.expired(kid.as_deref()) {
+            true => self.reload(kid.as_deref()).await,
+            false => self.keys(),
+        };
+        let validation = match &self.validation {
+            Some(validation) => validation.clone(),
+            None => config().validation(),
+        };
+        keys.decode(token, &validation)
+    }
+
+    /// 是否需要重新加载
+    fn expired(&self, kid: Option<&str>) -> bool {
+        let state = self.state.read().unwrap_or_else(|e| e.into_inner());
+        let elapsed = match state.checked {
+            Some(checked) => checked.elapsed(),
+            None => return true,
+        };
+        let missing = kid.is_some_and(|kid| state.keys.get(kid).is_none());
+        elapsed >= self.refresh || (missing && elapsed >= self.cooldown)
+    }
+
+    async fn reload(&self, kid: Option<&str>) -> Arc<KeyRing> {
+        let _loading = self.loading.lock().await;
+        // 等待期间其他请求可能已经完成加载
+        if !self.expired(kid) {
+            return self.keys();
+        }
+        match self.fetch().await {
+            Ok(keys) => keys,
+            Err(e) => {
+                log::warn!("加载 JWKS 失败, 继续使用缓存的密钥, {}", e);
+                self.keys()
+            }
+        }
+    }
+
+    async fn fetch(&self) -> Result<Arc<KeyRing>, JwtError> {
+        let result = self
+            .read()
+            .await
+            .and_then(|document| KeyRing::from_jwks(&document))
+            .and_then(|ring| match ring.is_empty() {
+                // 文档为空或全部密钥无效时保留已缓存的密钥
+                true => Err(JwtError::Config("JWKS 中没有可用的密钥".to_owned())),
+                false => Ok(ring),
+            });
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        state.checked = Some(Instant::now());
+        let keys = Arc::new(result?);
+        state.keys = keys.clone();
+        Ok(keys)
+    }
+
+    async fn read(&self) -> Result<Vec<u8>, JwtError> {
+        match &self.source {
+            JwksSource::File(path) => {
+                let path = path.clone();
+                web::block(move || std::fs::read(&path))
+                    .await
+                    .map_err(|e| JwtError::Config(format!("读取 JWKS 文件失败, {}", e)))
+            }
+            JwksSource::Url(url) => {
+                let fail = |e: String| JwtError::Config(format!("请求 JWKS {} 失败, {}", url, e));
+                let mut res = Client::builder()
+                    .timeout(self.timeout)
+                    .finish()
+                    .get(url)
+                    .header(header::ACCEPT, "application/json")
+                    .send()
+                    .await
+                    .map_err(|e| fail(e.to_string()))?;
+                if !res.status().is_success() {
+                    return Err(fail(res.status().to_string()));
+                }
+                let body = res
+                    .body()
+                    .limit(1024 * 1024)
+                    .await
+                    .map_err(|e| fail(e.to_string()))?;
+                Ok(body.to_vec())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P256: &str = include_str!("testdata/p256.pem");
+    const P384: &str = include_str!("testdata/p384.pem");
+    const ED25519: &str = include_str!("testdata/ed25519.pem");
+
+    fn key(algorithm: Algorithm, pem: &str, kid: &str) -> JwtKey {
+        JwtKey::from_pem(algorithm, pem.as_bytes())
+            .unwrap()
+            .with_kid(kid)
+    }
+
+    #[test]
+    fn decode_with_published_jwks() {
+        let claims = serde_json::json!({ "exp": u64::MAX, "id": "10086" });
+        let ring = KeyRing::new()
+            .signing(key(Algorithm::EdDSA, ED25519, "ed"))
+            .signing(key(Algorithm::ES256, P256, "es"));
+        let token = ring.encode(&claims).unwrap();
+        let ed_token = KeyRing::new()
+            .signing(key(Algorithm::EdDSA, ED25519, "ed"))
+            .encode(&claims)
+            .unwrap();
+        let unknown = KeyRing::new()
+            .signing(key(Algorithm::ES384, P384, "unknown"))
+            .encode(&claims)
+            .unwrap();
+
+        // 发布的 JWKS 中 ed 标记为加密用途, 不用于验签
+        let mut document = serde_json::to_value(ring.jwks()).unwrap();
+        for jwk in document["keys"].as_array_mut().unwrap() {
+            if jwk["kid"] == "ed" {
+                jwk["use"] = "enc".into();
+            }
+        }
+        let path = std::env::temp_dir().join(format!("yn_util_jwks_{}.json", std::process::id()));
+        std::fs::write(&path, document.to_string()).unwrap();
+
+        let jwks = Jwks::file(path.to_str().unwrap())
+            .validation(Validation::default())
+            .cooldown(Duration::from_secs(3600));
+        actix_web::rt::System::new("test").block_on(async move {
+            let data = jwks.decode::<serde_json::Value>(&token).await.unwrap();
+            assert_eq!(data.claims["id"], "10086");
+            assert_eq!(jwks.keys().keys().len(), 1);
+            assert!(jwks.decode::<serde_json::Value>(&ed_token).await.is_err());
+            assert!(jwks.decode::<serde_json::Value>(&unknown).await.is_err());
+
+            // 空文档不会替换已缓存的密钥
+            std::fs::write(&path, r#"{"keys":[]}"#).unwrap();
+            assert!(jwks.load().await.is_err());
+            assert!(jwks.decode::<serde_json::Value>(&token).await.is_ok());
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+}
@@ -0,0 +1,641 @@
+use super::*;
+use jsonwebtoken::errors::{ErrorKind, Result as TokenResult};
+use jsonwebtoken::{Algorithm, Validation};
+use ring::signature::{self, KeyPair};
+use std::fmt;
+
+/// 公钥部分 用于生成 kid 与发布 JWKS
+#[derive(Clone, Debug)]
+pub(crate) enum PublicKey {
+    /// HMAC 密钥 不公开
+    Secret,
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    Ec {
+        curve: &'static str,
+        x: Vec<u8>,
+        y: Vec<u8>,
+    },
+    Ed {
+        x: Vec<u8>,
+    },
+}
+
+impl PublicKey {
+    /// RFC 7638 JWK Thumbprint (SHA-256, base64url)
+    fn thumbprint(&self, secret: &[u8]) -> String {
+        let b64 = |b: &[u8]| base64::encode_config(b, base64::URL_SAFE_NO_PAD);
+        let canonical = match self {
+            PublicKey::Secret => format!(r#"{{"k":"{}","kty":"oct"}}"#, b64(secret)),
+            PublicKey::Rsa { n, e } => {
+                format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, b64(e), b64(n))
+            }
+            PublicKey::Ec { curve, x, y } => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                curve,
+                b64(x),
+                b64(y)
+            ),
+            PublicKey::Ed { x } => format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, b64(x)),
+        };
+        b64(ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes()).as_ref())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Family {
+    Hmac,
+    Rsa,
+    Ec,
+    Ed,
+}
+
+fn family(algorithm: Algorithm) -> Family {
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Family::Hmac,
+        Algorithm::ES256 | Algorithm::ES384 => Family::Ec,
+        Algorithm::EdDSA => Family::Ed,
+        _ => Family::Rsa,
+    }
+}
+
+/// 签名 / 验签密钥
+///
+/// 支持 HMAC (HS256/384/512)、RSA (RS256/384/512, PS256/384/512)、ECDSA (ES256/384) 与 Ed25519 (EdDSA)。
+/// 私钥用于签名与验签, 公钥只能验签 (例如网关)。
+/// 私钥为 PKCS#8 格式 (RSA 也可以是 PKCS#1), 公钥为 SubjectPublicKeyInfo 格式 (RSA 也可以是 PKCS#1)。
+/// 未指定 kid 时使用 RFC 7638 JWK Thumbprint
+///
+/// ```sh
+/// openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out rsa.pem
+/// openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out ec.pem
+/// openssl genpkey -algorithm ED25519 -out ed.pem
+/// openssl pkey -in ed.pem -pubout -out ed.pub.pem
+/// ```
+#[derive(Clone)]
+pub struct JwtKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+    pub(crate) public: PublicKey,
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("private", &self.encoding.is_some())
+            .finish()
+    }
+}
+
+impl JwtKey {
+    /// HMAC 密钥 密钥为空时返回 [`JwtError::MissingSecret`]
+    pub fn hmac(algorithm: Algorithm, secret: &[u8]) -> Result<Self, JwtError> {
+        if secret.is_empty() {
+            return Err(JwtError::MissingSecret);
+        }
+        if family(algorithm) != Family::Hmac {
+            return Err(JwtError::UnsupportedAlgorithm(format!(
+                "{:?} 不能使用 HMAC 密钥",
+                algorithm
+            )));
+        }
+        let public = PublicKey::Secret;
+        Ok(JwtKey {
+            kid: public.thumbprint(secret),
+            algorithm,
+            encoding: Some(EncodingKey::from_secret(secret)),
+            decoding: DecodingKey::from_secret(secret),
+            public,
+        })
+    }
+
+    /// 从 PEM 读取私钥或公钥
+    pub fn from_pem(algorithm: Algorithm, pem: &[u8]) -> Result<Self, JwtError> {
+        let (label, der) = pem_decode(pem)?;
+        match label.as_str() {
+            "PRIVATE KEY" | "RSA PRIVATE KEY" => JwtKey::private(algorithm, &der),
+            "PUBLIC KEY" | "RSA PUBLIC KEY" => JwtKey::public(algorithm, &der),
+            "EC PRIVATE KEY" => Err(JwtError::Config(
+                "EC 私钥须为 PKCS#8 格式, 可使用 openssl pkcs8 -topk8 -nocrypt 转换".to_owned(),
+            )),
+            other => Err(JwtError::Config(format!("不支持的 PEM 类型: {}", other))),
+        }
+    }
+
+    /// 从 DER 读取私钥或公钥
+    pub fn from_der(algorithm: Algorithm, der: &[u8]) -> Result<Self, JwtError> {
+        JwtKey::private(algorithm, der).or_else(|_| JwtKey::public(algorithm, der))
+    }
+
+    /// 从文件读取 `-----BEGIN` 开头时按 PEM 解析, 否则按 DER 解析
+    pub fn from_file(algorithm: Algorithm, path: &str) -> Result<Self, JwtError> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| JwtError::Config(format!("读取密钥文件 {} 失败, {}", path, e)))?;
+        if String::from_utf8_lossy(&bytes)
+            .trim_start()
+            .starts_with("-----BEGIN")
+        {
+            JwtKey::from_pem(algorithm, &bytes)
+        } else {
+            JwtKey::from_der(algorithm, &bytes)
+        }
+    }
+
+    /// 指定 kid
+    pub fn with_kid(mut self, kid: &str) -> Self {
+        self.kid = kid.to_owned();
+        self
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// 是否可以签名 (持有私钥)
+    pub fn can_sign(&self) -> bool {
+        self.encoding.is_some()
+    }
+
+    fn private(algorithm: Algorithm, der: &[u8]) -> Result<Self, JwtError> {
+        let rejected = |e: ring::error::KeyRejected| JwtError::Config(format!("私钥无效: {}", e));
+        let (encoding, public) = match family(algorithm) {
+            Family::Hmac => return Err(JwtError::Config("HMAC 请使用 JwtKey::hmac".to_owned())),
+            Family::Rsa => {
+                // jsonwebtoken 签名使用 PKCS#1
+                let (pair, pkcs1) = match signature::RsaKeyPair::from_pkcs8(der) {
+                    Ok(pair) => (pair, pkcs8_inner(der).unwrap_or(der)),
+                    Err(_) => (signature::RsaKeyPair::from_der(der).map_err(rejected)?, der),
+                };
+                let public = PublicKey::Rsa {
+                    n: pair
+                        .public_key()
+                        .modulus()
+                        .big_endian_without_leading_zero()
+                        .to_vec(),
+                    e: pair
+                        .public_key()
+                        .exponent()
+                        .big_endian_without_leading_zero()
+                        .to_vec(),
+                };
+                (EncodingKey::from_rsa_der(pkcs1), public)
+            }
+            Family::Ec => {
+                let signing = match algorithm {
+                    Algorithm::ES384 => &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+                    _ => &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                };
+                let pair = signature::EcdsaKeyPair::from_pkcs8(signing, der).map_err(rejected)?;
+                (
+                    EncodingKey::from_ec_der(der),
+                    ec_point(algorithm, pair.public_key().as_ref())?,
+                )
+            }
+            Family::Ed => {
+                let pair =
+                    signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der).map_err(rejected)?;
+                let x = pair.public_key().as_ref().to_vec();
+                (EncodingKey::from_ed_der(der), PublicKey::Ed { x })
+            }
+        };
+        Ok(JwtKey::build(algorithm, Some(encoding), public))
+    }
+
+    fn public(algorithm: Algorithm, der: &[u8]) -> Result<Self, JwtError> {
+        let invalid = || JwtError::Config(format!("{:?} 公钥无效", algorithm));
+        let public = match family(algorithm) {
+            Family::Hmac => return Err(JwtError::Config("HMAC 请使用 JwtKey::hmac".to_owned())),
+            Family::Rsa => {
+                let pkcs1 = spki_key(der).unwrap_or(der);
+                let (n, e) = rsa_components(pkcs1).ok_or_else(invalid)?;
+                PublicKey::Rsa { n, e }
+            }
+            Family::Ec => ec_point(algorithm, spki_key(der).ok_or_else(invalid)?)?,
+            Family::Ed => match spki_key(der) {
+                Some(x) if x.len() == 32 => PublicKey::Ed { x: x.to_vec() },
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(JwtKey::build(algorithm, None, public))
+    }
+
+    pub(crate) fn build(
+        algorithm: Algorithm,
+        encoding: Option<EncodingKey>,
+        public: PublicKey,
+    ) -> Self {
+        let decoding = match &public {
+            PublicKey::Rsa { n, e } => DecodingKey::from_rsa_raw_components(n, e),
+            PublicKey::Ec { x, y, .. } => DecodingKey::from_ec_der(&[&[4u8][..], x, y].concat()),
+            PublicKey::Ed { x } => DecodingKey::from_ed_der(x),
+            PublicKey::Secret => unreachable!("HMAC 密钥由 JwtKey::hmac 创建"),
+        };
+        JwtKey {
+            kid: public.thumbprint(&[]),
+            algorithm,
+            encoding,
+            decoding,
+            public,
+        }
+    }
+}
+
+/// 未压缩的椭圆曲线点 `04 || x || y`
+pub(crate) fn ec_point(algorithm: Algorithm, point: &[u8]) -> Result<PublicKey, JwtError> {
+    let (curve, size) = match algorithm {
+        Algorithm::ES384 => ("P-384", 48),
+        _ => ("P-256", 32),
+    };
+    if point.len() != 1 + 2 * size || point[0] != 4 {
+        return Err(JwtError::Config(format!(
+            "{:?} 需要 {} 曲线的公钥",
+            algorithm, curve
+        )));
+    }
+    Ok(PublicKey::Ec {
+        curve,
+        x: point[1..=size].to_vec(),
+        y: point[1 + size..].to_vec(),
+    })
+}
+
+fn pem_decode(pem: &[u8]) -> Result<(String, Vec<u8>), JwtError> {
+    let invalid = || JwtError::Config("PEM 格式无效".to_owned());
+    let text = std::str::from_utf8(pem).map_err(|_| invalid())?;
+    let begin = text.find("-----BEGIN ").ok_or_else(invalid)? + "-----BEGIN ".len();
+    let text = &text[begin..];
+    let label_end = text.find("-----").ok_or_else(invalid)?;
+    let label = text[..label_end].trim().to_owned();
+    let text = &text[label_end + "-----".len()..];
+    let body_end = text.find("-----END").ok_or_else(invalid)?;
+    let body: String = text[..body_end]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let der = base64::decode(&body).map_err(|_| invalid())?;
+    Ok((label, der))
+}
+
+/// 读取一个 DER 元素 返回 (tag, 内容, 剩余)
+fn der_next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, b| acc << 8 | *b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// 读取指定类型的 DER 元素 返回 (内容, 剩余)
+fn der_expect(input: &[u8], expected: u8) -> Option<(&[u8], &[u8])> {
+    der_next(input)
+        .filter(|(tag, _, _)| *tag == expected)
+        .map(|(_, content, rest)| (content, rest))
+}
+
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+
+/// SubjectPublicKeyInfo 中的公钥
+fn spki_key(der: &[u8]) -> Option<&[u8]> {
+    let (spki, _) = der_expect(der, SEQUENCE)?;
+    let (_, rest) = der_expect(spki, SEQUENCE)?;
+    match der_expect(rest, BIT_STRING)? {
+        ([0, key @ ..], _) => Some(key),
+        _ => None,
+    }
+}
+
+/// PKCS#8 中的私钥
+fn pkcs8_inner(der: &[u8]) -> Option<&[u8]> {
+    let (info, _) = der_expect(der, SEQUENCE)?;
+    let (_, rest) = der_expect(info, INTEGER)?;
+    let (_, rest) = der_expect(rest, SEQUENCE)?;
+    der_expect(rest, OCTET_STRING).map(|(key, _)| key)
+}
+
+/// PKCS#1 RSAPublicKey 的 (n, e)
+fn rsa_components(der: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (key, _) = der_expect(der, SEQUENCE)?;
+    let (n, rest) = der_expect(key, INTEGER)?;
+    let (e, _) = der_expect(rest, INTEGER)?;
+    let strip = |b: &[u8]| {
+        let start = b.iter().position(|x| *x != 0).unwrap_or(b.len());
+        b[start..].to_vec()
+    };
+    Some((strip(n), strip(e)))
+}
+
+/// 密钥环
+///
+/// 使用当前密钥签名 (token 头部带有 `kid`), 按 `kid` 选择密钥验签。
+/// 轮换时旧密钥保留用于验签, 已签发的 token 不会失效; 待旧 token 全部过期后再移除旧密钥。
+/// 不带 `kid` 的 token (轮换前签发) 使用当前密钥验签
+///
+/// # Examples
+/// ```rust,no_run
+/// use jsonwebtoken::Algorithm;
+/// use yn_util::jwt::{self, JwtConfig, JwtKey, KeyRing};
+///
+/// // 签发方
+/// let key = JwtKey::from_file(Algorithm::ES256, "/run/secrets/jwt-2021-01.pem")?.with_kid("2021-01");
+/// jwt::init(JwtConfig::new().keys(KeyRing::new().signing(key)))?;
+///
+/// // 轮换 旧密钥继续用于验签
+/// let key = JwtKey::from_file(Algorithm::ES256, "/run/secrets/jwt-2021-02.pem")?.with_kid("2021-02");
+/// jwt::rotate(key)?;
+/// // 旧 token 全部过期后
+/// jwt::retire("2021-01")?;
+///
+/// // 网关只持有公钥
+/// let ring = KeyRing::new()
+///     .verifying(JwtKey::from_file(Algorithm::ES256, "/etc/jwt/2021-01.pub.pem")?.with_kid("2021-01"))
+///     .verifying(JwtKey::from_file(Algorithm::ES256, "/etc/jwt/2021-02.pub.pem")?.with_kid("2021-02"));
+/// jwt::init(JwtConfig::new().keys(ring))?;
+/// # Ok::<(), yn_util::jwt::JwtError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct KeyRing {
+    keys: Vec<JwtKey>,
+    current: Option<String>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        KeyRing::default()
+    }
+
+    /// 加入签名密钥 (成为当前密钥)
+    pub fn signing(mut self, key: JwtKey) -> Self {
+        self.rotate(key);
+        self
+    }
+
+    /// 加入仅用于验签的密钥
+    pub fn verifying(mut self, key: JwtKey) -> Self {
+        self.insert(key);
+        self
+    }
+
+    /// 加入密钥 kid 相同时替换
+    pub fn insert(&mut self, key: JwtKey) {
+        match self.keys.iter_mut().find(|k| k.kid == key.kid) {
+            Some(slot) => *slot = key,
+            None => self.keys.push(key),
+        }
+    }
+
+    /// 轮换 新密钥成为当前密钥, 原有密钥保留用于验签
+    pub fn rotate(&mut self, key: JwtKey) {
+        self.current = Some(key.kid.clone());
+        self.insert(key);
+    }
+
+    /// 移除密钥 使用该密钥签发的 token 随即失效
+    pub fn remove(&mut self, kid: &str) -> Option<JwtKey> {
+        let index = self.keys.iter().position(|k| k.kid == kid)?;
+        if self.current.as_deref() == Some(kid) {
+            self.current = None;
+        }
+        Some(self.keys.remove(index))
+    }
+
+    /// 当前签名密钥 未指定时为最后加入的密钥
+    pub fn current(&self) -> Option<&JwtKey> {
+        match &self.current {
+            Some(kid) => self.get(kid),
+            None => self.keys.last(),
+        }
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&JwtKey> {
+        self.keys.iter().find(|k| k.kid == kid)
+    }
+
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// 当前密钥是否可以签名 (持有私钥)
+    pub fn can_sign(&self) -> bool {
+        self.current().is_some_and(JwtKey::can_sign)
+    }
+
+    /// 使用当前密钥签名
+    pub fn encode<T: ser::Serialize>(&self, claims: &T) -> TokenResult<String> {
+        let key = self.current().ok_or(ErrorKind::InvalidKeyFormat)?;
+        let encoding = key.encoding.as_ref().ok_or(ErrorKind::InvalidKeyFormat)?;
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        jsonwebtoken::encode(&header, claims, encoding)
+    }
+
+    /// 按 `kid` 选择密钥验签 只接受该密钥的算法
+    pub fn decode<T: de::DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> TokenResult<TokenData<T>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = match &header.kid {
+            Some(kid) => self.get(kid),
+            None => self.current(),
+        };
+        let key = key.ok_or(ErrorKind::InvalidSignature)?;
+        let mut validation = validation.clone();
+        validation.algorithms = vec![key.algorithm];
+        jsonwebtoken::decode(token, &key.decoding, &validation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // openssl genpkey 生成的测试密钥
+    const RSA: &str = include_str!("testdata/rsa.pem");
+    const RSA_PUB: &str = include_str!("testdata/rsa.pub.pem");
+    const RSA_PKCS1: &str = include_str!("testdata/rsa.pkcs1.pem");
+    const RSA_PKCS1_PUB: &str = include_str!("testdata/rsa.pkcs1.pub.pem");
+    const P256: &str = include_str!("testdata/p256.pem");
+    const P256_PUB: &str = include_str!("testdata/p256.pub.pem");
+    const P256_SEC1: &str = include_str!("testdata/p256.sec1.pem");
+    const P384: &str = include_str!("testdata/p384.pem");
+    const P384_PUB: &str = include_str!("testdata/p384.pub.pem");
+    const ED25519: &str = include_str!("testdata/ed25519.pem");
+    const ED25519_PUB: &str = include_str!("testdata/ed25519.pub.pem");
+
+    fn der(pem: &str, label: &str) -> Vec<u8> {
+        let (actual, der) = pem_decode(pem.as_bytes()).unwrap();
+        assert_eq!(actual, label);
+        der
+    }
+
+    /// 整个输入为一个 SEQUENCE
+    fn single_sequence(der: &[u8]) -> &[u8] {
+        let (tag, content, rest) = der_next(der).unwrap();
+        assert_eq!(tag, SEQUENCE);
+        assert!(rest.is_empty());
+        content
+    }
+
+    #[test]
+    fn pem_and_der() {
+        for (pem, label) in [
+            (RSA, "PRIVATE KEY"),
+            (RSA_PUB, "PUBLIC KEY"),
+            (RSA_PKCS1, "RSA PRIVATE KEY"),
+            (RSA_PKCS1_PUB, "RSA PUBLIC KEY"),
+            (P256, "PRIVATE KEY"),
+            (P256_PUB, "PUBLIC KEY"),
+            (P384, "PRIVATE KEY"),
+            (P384_PUB, "PUBLIC KEY"),
+            (ED25519, "PRIVATE KEY"),
+            (ED25519_PUB, "PUBLIC KEY"),
+        ] {
+            single_sequence(&der(pem, label));
+        }
+        assert!(pem_decode(b"-----BEGIN PUBLIC KEY-----\n!!!\n-----END PUBLIC KEY-----").is_err());
+        assert!(pem_decode(b"MIIB").is_err());
+    }
+
+    #[test]
+    fn der_lengths() {
+        assert_eq!(
+            der_next(&[0x04, 0x01, 0xaa, 0xbb]),
+            Some((0x04, &[0xaa][..], &[0xbb][..]))
+        );
+        let long = [&[0x04, 0x81, 0x80][..], &[0u8; 0x80]].concat();
+        assert_eq!(der_next(&long).unwrap().1.len(), 0x80);
+        // 长度超出输入 / 不定长
+        assert_eq!(der_next(&[0x04, 0x02, 0xaa]), None);
+        assert_eq!(der_next(&[0x04, 0x80, 0xaa]), None);
+        assert_eq!(der_next(&[0x04]), None);
+    }
+
+    #[test]
+    fn rsa_structures() {
+        // PKCS#8 内层与 SPKI 中的公钥分别为 PKCS#1 私钥与公钥
+        let pkcs8 = der(RSA, "PRIVATE KEY");
+        assert_eq!(
+            pkcs8_inner(&pkcs8).unwrap(),
+            &der(RSA_PKCS1, "RSA PRIVATE KEY")[..]
+        );
+        let spki = der(RSA_PUB, "PUBLIC KEY");
+        let pkcs1 = der(RSA_PKCS1_PUB, "RSA PUBLIC KEY");
+        assert_eq!(spki_key(&spki).unwrap(), &pkcs1[..]);
+        let (n, e) = rsa_components(&pkcs1).unwrap();
+        assert_eq!(n.len(), 256);
+        assert_eq!(e, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn ec_structures() {
+        for (pem, pub_pem, signing, size) in [
+            (
+                P256,
+                P256_PUB,
+                &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                65,
+            ),
+            (
+                P384,
+                P384_PUB,
+                &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+                97,
+            ),
+        ] {
+            let pkcs8 = der(pem, "PRIVATE KEY");
+            let pair = signature::EcdsaKeyPair::from_pkcs8(signing, &pkcs8).unwrap();
+            // 内层为 SEC1 ECPrivateKey
+            single_sequence(pkcs8_inner(&pkcs8).unwrap());
+            let spki = der(pub_pem, "PUBLIC KEY");
+            let point = spki_key(&spki).unwrap();
+            assert_eq!(point.len(), size);
+            assert_eq!(point, pair.public_key().as_ref());
+        }
+    }
+
+    #[test]
+    fn ed_structures() {
+        let pkcs8 = der(ED25519, "PRIVATE KEY");
+        let pair = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8).unwrap();
+        // 内层为 32 字节种子
+        let (seed, rest) = der_expect(pkcs8_inner(&pkcs8).unwrap(), OCTET_STRING).unwrap();
+        assert_eq!(seed.len(), 32);
+        assert!(rest.is_empty());
+        let spki = der(ED25519_PUB, "PUBLIC KEY");
+        let x = spki_key(&spki).unwrap();
+        assert_eq!(x, pair.public_key().as_ref());
+    }
+
+    #[test]
+    fn sign_with_private_verify_with_public() {
+        let claims = serde_json::json!({ "exp": u64::MAX, "id": "10086" });
+        for (algorithm, pem, pub_pem) in [
+            (Algorithm::RS256, RSA, RSA_PUB),
+            (Algorithm::PS256, RSA, RSA_PUB),
+            (Algorithm::RS384, RSA_PKCS1, RSA_PKCS1_PUB),
+            (Algorithm::ES256, P256, P256_PUB),
+            (Algorithm::ES384, P384, P384_PUB),
+            (Algorithm::EdDSA, ED25519, ED25519_PUB),
+        ] {
+            let private = JwtKey::from_pem(algorithm, pem.as_bytes()).unwrap();
+            let public = JwtKey::from_pem(algorithm, pub_pem.as_bytes()).unwrap();
+            assert!(private.can_sign());
+            assert!(!public.can_sign());
+            assert_eq!(private.kid(), public.kid(), "{:?}", algorithm);
+
+            let signing = KeyRing::new().signing(private);
+            assert!(signing.can_sign());
+            let token = signing.encode(&claims).unwrap();
+            let ring = KeyRing::new().verifying(public);
+            let data = ring
+                .decode::<serde_json::Value>(&token, &Validation::new(algorithm))
+                .unwrap();
+            assert_eq!(data.claims["id"], "10086");
+            assert!(!ring.can_sign());
+            assert!(ring.encode(&claims).is_err());
+        }
+    }
+
+    #[test]
+    fn rejected_keys() {
+        assert!(matches!(
+            JwtKey::from_pem(Algorithm::ES256, P256_SEC1.as_bytes()),
+            Err(JwtError::Config(_))
+        ));
+        // 曲线不匹配
+        assert!(JwtKey::from_pem(Algorithm::ES384, P256_PUB.as_bytes()).is_err());
+        assert!(JwtKey::from_pem(Algorithm::EdDSA, RSA_PUB.as_bytes()).is_err());
+        assert!(JwtKey::from_pem(Algorithm::HS256, RSA.as_bytes()).is_err());
+    }
+}
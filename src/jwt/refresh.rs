@@ -0,0 +1,354 @@
+use super::*;
+use crate::caches::{Cache, CacheResult, DEFAULT};
+use crate::session::new_id;
+use crate::utils::{BusinessError, Resp};
+
+/// 创建令牌族并加入用户索引
+const ISSUE_SCRIPT: &str = r#"
+redis.call("HSET", KEYS[1], "current", ARGV[1], "user_id", ARGV[2], "name", ARGV[3], "created", ARGV[4])
+redis.call("PEXPIRE", KEYS[1], ARGV[5])
+redis.call("SADD", KEYS[2], ARGV[6])
+redis.call("PEXPIRE", KEYS[2], ARGV[5])
+return 1
+"#;
+
+/// 校验并轮换刷新令牌 用户索引随令牌族续期
+///
+/// 返回 `{状态, 用户id, 用户名称}` 状态 1.轮换成功 0.无效或已过期 -1.重复使用 (整个令牌族已注销)
+const ROTATE_SCRIPT: &str = r#"
+local family = redis.call("HMGET", KEYS[1], "current", "user_id", "name")
+if not family[1] then
+    return {0, "", ""}
+end
+if family[1] == ARGV[1] then
+    redis.call("HSET", KEYS[1], "current", ARGV[2])
+    redis.call("PEXPIRE", KEYS[1], ARGV[3])
+    redis.call("SET", KEYS[2], 1, "PX", ARGV[3])
+    redis.call("PEXPIRE", KEYS[3], ARGV[3])
+    return {1, family[2], family[3]}
+end
+if redis.call("EXISTS", KEYS[2]) == 1 then
+    redis.call("DEL", KEYS[1])
+    redis.call("SREM", KEYS[3], ARGV[4])
+    return {-1, family[2], family[3]}
+end
+return {0, "", ""}
+"#;
+
+/// 注销令牌所在的令牌族 令牌须为当前或已使用过的令牌
+const REVOKE_SCRIPT: &str = r#"
+if redis.call("HGET", KEYS[1], "current") == ARGV[1] or redis.call("EXISTS", KEYS[2]) == 1 then
+    redis.call("SREM", KEYS[3], ARGV[2])
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// 注销索引中属于该用户的令牌族 同时移除已过期的令牌族
+///
+/// `KEYS[1]` 为用户索引, 之后为各令牌族; `ARGV[1]` 为用户 id, 之后为对应的令牌族 id
+const REVOKE_USER_SCRIPT: &str = r#"
+local revoked = 0
+for i = 2, #KEYS do
+    local owner = redis.call("HGET", KEYS[i], "user_id")
+    if owner == ARGV[1] then
+        revoked = revoked + redis.call("DEL", KEYS[i])
+        redis.call("SREM", KEYS[1], ARGV[i])
+    elseif not owner then
+        redis.call("SREM", KEYS[1], ARGV[i])
+    end
+end
+return revoked
+"#;
+
+/// 访问令牌与刷新令牌
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    /// 固定为 `Bearer`
+    pub token_type: String,
+    /// 访问令牌有效期 秒
+    pub expires_in: u64,
+    pub refresh_token: String,
+    /// 刷新令牌有效期 秒
+    pub refresh_expires_in: u64,
+}
+
+impl TokenPair {
+    /// 转为统一响应结构
+    pub fn to_resp(self) -> Resp<TokenPair> {
+        Resp::ok(Some(self), "ok", None, None, None)
+    }
+}
+
+/// 签发访问令牌与刷新令牌
+///
+/// 访问令牌为短期 jwt ([`UserToken`]); 刷新令牌为随机串, redis 中只保存其 SHA-256。
+/// 一次登录签发的刷新令牌属于同一令牌族, 每次刷新都换发新的刷新令牌, 旧令牌随即失效;
+/// 已使用过的刷新令牌再次出现 (可能已泄露) 时注销整个令牌族, 该次登录须重新认证。
+/// 刷新令牌 `refresh_ttl` 内未使用则过期, 使用后重新计时。
+///
+/// 刷新令牌为 `{用户标签}.{令牌族}.{随机串}`, 用户标签由用户 id 的 SHA-256 截取,
+/// 同一用户的令牌族与用户索引使用该标签作为 hash tag, 在集群中位于同一 slot
+///
+/// # Examples
+/// ```rust,no_run
+/// use actix_web::{web, HttpResponse};
+/// use yn_util::jwt::TokenIssuer;
+/// use yn_util::utils::BusinessError;
+///
+/// #[derive(serde::Deserialize)]
+/// struct Refresh {
+///     refresh_token: String,
+/// }
+///
+/// async fn login() -> Result<HttpResponse, BusinessError> {
+///     // 校验用户名密码后
+///     TokenIssuer::new().issue("5fa0a0a0a0a0a0a0a0a0a0a0", "admin").await?.to_resp().to_json_result()
+/// }
+///
+/// async fn refresh(form: web::Json<Refresh>) -> Result<HttpResponse, BusinessError> {
+///     TokenIssuer::new().refresh(&form.refresh_token).await?.to_resp().to_json_result()
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TokenIssuer {
+    name: String,
+    prefix: String,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+}
+
+impl Default for TokenIssuer {
+    fn default() -> Self {
+        TokenIssuer::named(DEFAULT)
+    }
+}
+
+impl TokenIssuer {
+    /// 使用默认缓存
+    pub fn new() -> Self {
+        TokenIssuer::default()
+    }
+
+    /// 使用命名缓存 (缓存在首次访问时获取, 可先于缓存初始化创建)
+    pub fn named(name: &str) -> Self {
+        TokenIssuer {
+            name: name.to_owned(),
+            prefix: "refresh".to_owned(),
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(14 * DAY_ONE),
+        }
+    }
+
+    /// 设置 key 前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// 访问令牌有效期 默认 15 分钟
+    pub fn access_ttl(mut self, ttl: Duration) -> Self {
+        self.access_ttl = ttl;
+        self
+    }
+
+    /// 刷新令牌有效期 默认 14 天
+    pub fn refresh_ttl(mut self, ttl: Duration) -> Self {
+        self.refresh_ttl = ttl.max(Duration::from_secs(1));
+        self
+    }
+
+    fn cache(&self) -> CacheResult<Cache> {
+        Ok(Cache::named(&self.name)?.prefix(&self.prefix))
+    }
+
+    fn refresh_millis(&self) -> u64 {
+        self.refresh_ttl.as_millis() as u64
+    }
+
+    /// 令牌族、已使用令牌与用户索引的 key
+    fn family_keys(cache: &Cache, tag: &str, family: &str, hash: &str) -> [String; 3] {
+        [
+            cache.key(&tagged(tag, family)),
+            cache.key(&tagged(tag, &format!("{}:used:{}", family, hash))),
+            TokenIssuer::user_key(cache, tag),
+        ]
+    }
+
+    fn user_key(cache: &Cache, tag: &str) -> String {
+        cache.key(&tagged(tag, "families"))
+    }
+
+    fn hash(refresh_token: &str) -> String {
+        let digest = ring::digest::digest(&ring::digest::SHA256, refresh_token.as_bytes());
+        base64::encode_config(digest.as_ref(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn pair(
+        &self,
+        id: &str,
+        name: &str,
+        refresh_token: String,
+    ) -> Result<TokenPair, BusinessError> {
+        let claims = UserToken::new(id, name, self.access_ttl);
+        let access_token = encode_claims(&claims)
+            .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+        Ok(TokenPair {
+            access_token,
+            token_type: "Bearer".to_owned(),
+            expires_in: self.access_ttl.as_secs(),
+            refresh_token,
+            refresh_expires_in: self.refresh_ttl.as_secs(),
+        })
+    }
+
+    /// 登录后签发 (新的令牌族)
+    pub async fn issue(&self, id: &str, name: &str) -> Result<TokenPair, BusinessError> {
+        let cache = self.cache()?;
+        let tag = user_tag(id);
+        let family = new_id();
+        let refresh_token = format!("{}.{}.{}", tag, family, new_id());
+        let [family_key, _, user_key] = TokenIssuer::family_keys(&cache, &tag, &family, "");
+        // 先签发访问令牌, 失败时不创建令牌族
+        let pair = self.pair(id, name, refresh_token.clone())?;
+        let args = [
+            TokenIssuer::hash(&refresh_token),
+            id.to_owned(),
+            name.to_owned(),
+            date_time::timestamp().to_string(),
+            self.refresh_millis().to_string(),
+            family,
+        ];
+        cache
+            .backend()
+            .eval::<i64>(ISSUE_SCRIPT, &[family_key, user_key], &args)
+            .await?;
+        Ok(pair)
+    }
+
+    /// 使用刷新令牌换发新的令牌对
+    pub async fn refresh(&self, refresh_token: &str) -> Result<TokenPair, BusinessError> {
+        let (tag, family) = parse_token(refresh_token)?;
+        // 轮换后旧令牌即视为已使用, 无法签发访问令牌时不能轮换,
+        // 否则客户端拿不到新令牌, 重试旧令牌会注销整个令牌族
+        if !config().keys.can_sign() {
+            return Err(JwtError::MissingSecret.into());
+        }
+        let cache = self.cache()?;
+        let hash = TokenIssuer::hash(refresh_token);
+        let next = format!("{}.{}.{}", tag, family, new_id());
+        let args = [
+            hash.clone(),
+            TokenIssuer::hash(&next),
+            self.refresh_millis().to_string(),
+            family.to_owned(),
+        ];
+        let keys = TokenIssuer::family_keys(&cache, tag, family, &hash);
+        let (status, id, name): (i64, String, String) =
+            cache.backend().eval(ROTATE_SCRIPT, &keys, &args).await?;
+        match status {
+            1 => self.pair(&id, &name, next),
+            -1 => {
+                log::warn!("刷新令牌被重复使用, 已注销用户 {} 的令牌族 {}", id, family);
+                Err(BusinessError::Unauthorized)
+            }
+            _ => Err(BusinessError::Unauthorized),
+        }
+    }
+
+    /// 注销刷新令牌所在的令牌族 (退出登录) 返回是否存在
+    pub async fn revoke(&self, refresh_token: &str) -> Result<bool, BusinessError> {
+        let (tag, family) = parse_token(refresh_token)?;
+        let cache = self.cache()?;
+        let hash = TokenIssuer::hash(refresh_token);
+        let keys = TokenIssuer::family_keys(&cache, tag, family, &hash);
+        let args = [hash, family.to_owned()];
+        let revoked: i64 = cache.backend().eval(REVOKE_SCRIPT, &keys, &args).await?;
+        Ok(revoked == 1)
+    }
+
+    /// 注销用户的全部令牌族 返回注销的数量
+    pub async fn revoke_user(&self, user_id: &str) -> Result<i64, BusinessError> {
+        let cache = self.cache()?;
+        let tag = user_tag(user_id);
+        let user_key = TokenIssuer::user_key(&cache, &tag);
+        let families: Vec<String> = cache
+            .backend()
+            .query(redis::cmd("SMEMBERS").arg(&user_key))
+            .await?;
+        if families.is_empty() {
+            return Ok(0);
+        }
+        let mut keys = vec![user_key];
+        let mut args = vec![user_id.to_owned()];
+        for family in families {
+            keys.push(cache.key(&tagged(&tag, &family)));
+            args.push(family);
+        }
+        let revoked = cache
+            .backend()
+            .eval(REVOKE_USER_SCRIPT, &keys, &args)
+            .await?;
+        Ok(revoked)
+    }
+}
+
+/// 用户标签 同一用户的 key 使用相同的 hash tag
+fn user_tag(user_id: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, user_id.as_bytes());
+    base64::encode_config(&digest.as_ref()[..12], base64::URL_SAFE_NO_PAD)
+}
+
+/// `{tag}:part`
+fn tagged(tag: &str, part: &str) -> String {
+    format!("{{{}}}:{}", tag, part)
+}
+
+/// 刷新令牌中的 (用户标签, 令牌族)
+fn parse_token(refresh_token: &str) -> Result<(&str, &str), BusinessError> {
+    let mut parts = refresh_token.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(tag), Some(family), Some(secret))
+            if !tag.is_empty() && !family.is_empty() && !secret.is_empty() =>
+        {
+            Ok((tag, family))
+        }
+        _ => Err(BusinessError::Unauthorized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_parts() {
+        let tag = user_tag("5fa0a0a0a0a0a0a0a0a0a0a0");
+        assert_eq!(tag.len(), 16);
+        assert_eq!(tag, user_tag("5fa0a0a0a0a0a0a0a0a0a0a0"));
+        assert_ne!(tag, user_tag("5fa0a0a0a0a0a0a0a0a0a0a1"));
+        assert!(!tag.contains('.'));
+
+        let token = format!("{}.{}.{}", tag, new_id(), new_id());
+        let (parsed, family) = parse_token(&token).unwrap();
+        assert_eq!(parsed, tag);
+        assert_eq!(family.len(), 43);
+        for token in ["", "a.b", "a..c", ".b.c", "a.b."] {
+            assert!(parse_token(token).is_err(), "{}", token);
+        }
+    }
+
+    #[test]
+    fn keys_share_user_slot() {
+        let tag = user_tag("10086");
+        let hash_tag = format!("{{{}}}:", tag);
+        for key in [
+            tagged(&tag, "family"),
+            tagged(&tag, "family:used:hash"),
+            tagged(&tag, "families"),
+        ] {
+            assert!(key.starts_with(&hash_tag), "{}", key);
+        }
+    }
+}
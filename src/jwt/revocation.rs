@@ -0,0 +1,300 @@
+use super::*;
+use crate::caches::{degrade, Cache, CacheResult, DEFAULT};
+use crate::utils::BusinessError;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    // 进程内注销记录 缓存不可用时仍然生效 (仅限本进程)
+    static ref LOCAL: Mutex<LocalRevocations> = Mutex::new(LocalRevocations {
+        tokens: LruCache::new(100_000),
+        users: HashMap::new(),
+    });
+}
+
+/// 进程内注销记录 值为记录的过期时间 (秒)
+struct LocalRevocations {
+    /// jti -> token 过期时间
+    tokens: LruCache<String, u64>,
+    /// 用户 id -> (not_before, 过期时间)
+    users: HashMap<String, (u64, u64)>,
+}
+
+impl LocalRevocations {
+    fn is_revoked(&mut self, claims: &UserToken, now: u64) -> bool {
+        if !claims.jti.is_empty() {
+            match self.tokens.get(&claims.jti) {
+                Some(expires) if *expires > now => return true,
+                Some(_) => {
+                    self.tokens.pop(&claims.jti);
+                }
+                None => {}
+            }
+        }
+        match self.users.get(&claims.id) {
+            Some((not_before, expires)) if *expires > now => claims.iat < *not_before,
+            _ => false,
+        }
+    }
+
+    fn revoke_user(&mut self, user_id: &str, not_before: u64, expires: u64) {
+        self.users.retain(|_, (_, e)| *e > not_before);
+        self.users.insert(user_id.to_owned(), (not_before, expires));
+    }
+}
+
+/// 是否已在本进程中注销 (不访问缓存) 用于同步解码
+pub(crate) fn revoked_locally(claims: &UserToken) -> bool {
+    LOCAL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_revoked(claims, date_time::timestamp())
+}
+
+/// claims 中用于检查注销记录的字段
+#[derive(Deserialize)]
+struct Subject {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    jti: String,
+    #[serde(default)]
+    iat: u64,
+}
+
+/// 检查 claims 是否已注销 (全局配置的 [`Revocations`])
+///
+/// `id` / `jti` / `iat` 类型不符时无法检查, 视为无效 token
+pub(crate) async fn ensure_active(claims: &serde_json::Value) -> Result<(), BusinessError> {
+    let subject: Subject =
+        serde_json::from_value(claims.clone()).map_err(|_| BusinessError::Unauthorized)?;
+    if subject.id.is_empty() && subject.jti.is_empty() {
+        return Ok(());
+    }
+    let claims = UserToken {
+        id: subject.id,
+        jti: subject.jti,
+        iat: subject.iat,
+        ..Default::default()
+    };
+    if config().revocations.is_revoked(&claims).await? {
+        return Err(BusinessError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// token 注销记录
+///
+/// 单个 token 按 `jti` 注销, 记录在 token 过期时一并过期;
+/// 注销用户 (退出全部设备) 时记录当前时间, 此前签发 (`iat` 更早) 的 token 全部失效。
+/// 记录写入缓存与本进程内存, 缓存故障时 (降级模式) 只使用内存记录。
+///
+/// [`decode`] 只检查本进程的记录, [`verify`] 同时检查缓存。
+/// 刷新令牌需另外通过 [`TokenIssuer::revoke_user`] 注销
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::jwt::{self, JwtConfig, Revocations};
+///
+/// # async fn run(token: &str) -> Result<(), yn_util::utils::BusinessError> {
+/// jwt::init(JwtConfig::from_env()?.revocations(Revocations::named("auth")))?;
+///
+/// // 退出登录
+/// let data = jwt::verify(token).await?;
+/// jwt::config().revocations.revoke(&data.claims).await?;
+///
+/// // 修改密码后退出全部设备
+/// jwt::config().revocations.revoke_user(&data.claims.id).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Revocations {
+    name: String,
+    prefix: String,
+    max_ttl: Option<Duration>,
+}
+
+impl Default for Revocations {
+    fn default() -> Self {
+        Revocations::named(DEFAULT)
+    }
+}
+
+impl Revocations {
+    /// 使用默认缓存
+    pub fn new() -> Self {
+        Revocations::default()
+    }
+
+    /// 使用命名缓存 (缓存在首次访问时获取, 可先于缓存初始化创建)
+    pub fn named(name: &str) -> Self {
+        Revocations {
+            name: name.to_owned(),
+            prefix: "revoked".to_owned(),
+            max_ttl: None,
+        }
+    }
+
+    /// 设置 key 前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// 签发的 token 最长有效期 注销用户的记录保留该时间, 默认为全局配置的 `ttl`
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = Some(ttl);
+        self
+    }
+
+    fn cache(&self) -> CacheResult<Cache> {
+        Ok(Cache::named(&self.name)?.prefix(&self.prefix))
+    }
+
+    /// 同一用户的记录使用相同的 hash tag, 在集群中位于同一 slot
+    fn keys(cache: &Cache, claims: &UserToken) -> [String; 2] {
+        [
+            cache.key(&format!("{{{}}}:nbf", claims.id)),
+            cache.key(&format!("{{{}}}:jti:{}", claims.id, claims.jti)),
+        ]
+    }
+
+    /// 注销单个 token 已过期或没有 `jti` 的 token 无需注销
+    pub async fn revoke(&self, claims: &UserToken) -> Result<(), BusinessError> {
+        let now = date_time::timestamp();
+        // 保留到校验时允许的时钟误差之后
+        let expires = claims.exp + config().leeway;
+        if claims.jti.is_empty() || expires <= now {
+            return Ok(());
+        }
+        LOCAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .tokens
+            .put(claims.jti.clone(), expires);
+        let write = async {
+            let cache = self.cache()?;
+            let [_, key] = Revocations::keys(&cache, claims);
+            cache
+                .backend()
+                .query::<()>(
+                    redis::cmd("SET")
+                        .arg(key)
+                        .arg(1)
+                        .arg("EX")
+                        .arg(expires - now),
+                )
+                .await
+        };
+        degrade(write.await)?;
+        Ok(())
+    }
+
+    /// 注销用户此前签发的全部 token
+    pub async fn revoke_user(&self, user_id: &str) -> Result<(), BusinessError> {
+        let config = config();
+        let now = date_time::timestamp();
+        let ttl = self.max_ttl.unwrap_or(config.ttl).as_secs() + config.leeway;
+        LOCAL
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .revoke_user(user_id, now, now + ttl);
+        let write = async {
+            let cache = self.cache()?;
+            let claims = UserToken {
+                id: user_id.to_owned(),
+                ..Default::default()
+            };
+            let [key, _] = Revocations::keys(&cache, &claims);
+            cache
+                .backend()
+                .query::<()>(
+                    redis::cmd("SET")
+                        .arg(key)
+                        .arg(now)
+                        .arg("EX")
+                        .arg(ttl.max(1)),
+                )
+                .await
+        };
+        degrade(write.await)?;
+        Ok(())
+    }
+
+    /// 是否已注销
+    pub async fn is_revoked(&self, claims: &UserToken) -> Result<bool, BusinessError> {
+        if revoked_locally(claims) {
+            return Ok(true);
+        }
+        let read = async {
+            let cache = self.cache()?;
+            let [not_before, jti] = Revocations::keys(&cache, claims);
+            let mut cmd = redis::cmd("MGET");
+            cmd.arg(not_before);
+            if !claims.jti.is_empty() {
+                cmd.arg(jti);
+            }
+            cache.backend().query::<Vec<Option<u64>>>(&cmd).await
+        };
+        let values = match degrade(read.await)? {
+            Some(values) => values,
+            None => return Ok(false),
+        };
+        let revoked = match values.as_slice() {
+            [not_before, jti] => jti.is_some() || not_before.is_some_and(|nb| claims.iat < nb),
+            [not_before] => not_before.is_some_and(|nb| claims.iat < nb),
+            _ => false,
+        };
+        Ok(revoked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mistyped_claims_are_unauthorized() {
+        actix_web::rt::System::new("test").block_on(async move {
+            for claims in [
+                serde_json::json!({ "id": 10086 }),
+                serde_json::json!({ "id": "10086", "iat": "yesterday" }),
+                serde_json::json!("10086"),
+            ] {
+                assert!(matches!(
+                    ensure_active(&claims).await,
+                    Err(BusinessError::Unauthorized)
+                ));
+            }
+            // 没有 id / jti 的自定义 claims 无需检查
+            assert!(ensure_active(&serde_json::json!({ "exp": 1 }))
+                .await
+                .is_ok());
+        });
+    }
+
+    #[test]
+    fn local_records() {
+        let mut local = LocalRevocations {
+            tokens: LruCache::new(10),
+            users: HashMap::new(),
+        };
+        let token = |jti: &str, iat: u64| UserToken {
+            id: "10086".to_owned(),
+            jti: jti.to_owned(),
+            iat,
+            ..Default::default()
+        };
+        local.tokens.put("a".to_owned(), 200);
+        assert!(local.is_revoked(&token("a", 50), 100));
+        assert!(!local.is_revoked(&token("a", 50), 200));
+        assert!(!local.is_revoked(&token("b", 50), 100));
+
+        local.revoke_user("10086", 100, 300);
+        assert!(local.is_revoked(&token("b", 99), 150));
+        assert!(!local.is_revoked(&token("b", 100), 150));
+        assert!(!local.is_revoked(&token("b", 99), 300));
+    }
+}
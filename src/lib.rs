@@ -8,6 +8,9 @@ pub mod jwt;
 pub mod utils;
 pub mod dao;
 pub mod caches;
+pub mod normalize;
+pub mod middleware;
+pub mod session;
 
 #[macro_use]
 extern crate lazy_static;
@@ -0,0 +1,223 @@
+use super::*;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
+
+mod auth;
+mod idempotency;
+mod rate_limit;
+mod session;
+pub use auth::*;
+pub use idempotency::*;
+pub use rate_limit::*;
+pub use session::*;
+
+/// 客户端 ip (tcp 对端地址)
+///
+/// 不读取 Forwarded / X-Forwarded-For, 这些请求头可由客户端任意伪造;
+/// 部署在反向代理之后时使用 [`forwarded_ip`]
+pub fn client_ip(req: &ServiceRequest) -> Option<String> {
+    req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+/// 经过可信代理时的客户端 ip
+///
+/// 只有对端地址属于 trusted 时才读取 Forwarded (优先) / X-Forwarded-For,
+/// 从右向左跳过可信代理, 取第一个不可信的地址; 否则与 [`client_ip`] 相同
+pub fn forwarded_ip(req: &ServiceRequest, trusted: &[IpAddr]) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    Some(forwarded_client(peer, req.headers(), trusted).to_string())
+}
+
+fn forwarded_client(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    if !trusted.contains(&peer) {
+        return client;
+    }
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        match hop {
+            Some(ip) => client = ip,
+            // 无法识别的地址 (如 `unknown`) 之前的部分不再可信
+            None => break,
+        }
+        if !trusted.contains(&client) {
+            break;
+        }
+    }
+    client
+}
+
+/// 代理链上的地址 从客户端到最近的代理
+///
+/// 同名请求头出现多行时 actix-http 不保证顺序, 视为无法识别
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        let lines: Vec<_> = headers.get_all(name).collect();
+        match lines.as_slice() {
+            [] => vec![],
+            [line] => match line.to_str() {
+                Ok(line) => line.split(',').collect(),
+                Err(_) => vec![""],
+            },
+            _ => vec![""],
+        }
+    };
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    match name.eq_ignore_ascii_case("for") {
+                        true => Some(parse_node(value)),
+                        false => None,
+                    }
+                })?
+            })
+            .collect();
+    }
+    values("x-forwarded-for")
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// `1.2.3.4` / `1.2.3.4:80` / `"[2001:db8::1]:80"` / `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse()
+        .or_else(|_| node.parse::<SocketAddr>().map(|a| a.ip()))
+        .ok()
+}
+
+/// 请求头 `Authorization: Bearer <token>` 中的 token 其他认证方案 (如 `Basic`) 返回 None
+pub fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    header_token(req.headers())
+}
+
+pub(crate) fn header_token(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = match value.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("bearer ") => &value[7..],
+        _ => return None,
+    };
+    match token.trim() {
+        "" => None,
+        token => Some(token.to_owned()),
+    }
+}
+
+/// 请求头 token 中的用户 id (默认 `jwt::UserToken`) 已注销的 token 返回 None
+pub async fn token_user_id(req: &ServiceRequest) -> Option<String> {
+    let token = bearer_token(req)?;
+    jwt::verify(&token).await.ok().map(|data| data.claims.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+
+    fn headers(pairs: &[(header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(name.clone(), HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn xff() -> header::HeaderName {
+        header::HeaderName::from_static("x-forwarded-for")
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_headers() {
+        let h = headers(&[(xff(), "1.1.1.1")]);
+        assert_eq!(forwarded_client(ip("9.9.9.9"), &h, &[]), ip("9.9.9.9"));
+        let trusted = [ip("10.0.0.1")];
+        assert_eq!(forwarded_client(ip("9.9.9.9"), &h, &trusted), ip("9.9.9.9"));
+    }
+
+    #[test]
+    fn skips_trusted_hops_from_right() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        // 客户端伪造的 6.6.6.6 位于最左, 不会被采用
+        let h = headers(&[(xff(), "6.6.6.6, 1.1.1.1, 10.0.0.2")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("1.1.1.1")
+        );
+        let h = headers(&[(xff(), "1.1.1.1:5000, 10.0.0.2")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("1.1.1.1")
+        );
+        // 多行请求头无法确定顺序, 不采用
+        let h = headers(&[(xff(), "6.6.6.6"), (xff(), "1.1.1.1")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.1")
+        );
+        // 全部为可信代理时取最左
+        let h = headers(&[(xff(), "10.0.0.2")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.2")
+        );
+        // 无法识别的地址之前的部分不可信
+        let h = headers(&[(xff(), "1.1.1.1, unknown")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_header_preferred() {
+        let trusted = [ip("10.0.0.1")];
+        let h = headers(&[
+            (xff(), "6.6.6.6"),
+            (
+                header::FORWARDED,
+                "for=\"[2001:db8::1]:4711\";proto=https, For=10.0.0.1",
+            ),
+        ]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("2001:db8::1")
+        );
+        let h = headers(&[(header::FORWARDED, "proto=https")]);
+        assert_eq!(
+            forwarded_client(ip("10.0.0.1"), &h, &trusted),
+            ip("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn bearer_scheme_only() {
+        let token = |value: &'static str| header_token(&headers(&[(header::AUTHORIZATION, value)]));
+        assert_eq!(token("Bearer abc"), Some("abc".to_owned()));
+        assert_eq!(token("bearer  abc "), Some("abc".to_owned()));
+        assert_eq!(token("Bearer "), None);
+        assert_eq!(token("Basic dXNlcjpwdw=="), None);
+        assert_eq!(token("abc.def.ghi"), None);
+        assert_eq!(header_token(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn parse_nodes() {
+        assert_eq!(parse_node(" 1.2.3.4 "), Some(ip("1.2.3.4")));
+        assert_eq!(parse_node("1.2.3.4:80"), Some(ip("1.2.3.4")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("_hidden"), None);
+    }
+}
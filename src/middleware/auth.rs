@@ -0,0 +1,414 @@
+use super::*;
+use crate::jwt::{Jwks, UserToken};
+use crate::utils::{BusinessError, Resp};
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, ServiceResponse};
+use actix_web::http::{HeaderValue, Method};
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// 已通过认证的 token 放入请求扩展中
+#[derive(Clone)]
+struct Authenticated {
+    claims: Value,
+}
+
+/// token 来源
+#[derive(Clone, Debug)]
+enum TokenSource {
+    /// `Authorization: Bearer <token>`
+    Header,
+    Cookie(String),
+    Query(String),
+}
+
+impl TokenSource {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        match self {
+            TokenSource::Header => bearer_token(req),
+            TokenSource::Cookie(name) => req
+                .cookie(name)
+                .map(|c| c.value().to_owned())
+                .filter(|v| !v.is_empty()),
+            TokenSource::Query(name) => {
+                web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                    .ok()?
+                    .into_inner()
+                    .remove(name)
+                    .filter(|v| !v.is_empty())
+            }
+        }
+    }
+}
+
+/// 认证中间件
+///
+/// 依次从 `Authorization` 请求头与配置的 cookie / 查询参数中读取 token, 使用全局密钥环
+/// (或 [`Jwks`]) 验签并检查注销记录, 通过后可以在 handler 中使用 [`AuthUser`] 读取 claims。
+/// 认证失败时返回 401 与 `Resp::err(401, ...)`
+///
+/// 公开路径不要求认证, 带有 token 时尝试认证 (有效时仍可读取 [`AuthUser`], 无效时按未登录处理);
+/// CORS 预检 (OPTIONS) 请求直接放行
+///
+/// # Examples
+/// ```rust,no_run
+/// use actix_web::{web, App, HttpResponse};
+/// use yn_util::jwt::UserToken;
+/// use yn_util::middleware::{AuthUser, Authentication};
+///
+/// #[derive(serde::Deserialize)]
+/// struct AdminClaims {
+///     id: String,
+///     role: String,
+/// }
+///
+/// async fn me(user: AuthUser) -> HttpResponse {
+///     HttpResponse::Ok().body(user.name.clone())
+/// }
+///
+/// async fn admin(user: AuthUser<AdminClaims>) -> HttpResponse {
+///     HttpResponse::Ok().body(user.role.clone())
+/// }
+///
+/// let app = App::new()
+///     .wrap(
+///         Authentication::new()
+///             .cookie("access_token")
+///             .public("/login")
+///             .public("/public/*"),
+///     )
+///     .route("/me", web::get().to(me))
+///     .route("/admin", web::get().to(admin));
+/// ```
+#[derive(Clone)]
+pub struct Authentication {
+    sources: Vec<TokenSource>,
+    public: Vec<String>,
+    jwks: Option<Jwks>,
+    revocation: bool,
+    message: String,
+}
+
+impl Default for Authentication {
+    fn default() -> Self {
+        Authentication {
+            sources: vec![TokenSource::Header],
+            public: vec![],
+            jwks: None,
+            revocation: true,
+            message: BusinessError::Unauthorized.to_string(),
+        }
+    }
+}
+
+impl Authentication {
+    /// 默认只读取 `Authorization` 请求头
+    pub fn new() -> Self {
+        Authentication::default()
+    }
+
+    /// 同时从 cookie 读取 token
+    pub fn cookie(mut self, name: &str) -> Self {
+        self.sources.push(TokenSource::Cookie(name.to_owned()));
+        self
+    }
+
+    /// 同时从查询参数读取 token (例如 websocket / 文件下载链接)
+    pub fn query(mut self, name: &str) -> Self {
+        self.sources.push(TokenSource::Query(name.to_owned()));
+        self
+    }
+
+    /// 加入公开路径 以 `*` 结尾时按前缀匹配
+    ///
+    /// 路径相对于中间件所在的 scope: `web::scope("/api").wrap(Authentication::new().public("/login"))`
+    /// 公开的是 `/api/login`, 写作 `/api/login` 不会匹配。
+    /// 与路由使用相同的路径 (解码 `%75` 等非保留字符), 不处理 `..` 与重复的 `/`;
+    /// 使用 `NormalizePath` 时应注册在本中间件外层, 使两者都匹配规范化后的路径
+    pub fn public(mut self, path: &str) -> Self {
+        self.public.push(path.to_owned());
+        self
+    }
+
+    /// 使用 JWKS 验签 (例如其他服务签发的 token)
+    pub fn jwks(mut self, jwks: Jwks) -> Self {
+        self.jwks = Some(jwks);
+        self
+    }
+
+    /// 是否检查注销记录 默认检查
+    pub fn revocation(mut self, enabled: bool) -> Self {
+        self.revocation = enabled;
+        self
+    }
+
+    /// 设置认证失败时的提示信息
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = message.to_owned();
+        self
+    }
+
+    fn is_public(&self, req: &ServiceRequest) -> bool {
+        // 路由匹配的路径, 而不是原始的 req.path()
+        let path = req.match_info().path();
+        self.public.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == p,
+        })
+    }
+
+    async fn authenticate(&self, req: &ServiceRequest) -> Result<Authenticated, BusinessError> {
+        let token = self
+            .sources
+            .iter()
+            .find_map(|source| source.extract(req))
+            .ok_or(BusinessError::Unauthorized)?;
+        let claims = match &self.jwks {
+            Some(jwks) => {
+                let data = jwks
+                    .decode::<Value>(&token)
+                    .await
+                    .map_err(jwt::JwtError::from)?;
+                if self.revocation {
+                    jwt::ensure_active(&data.claims).await?;
+                }
+                data.claims
+            }
+            None if self.revocation => jwt::verify_claims::<Value>(&token).await?.claims,
+            None => {
+                jwt::decode_claims::<Value>(&token)
+                    .map_err(jwt::JwtError::from)?
+                    .claims
+            }
+        };
+        Ok(Authenticated { claims })
+    }
+}
+
+impl<S, B> Transform<S> for Authentication
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticationMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthenticationMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: Authentication,
+}
+
+impl<S, B> Service for AuthenticationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        Box::pin(async move {
+            if req.method() == Method::OPTIONS {
+                let fut = service.borrow_mut().call(req);
+                return fut.await;
+            }
+            if config.is_public(&req) {
+                // 没有 token 时不访问缓存 / JWKS
+                if let Ok(authenticated) = config.authenticate(&req).await {
+                    req.extensions_mut().insert(authenticated);
+                }
+                let fut = service.borrow_mut().call(req);
+                return fut.await;
+            }
+            match config.authenticate(&req).await {
+                Ok(authenticated) => {
+                    req.extensions_mut().insert(authenticated);
+                }
+                Err(BusinessError::Unauthorized) => {
+                    let mut resp =
+                        HttpResponse::Unauthorized().json(Resp::err(401, &config.message));
+                    resp.headers_mut().insert(
+                        actix_web::http::header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static("Bearer"),
+                    );
+                    return Ok(req.into_response(resp.into_body()));
+                }
+                Err(e) => return Ok(req.error_response(e)),
+            }
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+/// 当前用户的 token claims (默认为 [`UserToken`])
+///
+/// 启用 [`Authentication`] 中间件时读取其验证结果;
+/// 未启用时从 `Authorization` 请求头读取并验证 token。未认证时返回 401
+pub struct AuthUser<T = UserToken>(pub T);
+
+impl<T> AuthUser<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for AuthUser<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for AuthUser<T> {
+    type Error = BusinessError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(authenticated) = req.extensions().get::<Authenticated>() {
+            let claims = serde_json::from_value(authenticated.claims.clone())
+                .map(AuthUser)
+                .map_err(|_| BusinessError::Unauthorized);
+            return Box::pin(async move { claims });
+        }
+        let token = header_token(req.headers());
+        Box::pin(async move {
+            let token = token.ok_or(BusinessError::Unauthorized)?;
+            Ok(AuthUser(jwt::verify_claims::<T>(&token).await?.claims))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, App};
+
+    #[test]
+    fn public_paths() {
+        actix_web::rt::System::new("test").block_on(public());
+    }
+
+    fn request(method: Method, uri: &str, token: Option<&str>) -> test::TestRequest {
+        let mut req = test::TestRequest::default().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req
+    }
+
+    async fn public() {
+        let auth = Authentication::new()
+            .revocation(false)
+            .public("/login")
+            .public("/public/*");
+        let mut app = test::init_service(App::new().wrap(auth).default_service(web::to(
+            |user: Option<AuthUser>| async move {
+                let body = user.map(|u| u.id.clone()).unwrap_or_default();
+                Ok::<_, Error>(HttpResponse::Ok().body(body))
+            },
+        )))
+        .await;
+        let token = jwt::try_encode("10086", "admin").unwrap();
+        let res =
+            test::call_service(&mut app, request(Method::GET, "/me", None).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/me", Some("invalid")).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/me", Some(&token)).to_request(),
+        )
+        .await;
+        assert_eq!(test::read_body(res).await, "10086");
+
+        // 公开路径: 无效 token 按未登录处理, 有效 token 仍可读取用户
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/login", Some("invalid")).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "");
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/public/a", Some(&token)).to_request(),
+        )
+        .await;
+        assert_eq!(test::read_body(res).await, "10086");
+
+        // 与路由相同的解码路径; 精确匹配不含子路径
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/p%75blic/a", None).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/login/x", None).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res =
+            test::call_service(&mut app, request(Method::OPTIONS, "/me", None).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // scope 内的公开路径相对于 scope
+        let mut app = test::init_service(
+            App::new().service(
+                web::scope("/api")
+                    .wrap(Authentication::new().revocation(false).public("/login"))
+                    .default_service(web::to(|| async {
+                        Ok::<_, Error>(HttpResponse::Ok().finish())
+                    })),
+            ),
+        )
+        .await;
+        let res = test::call_service(
+            &mut app,
+            request(Method::GET, "/api/login", None).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res =
+            test::call_service(&mut app, request(Method::GET, "/api/me", None).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
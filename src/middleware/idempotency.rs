@@ -0,0 +1,641 @@
+use super::*;
+use crate::caches::{degrade, Cache, CacheResult, Codec, Json, DEFAULT};
+use crate::utils::{md5_str, BusinessError, Resp};
+use actix_service::{Service, Transform};
+use actix_web::body::{Body, BodySize, MessageBody, ResponseBody};
+use actix_web::dev::{Payload, ServiceResponse};
+use actix_web::http::{HeaderMap, HeaderName, Method, StatusCode};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use futures::future::{ok, poll_fn, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// 重放的响应带有此响应头
+const REPLAYED: &str = "idempotent-replayed";
+
+/// 不保存的响应头: 逐跳头部与长度在重放时重新生成, cookie 不随重放下发
+const SKIPPED_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "set-cookie",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 保存的响应 `status` 为 `None` 时表示首个请求仍在处理
+#[derive(Serialize, Deserialize)]
+struct StoredResponse {
+    /// 请求方法、路径、查询参数与请求体的摘要 同一 key 用于不同请求时拒绝
+    fingerprint: String,
+    status: Option<u16>,
+    headers: Vec<(String, String)>,
+    /// base64
+    body: String,
+}
+
+/// 幂等中间件
+///
+/// 带有 `Idempotency-Key` 请求头的请求 (默认 POST / PATCH) 只执行一次:
+/// 首个请求的状态码、响应头与响应体保存在 redis 中, 相同 key 的重试直接返回保存的响应
+/// (带有 `Idempotent-Replayed: true` 响应头); 首个请求仍在处理时, 重复请求等待其完成或返回 409.
+/// 5xx 与处理出错的请求不保存, 客户端可以重试; 同一 key 用于方法、路径、查询参数或请求体不同的请求时返回 422.
+/// 请求体须读入内存计算摘要, 超过 `max_request` 时返回 413
+///
+/// key 按 token 中的用户 id 隔离, 未登录的请求共用同一空间
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use actix_web::{web, App, HttpResponse};
+/// use yn_util::middleware::Idempotency;
+///
+/// let app = App::new().service(
+///     web::resource("/orders")
+///         .wrap(Idempotency::new().ttl(Duration::from_secs(3600)).wait(Duration::from_secs(5)))
+///         .route(web::post().to(|| HttpResponse::Created())),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Idempotency {
+    name: String,
+    prefix: String,
+    header: HeaderName,
+    methods: Vec<Method>,
+    ttl: Duration,
+    lock_ttl: Duration,
+    wait: Option<Duration>,
+    max_request: usize,
+    max_body: usize,
+}
+
+impl Default for Idempotency {
+    fn default() -> Self {
+        Idempotency::named(DEFAULT)
+    }
+}
+
+impl Idempotency {
+    /// 使用默认缓存
+    pub fn new() -> Self {
+        Idempotency::default()
+    }
+
+    /// 使用命名缓存
+    pub fn named(name: &str) -> Self {
+        Idempotency {
+            name: name.to_owned(),
+            prefix: "idempotency".to_owned(),
+            header: HeaderName::from_static("idempotency-key"),
+            methods: vec![Method::POST, Method::PATCH],
+            ttl: Duration::from_secs(24 * 3600),
+            lock_ttl: Duration::from_secs(30),
+            wait: None,
+            max_request: 1024 * 1024,
+            max_body: 1024 * 1024,
+        }
+    }
+
+    /// 设置 key 前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// 设置请求头名称 (默认 `Idempotency-Key`), 名称不合法时返回错误
+    pub fn header(mut self, header: &str) -> Result<Self, BusinessError> {
+        self.header = HeaderName::from_bytes(header.as_bytes()).map_err(|_| {
+            BusinessError::ValidationError {
+                field: "header".to_owned(),
+            }
+        })?;
+        Ok(self)
+    }
+
+    /// 设置需要幂等处理的请求方法
+    pub fn methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// 设置响应的保存时间
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 设置处理中标记的过期时间 应大于接口的最长处理时间
+    pub fn lock_ttl(mut self, ttl: Duration) -> Self {
+        self.lock_ttl = ttl;
+        self
+    }
+
+    /// 重复请求等待首个请求完成的最长时间 (默认不等待, 直接返回 409)
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = Some(wait);
+        self
+    }
+
+    /// 设置幂等请求的请求体上限 超过时返回 413
+    pub fn max_request(mut self, size: usize) -> Self {
+        self.max_request = size;
+        self
+    }
+
+    /// 设置保存的响应体上限 超过时不保存
+    pub fn max_body(mut self, size: usize) -> Self {
+        self.max_body = size;
+        self
+    }
+
+    fn cache(&self) -> CacheResult<Cache> {
+        Ok(Cache::named(&self.name)?.prefix(&self.prefix))
+    }
+
+    /// 抢占 key 成功时返回 `None`, 否则返回已保存的记录
+    async fn begin(&self, key: &str, fingerprint: &str) -> CacheResult<Option<StoredResponse>> {
+        let cache = self.cache()?;
+        let pending = StoredResponse {
+            fingerprint: fingerprint.to_owned(),
+            status: None,
+            headers: vec![],
+            body: "".to_owned(),
+        };
+        loop {
+            let created: Option<String> = cache
+                .backend()
+                .query(
+                    redis::cmd("SET")
+                        .arg(cache.key(key))
+                        .arg(Json::encode(&pending)?)
+                        .arg("NX")
+                        .arg("PX")
+                        .arg(self.lock_ttl.as_millis() as u64),
+                )
+                .await?;
+            if created.is_some() {
+                return Ok(None);
+            }
+            // 记录恰好过期时重新抢占
+            if let Some(stored) = cache.get(key).await? {
+                return Ok(Some(stored));
+            }
+        }
+    }
+
+    /// 等待首个请求完成 超时返回 `None`
+    async fn wait_done(&self, key: &str) -> CacheResult<Option<StoredResponse>> {
+        let deadline = Instant::now() + self.wait.unwrap_or_default();
+        let cache = self.cache()?;
+        while Instant::now() < deadline {
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            match cache.get::<StoredResponse>(key).await? {
+                Some(stored) if stored.status.is_some() => return Ok(Some(stored)),
+                Some(_) => {}
+                // 首个请求失败 已释放
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<S, B> Transform<S> for Idempotency
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotencyMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            config: Rc::new(self.clone()),
+        })
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: Rc<Idempotency>,
+}
+
+impl<S, B> Service for IdempotencyMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        Box::pin(async move {
+            let idem_key = match req.headers().get(&config.header) {
+                Some(value) if config.methods.contains(req.method()) => value.to_str().ok(),
+                _ => {
+                    let fut = service.borrow_mut().call(req);
+                    return Ok(fut.await?.map_body(|_, body| pass_through(body)));
+                }
+            };
+            let idem_key = match idem_key.map(str::trim) {
+                Some(key) if !key.is_empty() && key.len() <= 255 => key.to_owned(),
+                _ => {
+                    let message = format!("{} 格式错误", config.header);
+                    let resp = HttpResponse::BadRequest().json(Resp::err(400, &message));
+                    return Ok(req.into_response(resp));
+                }
+            };
+            let scope = token_user_id(&req)
+                .await
+                .unwrap_or_else(|| "anonymous".to_owned());
+            let key = format!("{}:{}", scope, md5_str(&idem_key));
+            let body = match read_payload(&mut req.take_payload(), config.max_request).await? {
+                Some(body) => body,
+                None => {
+                    let resp = HttpResponse::PayloadTooLarge().json(Resp::err(413, "请求体过大"));
+                    return Ok(req.into_response(resp));
+                }
+            };
+            let fingerprint = fingerprint(req.method(), req.path(), req.query_string(), &body);
+            if !body.is_empty() {
+                req.set_payload(Payload::Stream(Box::pin(futures::stream::once(ok(body)))));
+            }
+
+            let stored = match degrade(config.begin(&key, &fingerprint).await) {
+                Ok(Some(stored)) => stored,
+                // 缓存故障且开启降级时 不做幂等处理
+                Ok(None) => {
+                    let fut = service.borrow_mut().call(req);
+                    return Ok(fut.await?.map_body(|_, body| pass_through(body)));
+                }
+                Err(e) => return Ok(req.error_response(e)),
+            };
+            if let Some(stored) = stored {
+                if stored.fingerprint != fingerprint {
+                    let message = format!("{} 已用于其他请求", config.header);
+                    let resp = HttpResponse::UnprocessableEntity().json(Resp::err(422, &message));
+                    return Ok(req.into_response(resp));
+                }
+                let done = match (stored.status, config.wait) {
+                    (Some(_), _) => Some(stored),
+                    (None, Some(_)) => match degrade(config.wait_done(&key).await) {
+                        Ok(done) => done.flatten(),
+                        Err(e) => return Ok(req.error_response(e)),
+                    },
+                    (None, None) => None,
+                };
+                let resp = match done {
+                    Some(done) => replay(done),
+                    None => {
+                        HttpResponse::Conflict().json(Resp::err(409, "请求正在处理中, 请稍后重试"))
+                    }
+                };
+                return Ok(req.into_response(resp));
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let res = match fut.await {
+                Ok(res) => res,
+                Err(e) => {
+                    release(&config, &key).await;
+                    return Err(e);
+                }
+            };
+            if res.status().is_server_error() {
+                release(&config, &key).await;
+                return Ok(res.map_body(|_, body| pass_through(body)));
+            }
+            let mut res = res;
+            let body = match collect(res.take_body(), config.max_body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    release(&config, &key).await;
+                    return Err(e);
+                }
+            };
+            let body = match body {
+                Collected::Complete(body) => {
+                    let stored = StoredResponse {
+                        fingerprint,
+                        status: Some(res.status().as_u16()),
+                        headers: stored_headers(res.headers()),
+                        body: base64::encode(&body),
+                    };
+                    let saved = match config.cache() {
+                        Ok(cache) => cache.set(&key, &stored, Some(config.ttl)).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = saved {
+                        log::error!("保存幂等响应失败, {}", e);
+                        release(&config, &key).await;
+                    }
+                    Body::from(body)
+                }
+                Collected::Oversize(body) => {
+                    log::warn!("响应体超过 {} 字节, 不保存幂等响应", config.max_body);
+                    release(&config, &key).await;
+                    Body::from_message(body)
+                }
+            };
+            Ok(res.map_body(|_, _| ResponseBody::Other(body)))
+        })
+    }
+}
+
+fn pass_through<B: MessageBody + Unpin + 'static>(body: ResponseBody<B>) -> ResponseBody<Body> {
+    ResponseBody::Other(Body::from_message(body))
+}
+
+/// 删除处理中标记 允许客户端重试
+async fn release(config: &Idempotency, key: &str) {
+    let deleted = match config.cache() {
+        Ok(cache) => cache.delete(key).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = deleted {
+        log::error!("释放幂等 key 失败, {}", e);
+    }
+}
+
+/// 需要保存的响应头
+fn stored_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(k, _)| !SKIPPED_HEADERS.contains(&k.as_str()))
+        .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_owned())))
+        .collect()
+}
+
+/// 请求方法、路径、查询参数与请求体的摘要
+fn fingerprint(method: &Method, path: &str, query: &str, body: &[u8]) -> String {
+    let mut context = md5::Context::new();
+    context.consume(method.as_str());
+    context.consume(b" ");
+    context.consume(path);
+    context.consume(b"?");
+    context.consume(query);
+    context.consume(b"\n");
+    context.consume(body);
+    format!("{:x}", context.compute())
+}
+
+/// 读取请求体 超过 limit 时返回 `None`
+async fn read_payload(payload: &mut Payload, limit: usize) -> Result<Option<Bytes>, Error> {
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes.freeze()))
+}
+
+fn replay(stored: StoredResponse) -> HttpResponse {
+    let status = stored
+        .status
+        .and_then(|s| StatusCode::from_u16(s).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut builder = HttpResponse::build(status);
+    for (name, value) in stored.headers.iter() {
+        builder.header(name.as_str(), value.as_str());
+    }
+    builder.header(REPLAYED, "true");
+    builder.body(base64::decode(&stored.body).unwrap_or_default())
+}
+
+enum Collected<B> {
+    Complete(Bytes),
+    /// 超过上限 不保存, 已读取的部分与剩余部分照常发送
+    Oversize(Prefixed<B>),
+}
+
+/// 读取完整响应体 超过 limit 时停止读取
+async fn collect<B: MessageBody + Unpin>(mut body: B, limit: usize) -> Result<Collected<B>, Error> {
+    if matches!(body.size(), BodySize::Sized(size) if size > limit as u64) {
+        return Ok(Collected::Oversize(Prefixed {
+            head: None,
+            rest: body,
+        }));
+    }
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > limit {
+            return Ok(Collected::Oversize(Prefixed {
+                head: Some(bytes.freeze()),
+                rest: body,
+            }));
+        }
+    }
+    Ok(Collected::Complete(bytes.freeze()))
+}
+
+/// 已读取的部分响应体与剩余部分
+struct Prefixed<B> {
+    head: Option<Bytes>,
+    rest: B,
+}
+
+impl<B: MessageBody + Unpin> MessageBody for Prefixed<B> {
+    fn size(&self) -> BodySize {
+        match self.head {
+            Some(_) => BodySize::Stream,
+            None => self.rest.size(),
+        }
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Error>>> {
+        match self.head.take() {
+            Some(head) => Poll::Ready(Some(Ok(head))),
+            None => Pin::new(&mut self.rest).poll_next(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+
+    fn run<F: std::future::Future + 'static>(f: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(f)
+    }
+
+    async fn read_all<B: MessageBody + Unpin>(mut body: B) -> Vec<u8> {
+        let mut bytes = vec![];
+        while let Some(chunk) = poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes
+    }
+
+    /// 分块响应体
+    fn chunked(chunks: &[&'static [u8]]) -> Body {
+        let chunks: Vec<Result<Bytes, Error>> =
+            chunks.iter().map(|c| Ok(Bytes::from_static(c))).collect();
+        Body::from_message(actix_web::body::BodyStream::new(futures::stream::iter(
+            chunks,
+        )))
+    }
+
+    #[test]
+    fn collect_within_limit() {
+        run(async {
+            match collect(chunked(&[b"ab", b"cd"]), 4).await.unwrap() {
+                Collected::Complete(body) => assert_eq!(&body[..], b"abcd"),
+                Collected::Oversize(_) => panic!("未超过上限"),
+            }
+        });
+    }
+
+    #[test]
+    fn collect_stops_at_limit() {
+        run(async {
+            match collect(chunked(&[b"ab", b"cd", b"ef"]), 3).await.unwrap() {
+                Collected::Oversize(body) => {
+                    assert_eq!(body.head.as_deref(), Some(&b"abcd"[..]));
+                    assert_eq!(body.size(), BodySize::Stream);
+                    assert_eq!(read_all(body).await, b"abcdef");
+                }
+                Collected::Complete(_) => panic!("应超过上限"),
+            }
+            // 已知长度超过上限时不读取
+            match collect(Body::from("abcdef"), 3).await.unwrap() {
+                Collected::Oversize(body) => {
+                    assert!(body.head.is_none());
+                    assert_eq!(body.size(), BodySize::Sized(6));
+                    assert_eq!(read_all(body).await, b"abcdef");
+                }
+                Collected::Complete(_) => panic!("应超过上限"),
+            }
+        });
+    }
+
+    #[test]
+    fn fingerprint_covers_body() {
+        let body = b"{\"amount\":1}";
+        let a = fingerprint(&Method::POST, "/orders", "", body);
+        assert_eq!(a, fingerprint(&Method::POST, "/orders", "", body));
+        assert_ne!(
+            a,
+            fingerprint(&Method::POST, "/orders", "", b"{\"amount\":2}")
+        );
+        assert_ne!(a, fingerprint(&Method::PATCH, "/orders", "", body));
+        assert_ne!(a, fingerprint(&Method::POST, "/refunds", "", body));
+        // 查询参数不同的请求不能复用同一幂等键
+        let pay = fingerprint(&Method::POST, "/pay", "amount=1", b"");
+        assert_eq!(pay, fingerprint(&Method::POST, "/pay", "amount=1", b""));
+        assert_ne!(pay, fingerprint(&Method::POST, "/pay", "amount=1000", b""));
+        assert_ne!(pay, fingerprint(&Method::POST, "/pay", "", b""));
+    }
+
+    #[test]
+    fn read_payload_limit() {
+        run(async {
+            let (_, mut payload) = test::TestRequest::default()
+                .set_payload("abcdef")
+                .to_http_parts();
+            assert_eq!(
+                read_payload(&mut payload, 6).await.unwrap().as_deref(),
+                Some(&b"abcdef"[..])
+            );
+            let (_, mut payload) = test::TestRequest::default()
+                .set_payload("abcdef")
+                .to_http_parts();
+            assert!(read_payload(&mut payload, 5).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn header_name() {
+        let idem = Idempotency::new().header("X-Request-Id").unwrap();
+        assert_eq!(idem.header, HeaderName::from_static("x-request-id"));
+        assert!(matches!(
+            Idempotency::new().header("bad header"),
+            Err(BusinessError::ValidationError { field }) if field == "header"
+        ));
+    }
+
+    #[test]
+    fn hop_headers_not_stored() {
+        let resp = HttpResponse::Created()
+            .header("X-Order-Id", "42")
+            .header("Content-Length", "2")
+            .header("Transfer-Encoding", "chunked")
+            .header("Connection", "close")
+            .header("Set-Cookie", "sid=1")
+            .body("ok");
+        let headers = stored_headers(resp.headers());
+        assert_eq!(headers, vec![("x-order-id".to_owned(), "42".to_owned())]);
+
+        let stored = StoredResponse {
+            fingerprint: "".to_owned(),
+            status: Some(201),
+            headers,
+            body: base64::encode("ok"),
+        };
+        let resp = replay(stored);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get("x-order-id").unwrap(), "42");
+        assert_eq!(resp.headers().get(REPLAYED).unwrap(), "true");
+        assert!(resp.headers().get("set-cookie").is_none());
+    }
+
+    #[test]
+    fn handler_still_reads_body() {
+        run(async {
+            // 未注册的缓存 降级后直接执行
+            let idem = Idempotency::named("idempotency_unavailable").max_request(8);
+            let mut app =
+                test::init_service(actix_web::App::new().wrap(idem).route(
+                    "/echo",
+                    actix_web::web::post().to(|body: Bytes| async move {
+                        Ok::<_, Error>(HttpResponse::Ok().body(body))
+                    }),
+                ))
+                .await;
+            let req = test::TestRequest::post()
+                .uri("/echo")
+                .header("idempotency-key", "k1")
+                .set_payload("hello")
+                .to_request();
+            assert_eq!(test::read_response(&mut app, req).await, "hello");
+
+            let req = test::TestRequest::post()
+                .uri("/echo")
+                .header("idempotency-key", "k1")
+                .set_payload("hello world")
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        });
+    }
+}
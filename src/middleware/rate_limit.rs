@@ -0,0 +1,221 @@
+use super::*;
+use crate::caches::{RateDecision, RateLimiter};
+use crate::utils::Resp;
+use actix_service::{Service, Transform};
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// 从请求中提取限流 key, 返回 None 时不限流
+pub type KeyExtractor = Arc<dyn Fn(&ServiceRequest) -> Option<String> + Send + Sync>;
+
+/// 限流维度
+#[derive(Clone)]
+pub enum RateKey {
+    /// 客户端 ip
+    Ip,
+    /// token 中的用户 id, 未登录时按 ip
+    User,
+    /// 自定义
+    Custom(KeyExtractor),
+}
+
+impl RateKey {
+    async fn extract(&self, req: &ServiceRequest, trusted: &[IpAddr]) -> Option<String> {
+        let ip = || forwarded_ip(req, trusted).map(|ip| format!("ip:{}", ip));
+        match self {
+            RateKey::Ip => ip(),
+            RateKey::User => token_user_id(req)
+                .await
+                .map(|id| format!("user:{}", id))
+                .or_else(ip),
+            RateKey::Custom(f) => f(req),
+        }
+    }
+}
+
+/// 限流中间件
+///
+/// 超出限制时返回 429 与 `Resp::err(429, ...)`, 响应头带有
+/// `X-RateLimit-Limit` / `X-RateLimit-Remaining` / `X-RateLimit-Reset` (秒) 与 `Retry-After`
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use actix_web::{web, App, HttpResponse};
+/// use yn_util::caches::{RateLimit, RateLimiter};
+/// use yn_util::middleware::RateLimiting;
+///
+/// let login = RateLimiter::new(RateLimit::sliding_window(5, Duration::from_secs(60))).prefix("login");
+/// let app = App::new().service(
+///     web::resource("/login")
+///         .wrap(RateLimiting::new(login).message("登录过于频繁, 请稍后再试"))
+///         .route(web::post().to(|| HttpResponse::Ok())),
+/// );
+///
+/// // 部署在 nginx (10.0.0.1) 之后
+/// let api = RateLimiter::new(RateLimit::token_bucket(100, Duration::from_secs(10))).prefix("api");
+/// let proxies = ["10.0.0.1".parse().unwrap()];
+/// let sms = RateLimiter::new(RateLimit::fixed_window(1, Duration::from_secs(60))).prefix("sms");
+/// let app = App::new()
+///     .wrap(RateLimiting::new(api).by_user().trusted_proxies(&proxies))
+///     .service(
+///         web::resource("/sms")
+///             .wrap(RateLimiting::new(sms).by(|req| req.match_info().get("phone").map(|p| p.to_owned())))
+///             .route(web::post().to(|| HttpResponse::Ok())),
+///     );
+/// ```
+#[derive(Clone)]
+pub struct RateLimiting {
+    limiter: RateLimiter,
+    key: RateKey,
+    message: String,
+    trusted: Vec<IpAddr>,
+}
+
+impl RateLimiting {
+    /// 默认按客户端 ip 限流
+    pub fn new(limiter: RateLimiter) -> Self {
+        RateLimiting {
+            limiter,
+            key: RateKey::Ip,
+            message: "请求过于频繁, 请稍后再试".to_owned(),
+            trusted: vec![],
+        }
+    }
+
+    /// 按客户端 ip 限流
+    pub fn by_ip(mut self) -> Self {
+        self.key = RateKey::Ip;
+        self
+    }
+
+    /// 按 token 中的用户 id 限流
+    pub fn by_user(mut self) -> Self {
+        self.key = RateKey::User;
+        self
+    }
+
+    /// 自定义限流 key
+    pub fn by<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = RateKey::Custom(Arc::new(f));
+        self
+    }
+
+    /// 可信的反向代理地址
+    ///
+    /// 默认按 tcp 对端地址限流; 只有请求来自这些地址时才读取 Forwarded / X-Forwarded-For
+    /// (见 [`forwarded_ip`]), 否则客户端可伪造请求头绕过限流
+    pub fn trusted_proxies(mut self, proxies: &[IpAddr]) -> Self {
+        self.trusted = proxies.to_vec();
+        self
+    }
+
+    /// 设置超出限制时的提示信息
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = message.to_owned();
+        self
+    }
+}
+
+impl<S, B> Transform<S> for RateLimiting
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitingMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct RateLimitingMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: RateLimiting,
+}
+
+impl<S, B> Service for RateLimitingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        Box::pin(async move {
+            let key = match config.key.extract(&req, &config.trusted).await {
+                Some(key) => key,
+                None => {
+                    let fut = service.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+            let decision = match config.limiter.check(&key).await {
+                Ok(decision) => decision,
+                Err(e) => return Ok(req.error_response(e)),
+            };
+            if !decision.allowed {
+                let mut resp =
+                    HttpResponse::TooManyRequests().json(Resp::err(429, &config.message));
+                rate_headers(resp.headers_mut(), &decision);
+                return Ok(req.into_response(resp.into_body()));
+            }
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+            rate_headers(res.headers_mut(), &decision);
+            Ok(res)
+        })
+    }
+}
+
+fn rate_headers(headers: &mut HeaderMap, decision: &RateDecision) {
+    let secs = |d: Duration| (d.as_millis() as u64).div_ceil(1000);
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-limit"),
+        HeaderValue::from(decision.limit),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-remaining"),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-reset"),
+        HeaderValue::from(secs(decision.reset)),
+    );
+    if let Some(retry) = decision.retry_after {
+        headers.insert(
+            actix_web::http::header::RETRY_AFTER,
+            HeaderValue::from(secs(retry).max(1)),
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use super::*;
+use crate::caches::degrade;
+use crate::session::{new_id, Session, SessionInner, SessionRecord, SessionStatus, SessionStore};
+use crate::utils::BusinessError;
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::ServiceResponse;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use ring::hmac;
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+/// 会话中间件
+///
+/// cookie 中保存 `{session id}.{HMAC-SHA256 签名}`, 签名不符的 cookie 视为无会话;
+/// 每次请求顺延会话的过期时间, 处理函数通过 [`crate::session::Session`] 读写会话
+///
+/// # Examples
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use actix_web::App;
+/// use yn_util::middleware::Sessions;
+/// use yn_util::session::SessionStore;
+///
+/// let store = SessionStore::new().prefix("ynos:session").ttl(Duration::from_secs(7200));
+/// let app = App::new().wrap(
+///     Sessions::new(store, b"0123456789abcdef0123456789abcdef")
+///         .cookie_name("ynos_sid")
+///         .secure(true),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Sessions {
+    store: SessionStore,
+    key: hmac::Key,
+    cookie_name: String,
+    path: String,
+    domain: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: SameSite,
+    persistent: bool,
+    trusted: Vec<IpAddr>,
+}
+
+impl Sessions {
+    /// secret 用于签名 cookie, 建议不少于 32 字节
+    pub fn new(store: SessionStore, secret: &[u8]) -> Self {
+        Sessions {
+            store,
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            cookie_name: "yn_session".to_owned(),
+            path: "/".to_owned(),
+            domain: None,
+            secure: false,
+            http_only: true,
+            same_site: SameSite::Lax,
+            persistent: false,
+            trusted: vec![],
+        }
+    }
+
+    /// 设置 cookie 名称
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    /// 设置 cookie 路径
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    /// 设置 cookie 域名
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    /// 只在 https 下发送 cookie
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// 禁止脚本读取 cookie (默认开启)
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// 设置 SameSite (默认 Lax)
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// cookie 按会话过期时间持久保存 (默认关闭浏览器即失效)
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// 可信的反向代理地址 会话记录的 ip 默认取 tcp 对端地址, 见 [`forwarded_ip`]
+    pub fn trusted_proxies(mut self, proxies: &[IpAddr]) -> Self {
+        self.trusted = proxies.to_vec();
+        self
+    }
+
+    fn sign(&self, id: &str) -> String {
+        let tag = hmac::sign(&self.key, id.as_bytes());
+        format!(
+            "{}.{}",
+            id,
+            base64::encode_config(tag.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    fn verify(&self, value: &str) -> Option<String> {
+        let (id, tag) = value.rsplit_once('.')?;
+        let tag = base64::decode_config(tag, base64::URL_SAFE_NO_PAD).ok()?;
+        hmac::verify(&self.key, id.as_bytes(), &tag).ok()?;
+        Some(id.to_owned())
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.cookie_name.clone(), value)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(self.http_only)
+            .same_site(self.same_site)
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    fn set_cookie<B>(&self, res: &mut ServiceResponse<B>, id: &str) {
+        let mut cookie = self.cookie(self.sign(id));
+        if self.persistent {
+            cookie.set_max_age(time::Duration::seconds(self.store.ttl.as_secs() as i64));
+        }
+        if let Err(e) = res.response_mut().add_cookie(&cookie) {
+            log::error!("设置 session cookie 失败, {}", e);
+        }
+    }
+
+    fn remove_cookie<B>(&self, res: &mut ServiceResponse<B>) {
+        let mut cookie = self.cookie("".to_owned());
+        cookie.set_max_age(time::Duration::zero());
+        if let Err(e) = res.response_mut().add_cookie(&cookie) {
+            log::error!("清除 session cookie 失败, {}", e);
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Sessions
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionsMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            config: Rc::new(self.clone()),
+        })
+    }
+}
+
+pub struct SessionsMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    config: Rc<Sessions>,
+}
+
+impl<S, B> Service for SessionsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        Box::pin(async move {
+            let store = &config.store;
+            let cookie_id = req
+                .cookie(&config.cookie_name)
+                .and_then(|c| config.verify(c.value()));
+            let has_cookie = req.cookie(&config.cookie_name).is_some();
+            let loaded = match &cookie_id {
+                Some(id) => match degrade(store.load(id).await) {
+                    Ok(record) => record.flatten(),
+                    Err(e) => return Ok(req.error_response(e)),
+                },
+                None => None,
+            };
+            let inner = match loaded {
+                Some(record) => SessionInner {
+                    id: cookie_id.clone(),
+                    record,
+                    status: SessionStatus::Unchanged,
+                },
+                None => {
+                    let user_agent = req
+                        .headers()
+                        .get(header::USER_AGENT)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.to_owned());
+                    SessionInner {
+                        id: None,
+                        record: SessionRecord::new(forwarded_ip(&req, &config.trusted), user_agent),
+                        status: SessionStatus::Unchanged,
+                    }
+                }
+            };
+            let session = Session::new(inner);
+            req.extensions_mut().insert(session.clone());
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+
+            let (id, record, status) = {
+                let inner = session.0.borrow();
+                (inner.id.clone(), inner.record.clone(), inner.status)
+            };
+            let result = match (status, id) {
+                (SessionStatus::Unchanged, Some(id)) => match store.touch(&id, &record).await {
+                    Ok(true) if config.persistent => Ok(Some(id)),
+                    Ok(_) => Ok(None),
+                    Err(e) => Err(e),
+                },
+                (SessionStatus::Unchanged, None) => Ok(None),
+                (SessionStatus::Changed, Some(id)) => match store.save(&id, &record, true).await {
+                    Ok(true) => Ok(Some(id)),
+                    Ok(false) => {
+                        config.remove_cookie(&mut res);
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                },
+                (SessionStatus::Changed, None) | (SessionStatus::Renewed, None) => {
+                    let id = new_id();
+                    store.save(&id, &record, false).await.map(|_| Some(id))
+                }
+                (SessionStatus::Renewed, Some(old)) => {
+                    let id = new_id();
+                    match store.revoke(&old).await {
+                        Ok(_) => store.save(&id, &record, false).await.map(|_| Some(id)),
+                        Err(e) => Err(e),
+                    }
+                }
+                (SessionStatus::Purged, id) => {
+                    // 读取会话时缓存故障 (降级) 也要注销 cookie 中的会话
+                    let revoked = match id.or(cookie_id) {
+                        Some(id) => store.revoke(&id).await.map(|_| ()),
+                        None => Ok(()),
+                    };
+                    // 注销失败不能按降级处理, 否则客户端以为已退出而会话仍然有效
+                    if let Err(e) = revoked {
+                        return Ok(res.error_response(BusinessError::from(e)));
+                    }
+                    if has_cookie {
+                        config.remove_cookie(&mut res);
+                    }
+                    Ok(None)
+                }
+            };
+            match degrade(result) {
+                Ok(Some(Some(id))) => config.set_cookie(&mut res, &id),
+                Ok(_) => {}
+                Err(e) => return Ok(res.error_response(e)),
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[test]
+    fn logout_fails_when_revoke_fails() {
+        actix_web::rt::System::new("test").block_on(logout());
+    }
+
+    async fn logout() {
+        // 未注册的缓存: 读取会话降级为无会话, 注销必然失败
+        let store = SessionStore::named("session_logout_unavailable");
+        let sessions = Sessions::new(store, b"0123456789abcdef0123456789abcdef");
+        let cookie = Cookie::new("yn_session", sessions.sign("abc"));
+        let mut app = test::init_service(App::new().wrap(sessions).route(
+            "/logout",
+            web::post().to(|session: Session| async move {
+                session.purge();
+                Ok::<_, Error>(HttpResponse::Ok().finish())
+            }),
+        ))
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/logout")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        // 缓存错误以 InternalError 响应 (400)
+        assert_eq!(res.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        assert!(res.response().cookies().next().is_none());
+
+        // 没有会话时退出不访问缓存
+        let req = test::TestRequest::post().uri("/logout").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.status().is_success());
+    }
+}
@@ -0,0 +1,277 @@
+use bson::{doc, Bson, Document};
+use chrono::{Local, SecondsFormat};
+
+/// js 能精确表示的最大整数 2^53 - 1
+pub const JS_MAX_SAFE_INTEGER: i64 = 9_007_199_254_740_991;
+
+/// BSON 日期的输出方式
+#[derive(Clone, Debug)]
+pub enum DateFormat {
+    /// 按本地时区格式化 例如 `%Y-%m-%d %H:%M:%S`
+    Format(String),
+    /// RFC3339 字符串 (UTC)
+    Rfc3339,
+    /// 毫秒时间戳
+    Millis,
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        // 与 date_time::to_string 保持一致
+        DateFormat::Format("%Y-%m-%d %H:%M:%S".to_owned())
+    }
+}
+
+/// 文档规范化 - 递归处理嵌套文档与数组
+///
+/// * ObjectId 转为 hex 字符串
+/// * DateTime 按 [`DateFormat`] 转为字符串或毫秒时间戳
+/// * 超出 js 安全范围的 Int64 转为字符串
+/// * Decimal128 转为字符串 (直接序列化为 json 会 panic)
+///
+/// # Examples
+/// ```rust
+/// use bson::{doc, oid::ObjectId};
+/// use yn_util::normalize::{DateFormat, Normalizer};
+///
+/// let oid = ObjectId::new();
+/// let data = doc! { "items": [ { "product_id": oid.clone() } ] };
+/// let data = Normalizer::new().date_format(DateFormat::Millis).normalize(data);
+/// let items = data.get_array("items").unwrap();
+/// assert_eq!(items[0].as_document().unwrap().get_str("product_id").unwrap(), oid.to_hex());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    /// 日期输出方式
+    pub date_format: DateFormat,
+    /// 超出 js 安全整数范围的 Int64 是否转为字符串
+    pub safe_int64: bool,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer {
+            date_format: DateFormat::default(),
+            safe_int64: true,
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn new() -> Self {
+        Normalizer::default()
+    }
+
+    /// 设置日期输出方式
+    pub fn date_format(mut self, date_format: DateFormat) -> Self {
+        self.date_format = date_format;
+        self
+    }
+
+    /// 设置 Int64 是否按 js 安全范围转为字符串
+    pub fn safe_int64(mut self, safe_int64: bool) -> Self {
+        self.safe_int64 = safe_int64;
+        self
+    }
+
+    /// 规范化文档 保持原有字段顺序
+    pub fn normalize(&self, doc: Document) -> Document {
+        let mut data = doc! {};
+        for (k, v) in doc.into_iter() {
+            data.insert(k, self.normalize_bson(v));
+        }
+        data
+    }
+
+    /// 规范化单个值
+    pub fn normalize_bson(&self, value: Bson) -> Bson {
+        match value {
+            Bson::ObjectId(oid) => Bson::String(oid.to_hex()),
+            Bson::DateTime(dt) => match &self.date_format {
+                DateFormat::Format(f) => {
+                    Bson::String(dt.with_timezone(&Local).format(f).to_string())
+                }
+                DateFormat::Rfc3339 => {
+                    Bson::String(dt.to_rfc3339_opts(SecondsFormat::Millis, true))
+                }
+                DateFormat::Millis => Bson::Int64(dt.timestamp_millis()),
+            },
+            Bson::Int64(v)
+                if self.safe_int64
+                    && !(-JS_MAX_SAFE_INTEGER..=JS_MAX_SAFE_INTEGER).contains(&v) =>
+            {
+                Bson::String(v.to_string())
+            }
+            Bson::Decimal128(_) => match decimal128_to_string(&value) {
+                Some(s) => Bson::String(s),
+                None => Bson::Null,
+            },
+            Bson::Document(d) => Bson::Document(self.normalize(d)),
+            Bson::Array(list) => {
+                Bson::Array(list.into_iter().map(|v| self.normalize_bson(v)).collect())
+            }
+            v => v,
+        }
+    }
+}
+
+/// Decimal128 转字符串
+///
+/// bson 未开启 decimal128 特性时无法直接读取内容, 这里先编码成 bson 字节再按 IEEE 754-2008 (BID) 解析
+pub fn decimal128_to_string(value: &Bson) -> Option<String> {
+    if !matches!(value, Bson::Decimal128(_)) {
+        return None;
+    }
+    let mut buf = vec![];
+    doc! {"d": value.clone()}.to_writer(&mut buf).ok()?;
+    // 4 字节长度 + 1 字节类型 + "d\0"
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(buf.get(7..23)?);
+    let bits = u128::from_le_bytes(bytes);
+
+    let negative = bits >> 127 == 1;
+    let sign = if negative { "-" } else { "" };
+    let (exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 {
+        match (bits >> 122) & 0b11111 {
+            0b11110 => return Some(format!("{}Infinity", sign)),
+            0b11111 => return Some("NaN".to_owned()),
+            // 超出范围的系数按规范视为 0
+            _ => (((bits >> 111) & 0x3fff) as i32, 0u128),
+        }
+    } else {
+        (((bits >> 113) & 0x3fff) as i32, bits & ((1u128 << 113) - 1))
+    };
+    let exponent = exponent - 6176;
+    // 非规范系数 (> 10^34 - 1) 视为 0
+    let coefficient = if coefficient > 9_999_999_999_999_999_999_999_999_999_999_999 {
+        0
+    } else {
+        coefficient
+    };
+
+    let digits = coefficient.to_string();
+    let adjusted = exponent + digits.len() as i32 - 1;
+    let body = if exponent <= 0 && adjusted >= -6 {
+        if exponent == 0 {
+            digits
+        } else {
+            let scale = (-exponent) as usize;
+            if digits.len() > scale {
+                let (int, frac) = digits.split_at(digits.len() - scale);
+                format!("{}.{}", int, frac)
+            } else {
+                format!("0.{}{}", "0".repeat(scale - digits.len()), digits)
+            }
+        }
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let mantissa = if rest.is_empty() {
+            first.to_owned()
+        } else {
+            format!("{}.{}", first, rest)
+        };
+        format!(
+            "{}E{}{}",
+            mantissa,
+            if adjusted >= 0 { "+" } else { "" },
+            adjusted
+        )
+    };
+    Some(format!("{}{}", sign, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按 BID 位布局构造 Decimal128 (bson 未开启 decimal128 特性, 只能从字节解码)
+    fn decimal(bits: u128) -> Bson {
+        let mut buf = vec![];
+        buf.extend_from_slice(&24i32.to_le_bytes());
+        buf.push(0x13);
+        buf.extend_from_slice(b"d\0");
+        buf.extend_from_slice(&bits.to_le_bytes());
+        buf.push(0);
+        Document::from_reader(&mut buf.as_slice())
+            .unwrap()
+            .get("d")
+            .cloned()
+            .unwrap()
+    }
+
+    fn finite(negative: bool, exponent: i32, coefficient: u128) -> Bson {
+        let sign = if negative { 1u128 << 127 } else { 0 };
+        decimal(sign | (((exponent + 6176) as u128) << 113) | coefficient)
+    }
+
+    fn to_string(value: Bson) -> String {
+        decimal128_to_string(&value).unwrap()
+    }
+
+    #[test]
+    fn zero() {
+        assert_eq!(to_string(finite(false, 0, 0)), "0");
+        assert_eq!(to_string(finite(true, 0, 0)), "-0");
+        assert_eq!(to_string(finite(false, -2, 0)), "0.00");
+        assert_eq!(to_string(finite(false, 3, 0)), "0E+3");
+    }
+
+    #[test]
+    fn negative() {
+        assert_eq!(to_string(finite(true, 0, 5)), "-5");
+        assert_eq!(to_string(finite(true, -2, 123)), "-1.23");
+    }
+
+    #[test]
+    fn exponent() {
+        assert_eq!(to_string(finite(false, -2, 123)), "1.23");
+        assert_eq!(to_string(finite(false, -6, 1)), "0.000001");
+        assert_eq!(to_string(finite(false, -7, 1)), "1E-7");
+        assert_eq!(to_string(finite(false, 3, 1)), "1E+3");
+        assert_eq!(to_string(finite(false, 2, 123)), "1.23E+4");
+        assert_eq!(to_string(finite(false, -6176, 1)), "1E-6176");
+        assert_eq!(to_string(finite(false, 6111, 1)), "1E+6111");
+    }
+
+    #[test]
+    fn special() {
+        assert_eq!(to_string(decimal(0x7c << 120)), "NaN");
+        assert_eq!(to_string(decimal(0xfc << 120)), "NaN");
+        assert_eq!(to_string(decimal(0x78 << 120)), "Infinity");
+        assert_eq!(to_string(decimal(0xf8 << 120)), "-Infinity");
+    }
+
+    #[test]
+    fn large_coefficient() {
+        let max = 9_999_999_999_999_999_999_999_999_999_999_999;
+        assert_eq!(
+            to_string(finite(false, 0, max)),
+            "9999999999999999999999999999999999"
+        );
+        // 非规范系数视为 0
+        assert_eq!(to_string(finite(false, 0, max + 1)), "0");
+        // `11` 组合位 (隐含系数超过 113 位) 按规范视为 0, 指数位于 111-124 位
+        let bits = (0b11u128 << 125) | (6176u128 << 111) | 1;
+        assert_eq!(to_string(decimal(bits)), "0");
+        let bits = (1u128 << 127) | (0b11u128 << 125) | (6174u128 << 111);
+        assert_eq!(to_string(decimal(bits)), "-0.00");
+    }
+
+    #[test]
+    fn not_decimal() {
+        assert_eq!(decimal128_to_string(&Bson::Int32(1)), None);
+    }
+
+    #[test]
+    fn normalize_nested() {
+        let data = doc! { "a": [ { "n": i64::MAX, "d": finite(false, -1, 15) } ], "m": 1i64 };
+        let data = Normalizer::new().normalize(data);
+        let item = data.get_array("a").unwrap()[0]
+            .as_document()
+            .unwrap()
+            .clone();
+        assert_eq!(item.get_str("n").unwrap(), i64::MAX.to_string());
+        assert_eq!(item.get_str("d").unwrap(), "1.5");
+        assert_eq!(data.get_i64("m").unwrap(), 1);
+    }
+}
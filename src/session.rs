@@ -0,0 +1,477 @@
+use super::*;
+use crate::caches::{Cache, CacheResult, Codec, Json, DEFAULT};
+use crate::date_time;
+use crate::utils::BusinessError;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{err, ok, ready, Ready};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// 生成 session id (256 位随机数)
+pub(crate) fn new_id() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("系统随机数不可用");
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// redis 中保存的会话
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct SessionRecord {
+    pub user_id: Option<String>,
+    /// 创建时间 秒
+    pub created: u64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub data: HashMap<String, Value>,
+}
+
+impl SessionRecord {
+    pub fn new(ip: Option<String>, user_agent: Option<String>) -> Self {
+        SessionRecord {
+            created: date_time::timestamp(),
+            ip,
+            user_agent,
+            ..Default::default()
+        }
+    }
+}
+
+/// 会话概要 用于列出用户的登录设备
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub user_id: Option<String>,
+    /// 创建时间 秒
+    pub created: u64,
+    /// 最后活动时间 秒
+    pub last_active: u64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// 会话存储
+///
+/// 会话保存在 `{prefix}:{id}`, 每次访问顺延过期时间;
+/// 已登录的会话 id 同时记录在 `{prefix}:user:{user_id}` 集合中, 用于列出和注销用户的全部会话
+///
+/// # Examples
+/// ```rust,no_run
+/// use yn_util::session::SessionStore;
+///
+/// # async fn run() -> yn_util::caches::CacheResult<()> {
+/// let store = SessionStore::new().prefix("ynos:session");
+/// // 修改密码后 注销其他设备
+/// for info in store.list("5fa0a0a0a0a0a0a0a0a0a0a0").await? {
+///     log::info!("{:?} {:?}", info.ip, info.user_agent);
+/// }
+/// store.revoke_user("5fa0a0a0a0a0a0a0a0a0a0a0").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SessionStore {
+    name: String,
+    prefix: String,
+    pub(crate) ttl: Duration,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        SessionStore::named(DEFAULT)
+    }
+}
+
+impl SessionStore {
+    /// 使用默认缓存
+    pub fn new() -> Self {
+        SessionStore::default()
+    }
+
+    /// 使用命名缓存 (缓存在首次访问时获取, 可先于缓存初始化创建)
+    pub fn named(name: &str) -> Self {
+        SessionStore {
+            name: name.to_owned(),
+            prefix: "session".to_owned(),
+            ttl: Duration::from_secs(30 * 60),
+        }
+    }
+
+    /// 设置 key 前缀
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// 设置空闲过期时间 每次访问后重新计时
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl.max(Duration::from_secs(1));
+        self
+    }
+
+    fn cache(&self) -> CacheResult<Cache> {
+        Ok(Cache::named(&self.name)?.prefix(&self.prefix))
+    }
+
+    fn user_key(user_id: &str) -> String {
+        format!("user:{}", user_id)
+    }
+
+    pub(crate) async fn load(&self, id: &str) -> CacheResult<Option<SessionRecord>> {
+        self.cache()?.get(id).await
+    }
+
+    /// 保存会话 `existing` 时只覆盖仍存在的会话 (已被注销的不再恢复), 返回是否保存
+    pub(crate) async fn save(
+        &self,
+        id: &str,
+        record: &SessionRecord,
+        existing: bool,
+    ) -> CacheResult<bool> {
+        let cache = self.cache()?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(cache.key(id))
+            .arg(Json::encode(record)?)
+            .arg("PX")
+            .arg(self.ttl.as_millis() as u64);
+        if existing {
+            cmd.arg("XX");
+        }
+        let saved: Option<String> = cache.backend().query(&cmd).await?;
+        if saved.is_none() {
+            return Ok(false);
+        }
+        if let Some(user_id) = &record.user_id {
+            let key = cache.key(&SessionStore::user_key(user_id));
+            cache
+                .backend()
+                .query::<()>(redis::cmd("SADD").arg(&key).arg(id))
+                .await?;
+            self.expire_index(&cache, user_id).await?;
+        }
+        Ok(true)
+    }
+
+    /// 顺延过期时间 返回会话是否仍存在
+    pub(crate) async fn touch(&self, id: &str, record: &SessionRecord) -> CacheResult<bool> {
+        let cache = self.cache()?;
+        let alive = cache.expire(id, self.ttl).await?;
+        if let (true, Some(user_id)) = (alive, &record.user_id) {
+            self.expire_index(&cache, user_id).await?;
+        }
+        Ok(alive)
+    }
+
+    /// 用户索引与最近访问的会话同时过期
+    async fn expire_index(&self, cache: &Cache, user_id: &str) -> CacheResult<()> {
+        cache
+            .expire(&SessionStore::user_key(user_id), self.ttl)
+            .await?;
+        Ok(())
+    }
+
+    /// 用户的全部有效会话
+    pub async fn list(&self, user_id: &str) -> CacheResult<Vec<SessionInfo>> {
+        let cache = self.cache()?;
+        let index = cache.key(&SessionStore::user_key(user_id));
+        let ids: Vec<String> = cache
+            .backend()
+            .query(redis::cmd("SMEMBERS").arg(&index))
+            .await?;
+        let now = date_time::timestamp();
+        let mut sessions = vec![];
+        let mut stale = vec![];
+        for id in ids {
+            let record = match cache.get::<SessionRecord>(&id).await? {
+                Some(record) if record.user_id.as_deref() == Some(user_id) => record,
+                _ => {
+                    stale.push(id);
+                    continue;
+                }
+            };
+            let idle = cache
+                .ttl(&id)
+                .await?
+                .map_or(0, |left| self.ttl.saturating_sub(left).as_secs());
+            sessions.push(SessionInfo {
+                id,
+                user_id: record.user_id,
+                created: record.created,
+                last_active: now.saturating_sub(idle),
+                ip: record.ip,
+                user_agent: record.user_agent,
+            });
+        }
+        if !stale.is_empty() {
+            cache
+                .backend()
+                .query::<()>(redis::cmd("SREM").arg(&index).arg(stale))
+                .await?;
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_active));
+        Ok(sessions)
+    }
+
+    /// 注销会话 返回会话是否存在
+    pub async fn revoke(&self, id: &str) -> CacheResult<bool> {
+        let cache = self.cache()?;
+        let record = cache.get::<SessionRecord>(id).await?;
+        let existed = cache.delete(id).await?;
+        if let Some(user_id) = record.and_then(|r| r.user_id) {
+            cache
+                .backend()
+                .query::<()>(
+                    redis::cmd("SREM")
+                        .arg(cache.key(&SessionStore::user_key(&user_id)))
+                        .arg(id),
+                )
+                .await?;
+        }
+        Ok(existed)
+    }
+
+    /// 注销用户的全部会话 返回注销的数量
+    pub async fn revoke_user(&self, user_id: &str) -> CacheResult<i64> {
+        self.revoke_user_except(user_id, None).await
+    }
+
+    /// 注销用户除 `keep` 外的全部会话 (例如修改密码时保留当前会话)
+    pub async fn revoke_user_except(&self, user_id: &str, keep: Option<&str>) -> CacheResult<i64> {
+        let cache = self.cache()?;
+        let index = SessionStore::user_key(user_id);
+        let ids: Vec<String> = cache
+            .backend()
+            .query(redis::cmd("SMEMBERS").arg(cache.key(&index)))
+            .await?;
+        let revoked: Vec<&str> = ids
+            .iter()
+            .map(|id| id.as_str())
+            .filter(|id| Some(*id) != keep)
+            .collect();
+        let total = cache.delete_many(&revoked).await?;
+        if revoked.len() == ids.len() {
+            cache.delete(&index).await?;
+        } else if !revoked.is_empty() {
+            cache
+                .backend()
+                .query::<()>(
+                    redis::cmd("SREM")
+                        .arg(cache.key(&index))
+                        .arg(revoked.as_slice()),
+                )
+                .await?;
+        }
+        Ok(total)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SessionStatus {
+    Unchanged,
+    Changed,
+    /// 更换 session id (登录后防止会话固定攻击)
+    Renewed,
+    Purged,
+}
+
+pub(crate) struct SessionInner {
+    pub id: Option<String>,
+    pub record: SessionRecord,
+    pub status: SessionStatus,
+}
+
+/// 当前请求的会话 需要启用 [`crate::middleware::Sessions`] 中间件
+///
+/// 修改在请求结束后写回 redis
+///
+/// # Examples
+/// ```rust,no_run
+/// use actix_web::{web, HttpResponse};
+/// use yn_util::session::Session;
+/// use yn_util::utils::BusinessError;
+///
+/// async fn login(session: Session) -> Result<HttpResponse, BusinessError> {
+///     // ... 校验密码
+///     session.login("5fa0a0a0a0a0a0a0a0a0a0a0");
+///     session.insert("role", &"admin")?;
+///     Ok(HttpResponse::Ok().finish())
+/// }
+///
+/// async fn cart(session: Session) -> Result<HttpResponse, BusinessError> {
+///     let items: Vec<String> = session.get("cart")?.unwrap_or_default();
+///     Ok(HttpResponse::Ok().json(items))
+/// }
+///
+/// async fn logout(session: Session) -> HttpResponse {
+///     session.purge();
+///     HttpResponse::Ok().finish()
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Session(pub(crate) Rc<RefCell<SessionInner>>);
+
+impl Session {
+    pub(crate) fn new(inner: SessionInner) -> Self {
+        Session(Rc::new(RefCell::new(inner)))
+    }
+
+    fn changed(&self) {
+        let mut inner = self.0.borrow_mut();
+        if inner.status == SessionStatus::Unchanged {
+            inner.status = SessionStatus::Changed;
+        }
+    }
+
+    /// session id 新会话在请求结束前为 `None`
+    pub fn id(&self) -> Option<String> {
+        self.0.borrow().id.clone()
+    }
+
+    /// 登录的用户 id
+    pub fn user_id(&self) -> Option<String> {
+        self.0.borrow().record.user_id.clone()
+    }
+
+    /// 读取
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, BusinessError> {
+        match self.0.borrow().record.data.get(key) {
+            Some(value) => serde_json::from_value(value.clone())
+                .map(Some)
+                .map_err(|e| BusinessError::InternalError { source: anyhow!(e) }),
+            None => Ok(None),
+        }
+    }
+
+    /// 写入
+    pub fn insert<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<(), BusinessError> {
+        let value = serde_json::to_value(value)
+            .map_err(|e| BusinessError::InternalError { source: anyhow!(e) })?;
+        self.0
+            .borrow_mut()
+            .record
+            .data
+            .insert(key.to_owned(), value);
+        self.changed();
+        Ok(())
+    }
+
+    /// 删除
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let value = self.0.borrow_mut().record.data.remove(key);
+        if value.is_some() {
+            self.changed();
+        }
+        value
+    }
+
+    /// 清空数据 (保留登录状态)
+    pub fn clear(&self) {
+        self.0.borrow_mut().record.data.clear();
+        self.changed();
+    }
+
+    /// 登录 绑定用户并更换 session id
+    pub fn login(&self, user_id: &str) {
+        self.0.borrow_mut().record.user_id = Some(user_id.to_owned());
+        self.renew();
+    }
+
+    /// 更换 session id 保留数据
+    pub fn renew(&self) {
+        let mut inner = self.0.borrow_mut();
+        if inner.status != SessionStatus::Purged {
+            inner.status = SessionStatus::Renewed;
+        }
+    }
+
+    /// 注销 删除会话并清除 cookie
+    pub fn purge(&self) {
+        let mut inner = self.0.borrow_mut();
+        inner.record.user_id = None;
+        inner.record.data.clear();
+        inner.status = SessionStatus::Purged;
+    }
+}
+
+fn request_session(req: &HttpRequest) -> Result<Session, BusinessError> {
+    req.extensions()
+        .get::<Session>()
+        .cloned()
+        .ok_or_else(|| BusinessError::InternalError {
+            source: anyhow!("未启用 session 中间件"),
+        })
+}
+
+impl FromRequest for Session {
+    type Error = BusinessError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(request_session(req))
+    }
+}
+
+/// 已登录会话的类型化数据, 会话数据整体反序列化为 `T`
+///
+/// 未登录或数据缺少字段时返回 401
+///
+/// # Examples
+/// ```rust,no_run
+/// use actix_web::HttpResponse;
+/// use yn_util::session::SessionData;
+///
+/// #[derive(serde::Deserialize)]
+/// struct Profile {
+///     role: String,
+/// }
+///
+/// async fn admin(profile: SessionData<Profile>) -> HttpResponse {
+///     HttpResponse::Ok().body(profile.role.clone())
+/// }
+/// ```
+pub struct SessionData<T>(pub T);
+
+impl<T> SessionData<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for SessionData<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for SessionData<T> {
+    type Error = BusinessError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session = match request_session(req) {
+            Ok(session) => session,
+            Err(e) => return err(e),
+        };
+        let inner = session.0.borrow();
+        if inner.record.user_id.is_none() {
+            return err(BusinessError::Unauthorized);
+        }
+        let data = inner.record.data.clone().into_iter().collect();
+        match serde_json::from_value(Value::Object(data)) {
+            Ok(data) => ok(SessionData(data)),
+            Err(_) => err(BusinessError::Unauthorized),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::*;
 use actix_web::{error, HttpResponse};
+use crate::normalize::Normalizer;
 use bson::{doc, oid::ObjectId, Document};
 use futures::StreamExt;
 use md5;
@@ -134,6 +135,12 @@ impl Resp<()> {
 #[async_trait::async_trait]
 pub trait CursorAsVec {
     async fn as_vec(&mut self, is_handle_id: bool) -> Result<Vec<Document>, BusinessError>;
+
+    /// 读取全部文档并规范化 (递归处理 ObjectId / DateTime 等)
+    async fn as_normalized_vec(
+        &mut self,
+        normalizer: &Normalizer,
+    ) -> Result<Vec<Document>, BusinessError>;
 }
 
 #[async_trait::async_trait]
@@ -161,6 +168,17 @@ impl CursorAsVec for Cursor {
         }
         Ok(list)
     }
+
+    async fn as_normalized_vec(
+        &mut self,
+        normalizer: &Normalizer,
+    ) -> Result<Vec<Document>, BusinessError> {
+        let mut list = vec![];
+        while let Some(result) = self.next().await {
+            list.push(normalizer.normalize(result?));
+        }
+        Ok(list)
+    }
 }
 
 /// 安全密鑰
@@ -207,36 +225,21 @@ pub fn struct_to_document<'a, T: Sized + Serialize + Deserialize<'a>>(t: &T) ->
 }
 
 /// 处理文档 objectid
+///
+/// 嵌套文档与数组中的 ObjectId / DateTime 会一并转换, 见 [`Normalizer`](crate::normalize::Normalizer)
 #[inline]
 pub fn document_handle_id(doc: Document, ids: Option<Vec<&str>>) -> Option<Document> {
-    let mut data = doc! {};
-    let keys = doc.keys();
     let handle_id = vec!["_id", "create_by", "update_by"];
-    // if !ids.is_none() {
-    let handle_id = [handle_id, ids.unwrap_or_else(|| vec![])].concat();
-    // };
-    for k in keys {
-        if handle_id.contains(&k.as_str()) {
-            let oid = match doc.get_object_id(k) {
-                Ok(id) => id.to_hex(),
-                Err(_) => doc
-                    .get(k)
-                    .unwrap_or(&bson::Bson::Null)
-                    .as_str()
-                    .unwrap_or("")
-                    .to_string(),
-            };
-            if !oid.is_empty() {
-                data.insert(k, oid);
-            } else {
-                data.insert(k, &bson::Bson::Null);
-            }
-        } else {
-            data.insert(k, doc.get(k).unwrap());
+    let handle_id = [handle_id, ids.unwrap_or_default()].concat();
+    let mut data = Normalizer::default().normalize(doc);
+    for k in handle_id {
+        let oid = match data.get(k) {
+            Some(v) => v.as_str().unwrap_or("").to_string(),
+            None => continue,
+        };
+        if oid.is_empty() {
+            data.insert(k, bson::Bson::Null);
         }
-        // if !k.eq("_id") {
-        //     data.insert(k, doc.get(k).unwrap());
-        // }
     }
     Some(data)
 }